@@ -0,0 +1,138 @@
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    bvh::Bvh,
+    geometry::{cross, dot, normalize, sub, V3},
+};
+
+/// Deterministic pseudo-random numbers in [0, 1) (SplitMix64)
+struct SampleRng(u64);
+
+impl SampleRng {
+    fn next(&mut self) -> f32 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// Ambient occlusion of each vertex: the fraction of `samples` cosine-weighted rays over the
+/// hemisphere of its normal that escape the mesh. 1 is fully lit, 0 is fully occluded.
+pub(crate) fn bake_ambient_occlusion(
+    positions: &[V3],
+    normals: &[V3],
+    faces: &[[usize; 3]],
+    samples: u32,
+) -> Vec<f32> {
+    let triangles = faces
+        .iter()
+        .map(|face| face.map(|vi| positions[vi].v))
+        .collect::<Vec<_>>();
+    let bvh = Bvh::new(triangles);
+    let Some((min, max)) = bvh.bounds() else {
+        return vec![1.0; positions.len()];
+    };
+    let diagonal = dot(sub(max, min), sub(max, min)).sqrt();
+    // Rays start slightly above the surface so that they do not hit their own faces
+    let offset = diagonal * 1e-4;
+
+    (0..positions.len())
+        .into_par_iter()
+        .map(|vi| {
+            let n = normalize(normals[vi].v);
+            if n == [0.0, 0.0, 0.0] || samples == 0 {
+                return 1.0;
+            }
+            let origin = [0, 1, 2].map(|k| positions[vi].v[k] + n[k] * offset);
+            // Orthonormal frame around the normal
+            let helper = if n[0].abs() < 0.9 {
+                [1.0, 0.0, 0.0]
+            } else {
+                [0.0, 1.0, 0.0]
+            };
+            let tangent = normalize(cross(helper, n));
+            let bitangent = cross(n, tangent);
+
+            // Seeded by the vertex so that the result does not depend on the threads
+            let mut rng = SampleRng(vi as u64);
+            let escaped = (0..samples)
+                .filter(|_| {
+                    let (u1, u2) = (rng.next(), rng.next());
+                    let r = u1.sqrt();
+                    let phi = 2.0 * std::f32::consts::PI * u2;
+                    let (x, y, z) = (r * phi.cos(), r * phi.sin(), (1.0 - u1).sqrt());
+                    let direction = [0, 1, 2].map(|k| tangent[k] * x + bitangent[k] * y + n[k] * z);
+                    !bvh.any_hit(origin, direction, diagonal)
+                })
+                .count();
+            escaped as f32 / samples as f32
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two triangles covering the quad `a`, `b`, `c`, `d`
+    fn quad(first: usize) -> [[usize; 3]; 2] {
+        [[first, first + 1, first + 2], [first, first + 2, first + 3]]
+    }
+
+    #[test]
+    fn flat_plane_is_uniform() {
+        let mut positions = Vec::new();
+        let mut faces = Vec::new();
+        for (x, y) in [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)] {
+            faces.extend(quad(positions.len()));
+            for [dx, dy] in [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]] {
+                positions.push(V3 {
+                    v: [x + dx, y + dy, 0.0],
+                });
+            }
+        }
+        let normals = vec![V3 { v: [0.0, 0.0, 1.0] }; positions.len()];
+        let occlusion = bake_ambient_occlusion(&positions, &normals, &faces, 64);
+        assert_eq!(occlusion, vec![1.0; positions.len()]);
+    }
+
+    #[test]
+    fn crevice_is_darker() {
+        // Floor from x = 0 to 4, and a wall at x = 0
+        let mut positions = [
+            [0.0, -2.0, 0.0],
+            [4.0, -2.0, 0.0],
+            [4.0, 2.0, 0.0],
+            [0.0, 2.0, 0.0],
+            [0.0, -2.0, 0.0],
+            [0.0, 2.0, 0.0],
+            [0.0, 2.0, 4.0],
+            [0.0, -2.0, 4.0],
+        ]
+        .map(|v| V3 { v })
+        .to_vec();
+        let faces = [quad(0), quad(4)].concat();
+        // Points of the floor: in the corner, and away from the wall
+        positions.push(V3 {
+            v: [0.05, 0.0, 0.0],
+        });
+        positions.push(V3 { v: [4.0, 0.0, 0.0] });
+        let normals = vec![V3 { v: [0.0, 0.0, 1.0] }; positions.len()];
+        let occlusion = bake_ambient_occlusion(&positions, &normals, &faces, 256);
+        let (corner, open) = (occlusion[8], occlusion[9]);
+        // Half of the hemisphere of the corner faces the wall
+        assert!((corner - 0.5).abs() < 0.1, "{}", corner);
+        assert!(open > corner + 0.2, "{} {}", open, corner);
+        assert!(occlusion.iter().all(|it| (0.0..=1.0).contains(it)));
+        // The same with one thread
+        let serial = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap()
+            .install(|| bake_ambient_occlusion(&positions, &normals, &faces, 256));
+        assert_eq!(serial, occlusion);
+    }
+}
@@ -0,0 +1,235 @@
+use crate::geometry::{cross, dot, sub};
+
+/// Triangles per leaf of the BVH
+const BVH_LEAF_SIZE: usize = 4;
+
+/// Node of a bounding volume hierarchy. The left child of an inner node follows it.
+#[derive(Debug, Clone, Copy)]
+struct BvhNode {
+    min: [f32; 3],
+    max: [f32; 3],
+    /// First triangle of a leaf, or index of the right child of an inner node
+    start: usize,
+    /// Number of triangles of a leaf, 0 for an inner node
+    count: usize,
+}
+
+/// Bounding volume hierarchy over the triangles of a mesh, for ray queries
+pub(crate) struct Bvh {
+    nodes: Vec<BvhNode>,
+    triangles: Vec<[[f32; 3]; 3]>,
+}
+
+impl Bvh {
+    pub(crate) fn new(mut triangles: Vec<[[f32; 3]; 3]>) -> Self {
+        let mut nodes = Vec::with_capacity(2 * triangles.len() / BVH_LEAF_SIZE + 1);
+        if !triangles.is_empty() {
+            let len = triangles.len();
+            build_node(&mut nodes, &mut triangles, 0, len);
+        }
+        Self { nodes, triangles }
+    }
+
+    /// Bounding box (min, max) of the triangles, `None` without triangles
+    pub(crate) fn bounds(&self) -> Option<([f32; 3], [f32; 3])> {
+        self.nodes.first().map(|root| (root.min, root.max))
+    }
+
+    /// Whether the ray hits a triangle at a distance in `(0, max_distance)`
+    #[cfg(feature = "ao")]
+    pub(crate) fn any_hit(&self, origin: [f32; 3], direction: [f32; 3], max_distance: f32) -> bool {
+        let mut hit = false;
+        self.visit_leaves(origin, direction, max_distance, |triangles| {
+            hit = triangles
+                .iter()
+                .any(|it| ray_hits_triangle(origin, direction, it, max_distance));
+            !hit
+        });
+        hit
+    }
+
+    /// Number of triangles crossed by the ray, or `None` when the ray grazes an edge, a vertex
+    /// or the plane of a triangle: its crossings are then ambiguous.
+    pub(crate) fn crossings(&self, origin: [f32; 3], direction: [f32; 3]) -> Option<usize> {
+        let mut count = 0;
+        let mut grazing = false;
+        self.visit_leaves(origin, direction, f32::INFINITY, |triangles| {
+            for triangle in triangles {
+                match ray_crosses_triangle(origin, direction, triangle) {
+                    Crossing::Miss => {}
+                    Crossing::Through => count += 1,
+                    Crossing::Grazing => grazing = true,
+                }
+            }
+            !grazing
+        });
+        (!grazing).then_some(count)
+    }
+
+    /// Call `visit` with the triangles of the leaves whose box the ray enters before
+    /// `max_distance`, until it returns false
+    fn visit_leaves(
+        &self,
+        origin: [f32; 3],
+        direction: [f32; 3],
+        max_distance: f32,
+        mut visit: impl FnMut(&[[[f32; 3]; 3]]) -> bool,
+    ) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let inverse = direction.map(|it| 1.0 / it);
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !ray_hits_box(origin, inverse, node.min, node.max, max_distance) {
+                continue;
+            }
+            if node.count > 0 {
+                if !visit(&self.triangles[node.start..node.start + node.count]) {
+                    return;
+                }
+            } else {
+                stack.push(node.start);
+                stack.push(index + 1);
+            }
+        }
+    }
+}
+
+/// Push the node of `triangles[start..end]` and its children, sorting the triangles in place
+fn build_node(nodes: &mut Vec<BvhNode>, triangles: &mut [[[f32; 3]; 3]], start: usize, end: usize) {
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for p in triangles[start..end].iter().flatten() {
+        for k in 0..3 {
+            min[k] = min[k].min(p[k]);
+            max[k] = max[k].max(p[k]);
+        }
+    }
+    let index = nodes.len();
+    nodes.push(BvhNode {
+        min,
+        max,
+        start,
+        count: end - start,
+    });
+    if end - start <= BVH_LEAF_SIZE {
+        return;
+    }
+
+    // Median split along the longest axis
+    let axis = (0..3)
+        .max_by(|&a, &b| (max[a] - min[a]).total_cmp(&(max[b] - min[b])))
+        .unwrap_or(0);
+    let centroid = |it: &[[f32; 3]; 3]| it[0][axis] + it[1][axis] + it[2][axis];
+    let middle = (start + end) / 2;
+    triangles[start..end]
+        .select_nth_unstable_by(middle - start, |a, b| centroid(a).total_cmp(&centroid(b)));
+    build_node(nodes, triangles, start, middle);
+    let right = nodes.len();
+    build_node(nodes, triangles, middle, end);
+    nodes[index].start = right;
+    nodes[index].count = 0;
+}
+
+/// Slab test of a ray against an axis-aligned box
+fn ray_hits_box(
+    origin: [f32; 3],
+    inverse: [f32; 3],
+    min: [f32; 3],
+    max: [f32; 3],
+    max_distance: f32,
+) -> bool {
+    let mut near = 0.0f32;
+    let mut far = max_distance;
+    for k in 0..3 {
+        let t0 = (min[k] - origin[k]) * inverse[k];
+        let t1 = (max[k] - origin[k]) * inverse[k];
+        // NaN (0 * inf) comparisons leave the bounds unchanged
+        near = near.max(t0.min(t1));
+        far = far.min(t0.max(t1));
+    }
+    near <= far
+}
+
+/// Möller-Trumbore intersection, with the triangles seen from both sides
+#[cfg(feature = "ao")]
+fn ray_hits_triangle(
+    origin: [f32; 3],
+    direction: [f32; 3],
+    [a, b, c]: &[[f32; 3]; 3],
+    max_distance: f32,
+) -> bool {
+    let ab = sub(*b, *a);
+    let ac = sub(*c, *a);
+    let p = cross(direction, ac);
+    let det = dot(ab, p);
+    if det.abs() < f32::EPSILON * dot(ab, ab).max(dot(ac, ac)) {
+        return false;
+    }
+    let t_vec = sub(origin, *a);
+    let u = dot(t_vec, p) / det;
+    if !(0.0..=1.0).contains(&u) {
+        return false;
+    }
+    let q = cross(t_vec, ab);
+    let v = dot(direction, q) / det;
+    if v < 0.0 || u + v > 1.0 {
+        return false;
+    }
+    let t = dot(ac, q) / det;
+    t > 0.0 && t < max_distance
+}
+
+/// Relative distance to the edges, and to the plane of a triangle, under which a ray grazes it
+const GRAZING_EPSILON: f32 = 1e-5;
+
+/// How a ray goes through a triangle, for the parity counts
+enum Crossing {
+    Miss,
+    Through,
+    /// Close to an edge or a vertex, or along the plane of the triangle
+    Grazing,
+}
+
+/// Möller-Trumbore intersection, at a positive distance, telling apart the ambiguous crossings
+fn ray_crosses_triangle(
+    origin: [f32; 3],
+    direction: [f32; 3],
+    [a, b, c]: &[[f32; 3]; 3],
+) -> Crossing {
+    let ab = sub(*b, *a);
+    let ac = sub(*c, *a);
+    let normal = cross(ab, ac);
+    let area2 = dot(normal, normal);
+    if area2 == 0.0 {
+        // A degenerate triangle has no inside to cross
+        return Crossing::Miss;
+    }
+    let t_vec = sub(origin, *a);
+    let p = cross(direction, ac);
+    let det = dot(ab, p);
+    if det.abs() < GRAZING_EPSILON * area2.sqrt() * dot(direction, direction).sqrt() {
+        // Parallel to the triangle: ambiguous only in its plane
+        let distance = dot(t_vec, normal).abs() / area2.sqrt();
+        let size = dot(ab, ab).max(dot(ac, ac)).sqrt();
+        return if distance < GRAZING_EPSILON * size {
+            Crossing::Grazing
+        } else {
+            Crossing::Miss
+        };
+    }
+    let u = dot(t_vec, p) / det;
+    let q = cross(t_vec, ab);
+    let v = dot(direction, q) / det;
+    let t = dot(ac, q) / det;
+    let w = 1.0 - u - v;
+    if u < -GRAZING_EPSILON || v < -GRAZING_EPSILON || w < -GRAZING_EPSILON || t < 0.0 {
+        return Crossing::Miss;
+    }
+    if u < GRAZING_EPSILON || v < GRAZING_EPSILON || w < GRAZING_EPSILON || t == 0.0 {
+        return Crossing::Grazing;
+    }
+    Crossing::Through
+}
@@ -0,0 +1,3285 @@
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    io::{Cursor, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use gltf::json::{self, validation::Checked::Valid};
+use rayon::{
+    iter::{IntoParallelRefIterator, ParallelIterator},
+    slice::ParallelSlice,
+};
+use stl_io::{IndexedMesh, IndexedTriangle};
+
+use crate::{
+    geometry::{
+        angle_between, bounding_coords, compute_tangents, connected_components, cross, dot,
+        fix_winding, mat4_mul, mat4_transform_normal, mat4_transform_point, normalize,
+        optimize_vertex_cache, reindex_vertices, remap_vertices, rotate_by_quaternion, sub,
+        vertex_fetch_remap, weld_vertices, weld_vertices_within_components, y_up_to_z_up,
+        z_up_to_y_up, V3,
+    },
+    ply::read_ply_file,
+    simplify::simplify_mesh_faces,
+    AoTarget, CenterMode, ConvertError, ConvertOptions, GltfBuilder, IndexType, InstanceTransform,
+    MaterialOptions, NormalMode, Quantization, ShadingMode, StlNormalMode, UpAxis, UvMode,
+    VertexLayout,
+};
+
+/// Faces whose edges make an angle with a squared sine below this are degenerate
+const DEGENERATE_SIN_SQ: f32 = 1e-12;
+
+/// Bytes read at the start of an STL file to find the `solid <name>` line
+const SOLID_LINE_MAX: u64 = 256;
+
+/// Linear base colors given in turn to the groups of the input with `group_materials`
+const GROUP_PALETTE: [[f32; 4]; 8] = [
+    [0.80, 0.10, 0.10, 1.0],
+    [0.10, 0.50, 0.80, 1.0],
+    [0.15, 0.65, 0.15, 1.0],
+    [0.90, 0.60, 0.05, 1.0],
+    [0.55, 0.15, 0.70, 1.0],
+    [0.05, 0.65, 0.60, 1.0],
+    [0.85, 0.30, 0.55, 1.0],
+    [0.45, 0.45, 0.45, 1.0],
+];
+
+/// Meshes with fewer faces accumulate their vertex normals on a single thread
+const PARALLEL_NORMALS_MIN_FACES: usize = 1 << 18;
+
+/// Read an ASCII or binary STL file, optionally gzip-compressed
+pub fn read_stl_file(path: impl AsRef<Path>) -> Result<IndexedMesh, ConvertError> {
+    if let Some(data) = read_gzip_file(path.as_ref())? {
+        return parse_stl(&mut Cursor::new(data));
+    }
+    let mut file = OpenOptions::new().read(true).open(path)?;
+    parse_stl(&mut file)
+}
+
+fn parse_stl<R: Read + Seek>(reader: &mut R) -> Result<IndexedMesh, ConvertError> {
+    let start = reader.stream_position()?;
+    let err = match stl_io::read_stl(reader) {
+        Ok(stl) => return Ok(stl),
+        Err(err) => err,
+    };
+    // Explain the failure from the first bytes of the file
+    let len = reader.seek(SeekFrom::End(0))? - start;
+    reader.seek(SeekFrom::Start(start))?;
+    let mut head = Vec::new();
+    reader.take(84).read_to_end(&mut head)?;
+    Err(ConvertError::Parse(describe_stl_error(&head, len, &err)))
+}
+
+/// Error message of an STL file of `len` bytes starting with `head`, with the format it looks
+/// like and the likely cause
+fn describe_stl_error(head: &[u8], len: u64, err: &std::io::Error) -> String {
+    let facet_count =
+        (head.len() >= 84).then(|| u32::from_le_bytes([head[80], head[81], head[82], head[83]]));
+    let binary_len = facet_count.map(|it| 84 + 50 * it as u64);
+    // Binary headers may also start with "solid": the length tells them apart
+    let ascii = head.starts_with(b"solid") && binary_len != Some(len);
+    match (ascii, facet_count, binary_len) {
+        (true, _, _) => format!(
+            "looks like ASCII STL but failed to parse the faces: {}",
+            err
+        ),
+        (false, Some(facet_count), Some(binary_len)) if binary_len != len => format!(
+            "looks like binary STL, but its header announces {} triangles ({} bytes) and the \
+             file has {} bytes: truncated or not an STL file",
+            facet_count, binary_len, len
+        ),
+        (false, Some(_), _) => format!("looks like binary STL but failed to parse: {}", err),
+        (false, None, _) => format!(
+            "{} bytes, shorter than the 84-byte header of a binary STL file: {}",
+            len, err
+        ),
+    }
+}
+
+/// Decompress the whole file if it starts with the gzip magic bytes.
+/// Returns `None` if the file is not compressed.
+fn read_gzip_file(path: &Path) -> Result<Option<Vec<u8>>, ConvertError> {
+    let mut file = OpenOptions::new().read(true).open(path)?;
+    let mut magic = [0u8; 2];
+    if file.read_exact(&mut magic).is_err() || !is_gzip(&magic) {
+        return Ok(None);
+    }
+    file.rewind()?;
+    let mut data = Vec::new();
+    flate2::read::GzDecoder::new(file).read_to_end(&mut data)?;
+    Ok(Some(data))
+}
+
+fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&[0x1f, 0x8b])
+}
+
+/// Read an ASCII or binary STL file through a memory map of the file.
+/// This avoids buffering the whole file when reading very large meshes.
+pub fn read_stl_file_mmap(path: impl AsRef<Path>) -> Result<IndexedMesh, ConvertError> {
+    let file = OpenOptions::new().read(true).open(path)?;
+    // Safety: the file is only read, and is expected not to be modified during the conversion
+    let mmap = unsafe { memmap2::Mmap::map(&file)? };
+    if is_gzip(&mmap) {
+        let mut data = Vec::new();
+        flate2::read::GzDecoder::new(&mmap[..]).read_to_end(&mut data)?;
+        return parse_stl(&mut Cursor::new(data));
+    }
+    parse_stl(&mut Cursor::new(&mmap[..]))
+}
+
+/// Read an ASCII or binary STL file already in memory, optionally gzip-compressed
+pub fn read_stl_bytes(data: &[u8]) -> Result<IndexedMesh, ConvertError> {
+    if is_gzip(data) {
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(data).read_to_end(&mut decompressed)?;
+        return parse_stl(&mut Cursor::new(decompressed));
+    }
+    parse_stl(&mut Cursor::new(data))
+}
+
+/// Read the name of an ASCII STL file from its `solid <name>` line, optionally gzip-compressed.
+/// Returns `None` for binary files and unnamed solids.
+pub fn read_stl_solid_name(path: impl AsRef<Path>) -> Result<Option<String>, ConvertError> {
+    let mut file = OpenOptions::new().read(true).open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut head = Vec::new();
+    (&mut file).take(SOLID_LINE_MAX).read_to_end(&mut head)?;
+    if is_gzip(&head) {
+        file.rewind()?;
+        head.clear();
+        flate2::read::GzDecoder::new(file)
+            .take(SOLID_LINE_MAX)
+            .read_to_end(&mut head)?;
+    } else if head.len() >= 84 {
+        // Binary STL headers may also start with "solid"
+        let facet_count = u32::from_le_bytes([head[80], head[81], head[82], head[83]]) as u64;
+        if file_len == 84 + 50 * facet_count {
+            return Ok(None);
+        }
+    }
+    Ok(parse_solid_name(&head))
+}
+
+/// Number of triangles of an STL file, optionally gzip-compressed, without reading the mesh:
+/// from the header of a binary file, or by counting the `endfacet` keywords of an ASCII file.
+pub fn read_stl_triangle_count(path: impl AsRef<Path>) -> Result<u64, ConvertError> {
+    let mut file = OpenOptions::new().read(true).open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut head = Vec::new();
+    (&mut file).take(84).read_to_end(&mut head)?;
+    if is_gzip(&head) {
+        file.rewind()?;
+        let mut decoder = flate2::read::GzDecoder::new(file);
+        head.clear();
+        (&mut decoder).take(84).read_to_end(&mut head)?;
+        let (ascii_count, len) = count_ascii_facets(Cursor::new(&head).chain(decoder))?;
+        return Ok(stl_triangle_count(&head, len, ascii_count));
+    }
+    let (ascii_count, len) = if head.starts_with(b"solid") {
+        file.rewind()?;
+        count_ascii_facets(file)?
+    } else {
+        (0, file_len)
+    };
+    Ok(stl_triangle_count(&head, len, ascii_count))
+}
+
+/// Triangle count of an STL file of `len` bytes starting with `head`.
+/// Files that do not look like ASCII are read as binary, trusting the count of the header.
+fn stl_triangle_count(head: &[u8], len: u64, ascii_count: u64) -> u64 {
+    if head.len() < 84 {
+        return ascii_count;
+    }
+    let facet_count = u32::from_le_bytes([head[80], head[81], head[82], head[83]]) as u64;
+    if len == 84 + 50 * facet_count || !head.starts_with(b"solid") {
+        facet_count
+    } else {
+        ascii_count
+    }
+}
+
+/// Count the `endfacet` keywords of a stream, in chunks. Returns the count and the stream length.
+fn count_ascii_facets(mut reader: impl Read) -> Result<(u64, u64), ConvertError> {
+    const KEYWORD: &[u8] = b"endfacet";
+    let mut buffer = vec![0u8; 1 << 16];
+    // Tail of the previous chunk, for the keywords split between two chunks
+    let mut kept = 0;
+    let mut count = 0;
+    let mut len = 0;
+    loop {
+        let read = match reader.read(&mut buffer[kept..]) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        len += read as u64;
+        let data = &buffer[..kept + read];
+        count += data
+            .windows(KEYWORD.len())
+            .filter(|it| it.eq_ignore_ascii_case(KEYWORD))
+            .count() as u64;
+        let tail = data.len().min(KEYWORD.len() - 1);
+        buffer.copy_within(kept + read - tail..kept + read, 0);
+        kept = tail;
+    }
+    Ok((count, len))
+}
+
+fn parse_solid_name(head: &[u8]) -> Option<String> {
+    let end = head.iter().position(|&it| it == b'\n')?;
+    let line = std::str::from_utf8(&head[..end]).ok()?;
+    let name = line.strip_prefix("solid ")?.trim();
+    (!name.is_empty() && !name.contains(char::is_control)).then(|| name.to_string())
+}
+
+/// Read the per-facet colors of a binary STL file using the Materialise Magics convention:
+/// the header contains `COLOR=` followed by the default RGBA color, and the attribute of each
+/// facet contains its RGB color (5 bits per channel) unless bit 15 is set.
+/// Returns `None` if the file has no color information.
+pub fn read_stl_colors(path: impl AsRef<Path>) -> Result<Option<Vec<[f32; 4]>>, ConvertError> {
+    if let Some(data) = read_gzip_file(path.as_ref())? {
+        let len = data.len() as u64;
+        return read_stl_colors_from(&mut Cursor::new(data), len);
+    }
+    let mut file = OpenOptions::new().read(true).open(path)?;
+    let file_len = file.metadata()?.len();
+    read_stl_colors_from(&mut file, file_len)
+}
+
+/// Same as `read_stl_colors`, for an uncompressed STL file already in memory
+pub fn read_stl_colors_bytes(data: &[u8]) -> Result<Option<Vec<[f32; 4]>>, ConvertError> {
+    read_stl_colors_from(&mut Cursor::new(data), data.len() as u64)
+}
+
+fn read_stl_colors_from<R: Read + Seek>(
+    file: &mut R,
+    file_len: u64,
+) -> Result<Option<Vec<[f32; 4]>>, ConvertError> {
+    let mut header = [0u8; 84];
+    if file_len < 84 || file.read_exact(&mut header).is_err() {
+        return Ok(None);
+    }
+    let facet_count = u32::from_le_bytes([header[80], header[81], header[82], header[83]]) as u64;
+    // Not a binary STL
+    if file_len != 84 + 50 * facet_count {
+        return Ok(None);
+    }
+    let Some(pos) = header[..80].windows(6).position(|it| it == b"COLOR=") else {
+        return Ok(None);
+    };
+    let default_color = if pos + 10 <= 80 {
+        [
+            header[pos + 6],
+            header[pos + 7],
+            header[pos + 8],
+            header[pos + 9],
+        ]
+        .map(|it| it as f32 / 255.0)
+    } else {
+        [1.0, 1.0, 1.0, 1.0]
+    };
+
+    let mut colors = Vec::with_capacity(facet_count as usize);
+    let mut attribute = [0u8; 2];
+    for _ in 0..facet_count {
+        // Skip the normal and vertices of the facet (12 f32)
+        file.seek(SeekFrom::Current(48))?;
+        file.read_exact(&mut attribute)?;
+        let attribute = u16::from_le_bytes(attribute);
+        let color = if attribute & 0x8000 == 0 {
+            let channel = |shift: u16| ((attribute >> shift) & 0x1f) as f32 / 31.0;
+            [channel(0), channel(5), channel(10), 1.0]
+        } else {
+            default_color
+        };
+        colors.push(color);
+    }
+    Ok(Some(colors))
+}
+
+/// Convert an STL mesh to glTF. `name` is used as the name of the glTF mesh and node.
+pub fn stl_to_gltf(
+    stl: IndexedMesh,
+    name: &str,
+    options: ConvertOptions,
+) -> Result<GltfBuilder, ConvertError> {
+    stl_to_gltf_with_colors(stl, None, name, options)
+}
+
+/// Convert an STL mesh to glTF, with one color per face (see `read_stl_colors`) emitted as `COLOR_0`.
+/// In indexed mode, the color of a vertex is the average color of its faces.
+pub fn stl_to_gltf_with_colors(
+    stl: IndexedMesh,
+    face_colors: Option<Vec<[f32; 4]>>,
+    name: &str,
+    options: ConvertOptions,
+) -> Result<GltfBuilder, ConvertError> {
+    if let Some(face_colors) = &face_colors {
+        if face_colors.len() != stl.faces.len() {
+            return Err(ConvertError::InvalidData(format!(
+                "Expected {} face colors, got {}",
+                stl.faces.len(),
+                face_colors.len()
+            )));
+        }
+    }
+    let options = centered_options(
+        &options,
+        stl.vertices.iter().map(|it| it.0),
+        options.up_axis,
+    );
+    let quantization = PositionQuantization::new(
+        stl.vertices.iter().map(|it| it.0),
+        &options,
+        options.up_axis,
+    );
+    let mut gltf = GltfBuilder::new();
+    let texture = push_options_texture(&mut gltf, &options)?;
+    let material = push_options_material(&mut gltf, &options, texture)?;
+    let variants = push_variant_materials(&mut gltf, &options, texture)?;
+    let parts = if options.split_components {
+        split_components(stl, face_colors)
+    } else {
+        vec![(stl, face_colors)]
+    };
+    let split = parts.len() > 1;
+    let mut nodes = Vec::new();
+    for (stl, face_colors) in parts {
+        // The parts without any valid triangle are left out
+        let mut primitive = match push_primitive(
+            &mut gltf,
+            stl,
+            None,
+            None,
+            face_colors,
+            None,
+            &options,
+            options.up_axis,
+            quantization,
+        ) {
+            Err(ConvertError::EmptyMesh) => continue,
+            result => result?,
+        };
+        primitive.material = material;
+
+        let part_name = if split {
+            format!("{}_{}", name, nodes.len())
+        } else {
+            name.to_string()
+        };
+        let mesh = gltf.push_mesh(Some(part_name.clone()), vec![primitive], None);
+        if !variants.is_empty() {
+            gltf.set_mesh_variants(mesh, &variants)?;
+        }
+        nodes.push(push_mesh_node(
+            &mut gltf,
+            mesh,
+            &part_name,
+            quantization,
+            &options,
+            options.up_axis,
+        )?);
+    }
+    if nodes.is_empty() {
+        if options.allow_empty {
+            return empty_gltf(name);
+        }
+        return Err(ConvertError::EmptyMesh);
+    }
+
+    let scene = gltf.push_named_scene(name, nodes);
+    gltf.set_default_scene(Some(scene));
+
+    Ok(gltf)
+}
+
+/// Split the mesh into its connected components (see `connected_components`), each with its
+/// own vertices and face colors. The vertices without faces are dropped.
+fn split_components(
+    stl: IndexedMesh,
+    face_colors: Option<Vec<[f32; 4]>>,
+) -> Vec<(IndexedMesh, Option<Vec<[f32; 4]>>)> {
+    let labels = connected_components(&stl);
+    let count = labels.iter().max().map_or(0, |it| it + 1);
+    let mut parts = (0..count)
+        .map(|_| {
+            let mesh = IndexedMesh {
+                vertices: Vec::new(),
+                faces: Vec::new(),
+            };
+            (mesh, face_colors.as_ref().map(|_| Vec::new()))
+        })
+        .collect::<Vec<_>>();
+    // Index of each vertex in its part
+    let local = stl
+        .vertices
+        .iter()
+        .zip(&labels)
+        .map(|(&vertex, &label)| {
+            let vertices = &mut parts[label].0.vertices;
+            vertices.push(vertex);
+            vertices.len() - 1
+        })
+        .collect::<Vec<_>>();
+    for (fi, face) in stl.faces.iter().enumerate() {
+        let (mesh, colors) = &mut parts[labels[face.vertices[0]]];
+        mesh.faces.push(IndexedTriangle {
+            normal: face.normal,
+            vertices: face.vertices.map(|vi| local[vi]),
+        });
+        if let (Some(colors), Some(face_colors)) = (colors, &face_colors) {
+            colors.push(face_colors[fi]);
+        }
+    }
+    parts.retain(|(mesh, _)| !mesh.faces.is_empty());
+    parts
+}
+
+/// A glTF whose scene holds a single node named `name`, without mesh.
+/// Used for the inputs without any triangle.
+fn empty_gltf(name: &str) -> Result<GltfBuilder, ConvertError> {
+    let mut gltf = GltfBuilder::new();
+    let node = gltf.push_node_children(None, Vec::new());
+    gltf.set_node_name(node, Some(name.to_string()))?;
+    let scene = gltf.push_named_scene(name, vec![node]);
+    gltf.set_default_scene(Some(scene));
+    Ok(gltf)
+}
+
+/// Load a Wavefront OBJ file and convert it to glTF, with one primitive per object/group.
+/// With `options.group_materials`, each group gets its own material.
+/// The OBJ geometry is expected to be Y-up. The vertex normals of the file are used when present.
+pub fn obj_to_gltf(
+    input_filename: impl AsRef<Path>,
+    name: &str,
+    options: ConvertOptions,
+) -> Result<GltfBuilder, ConvertError> {
+    let load_options = tobj::LoadOptions {
+        single_index: true,
+        triangulate: true,
+        ..Default::default()
+    };
+    let (models, _materials) =
+        tobj::load_obj(input_filename.as_ref(), &load_options).map_err(|err| match err {
+            tobj::LoadError::OpenFileFailed => ConvertError::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                err.to_string(),
+            )),
+            err => ConvertError::Parse(err.to_string()),
+        })?;
+
+    // Center all the objects together to keep their relative placement
+    let options = centered_options(
+        &options,
+        models.iter().flat_map(|model| {
+            model
+                .mesh
+                .positions
+                .chunks_exact(3)
+                .map(|p| [p[0], p[1], p[2]])
+        }),
+        UpAxis::Y,
+    );
+    // All the objects share the node, and so its dequantization
+    let quantization = PositionQuantization::new(
+        models.iter().flat_map(|model| {
+            model
+                .mesh
+                .positions
+                .chunks_exact(3)
+                .map(|p| [p[0], p[1], p[2]])
+        }),
+        &options,
+        UpAxis::Y,
+    );
+    let mut gltf = GltfBuilder::new();
+    let models = models
+        .into_iter()
+        .filter(|model| !model.mesh.indices.is_empty())
+        .collect::<Vec<_>>();
+    let texture = push_options_texture(&mut gltf, &options)?;
+    let materials = if options.group_materials {
+        let names = models.iter().map(|it| it.name.clone()).collect::<Vec<_>>();
+        push_group_materials(&mut gltf, &options, &names, texture)?
+            .into_iter()
+            .map(Some)
+            .collect()
+    } else {
+        vec![push_options_material(&mut gltf, &options, texture)?; models.len()]
+    };
+    let variants = push_variant_materials(&mut gltf, &options, texture)?;
+    let mut primitives = Vec::new();
+    for (model, material) in models.into_iter().zip(materials) {
+        let mesh = model.mesh;
+        let mut stl = IndexedMesh {
+            vertices: mesh
+                .positions
+                .chunks_exact(3)
+                .map(|p| stl_io::Vertex::new([p[0], p[1], p[2]]))
+                .collect(),
+            faces: Vec::new(),
+        };
+        let indices = mesh.indices.iter().map(|&i| i as usize).collect::<Vec<_>>();
+        push_triangles(&mut stl, 0, &indices)?;
+
+        // Only reuse the normals of the file when every vertex has one
+        let normals = (mesh.normals.len() == mesh.positions.len()).then(|| {
+            mesh.normals
+                .chunks_exact(3)
+                .map(|n| V3 {
+                    v: normalize([n[0], n[1], n[2]]),
+                })
+                .collect()
+        });
+        // OBJ texture coordinates have their origin at the bottom left, glTF at the top left
+        let uvs = (mesh.texcoords.len() / 2 == mesh.positions.len() / 3).then(|| {
+            mesh.texcoords
+                .chunks_exact(2)
+                .map(|uv| [uv[0], 1.0 - uv[1]])
+                .collect()
+        });
+        // The groups without any valid triangle are left out
+        let primitive = match push_primitive(
+            &mut gltf,
+            stl,
+            normals,
+            uvs,
+            None,
+            None,
+            &options,
+            UpAxis::Y,
+            quantization,
+        ) {
+            Err(ConvertError::EmptyMesh) => continue,
+            result => result?,
+        };
+        primitives.push((primitive, material));
+    }
+    if primitives.is_empty() {
+        if options.allow_empty {
+            return empty_gltf(name);
+        }
+        return Err(ConvertError::EmptyMesh);
+    }
+
+    let mesh = gltf.push_mesh_with_materials(Some(name.to_string()), primitives, None);
+    if !variants.is_empty() {
+        gltf.set_mesh_variants(mesh, &variants)?;
+    }
+    let node = push_mesh_node(&mut gltf, mesh, name, quantization, &options, UpAxis::Y)?;
+    let scene = gltf.push_named_scene(name, vec![node]);
+    gltf.set_default_scene(Some(scene));
+
+    Ok(gltf)
+}
+
+/// Load an ASCII or binary PLY file and convert it to glTF. The polygons are triangulated.
+/// The vertex normals and colors of the file are used when present.
+/// Like STL, the geometry is converted from `options.up_axis`.
+pub fn ply_to_gltf(
+    input_filename: impl AsRef<Path>,
+    name: &str,
+    options: ConvertOptions,
+) -> Result<GltfBuilder, ConvertError> {
+    let ply = read_ply_file(input_filename)?;
+    let options = centered_options(&options, ply.positions.iter().copied(), options.up_axis);
+    let quantization =
+        PositionQuantization::new(ply.positions.iter().copied(), &options, options.up_axis);
+
+    let mut stl = IndexedMesh {
+        vertices: ply
+            .positions
+            .iter()
+            .map(|&p| stl_io::Vertex::new(p))
+            .collect(),
+        faces: Vec::new(),
+    };
+    push_triangles(&mut stl, 0, &ply.indices)?;
+    let normals = ply.normals.map(|normals| {
+        normals
+            .into_iter()
+            .map(|n| V3 { v: normalize(n) })
+            .collect()
+    });
+
+    let mut gltf = GltfBuilder::new();
+    let texture = push_options_texture(&mut gltf, &options)?;
+    let material = push_options_material(&mut gltf, &options, texture)?;
+    let variants = push_variant_materials(&mut gltf, &options, texture)?;
+    let mut primitive = match push_primitive(
+        &mut gltf,
+        stl,
+        normals,
+        None,
+        None,
+        ply.colors,
+        &options,
+        options.up_axis,
+        quantization,
+    ) {
+        Err(ConvertError::EmptyMesh) if options.allow_empty => return empty_gltf(name),
+        result => result?,
+    };
+    primitive.material = material;
+
+    let mesh = gltf.push_mesh(Some(name.to_string()), vec![primitive], None);
+    if !variants.is_empty() {
+        gltf.set_mesh_variants(mesh, &variants)?;
+    }
+    let node = push_mesh_node(
+        &mut gltf,
+        mesh,
+        name,
+        quantization,
+        &options,
+        options.up_axis,
+    )?;
+    let scene = gltf.push_named_scene(name, vec![node]);
+    gltf.set_default_scene(Some(scene));
+
+    Ok(gltf)
+}
+
+/// Dequantization of the positions of a mesh, done by its node: `p = offset + scale * q`,
+/// with `q` the normalized positions in [-1, 1]
+#[derive(Debug, Clone, Copy)]
+struct PositionQuantization {
+    offset: [f32; 3],
+    scale: f32,
+    bits: Quantization,
+}
+
+impl PositionQuantization {
+    /// Fit the points of the input in [-1, 1] once in the output space.
+    /// `None` when the options do not quantize.
+    fn new(
+        points: impl Iterator<Item = [f32; 3]>,
+        options: &ConvertOptions,
+        up_axis: UpAxis,
+    ) -> Option<Self> {
+        let bits = options.quantize?;
+        let (min, max) = points
+            .map(|p| {
+                let p = options.transform_point(p);
+                match up_axis {
+                    UpAxis::Z => z_up_to_y_up(p),
+                    UpAxis::Y => p,
+                }
+            })
+            .fold(([f32::MAX; 3], [f32::MIN; 3]), |(min, max), p| {
+                (
+                    [0, 1, 2].map(|k| min[k].min(p[k])),
+                    [0, 1, 2].map(|k| max[k].max(p[k])),
+                )
+            });
+        let offset = [0, 1, 2].map(|k| (min[k] + max[k]) / 2.0);
+        let scale = (0..3).map(|k| (max[k] - min[k]) / 2.0).fold(0.0, f32::max);
+        Some(Self {
+            offset,
+            scale: if scale > 0.0 { scale } else { 1.0 },
+            bits,
+        })
+    }
+}
+
+/// Push the named node of a mesh, with the dequantization of its positions if any, and its
+/// bounding sphere and instances if requested. `up_axis` is the frame of the input.
+fn push_mesh_node(
+    gltf: &mut GltfBuilder,
+    mesh: json::Index<json::Mesh>,
+    name: &str,
+    quantization: Option<PositionQuantization>,
+    options: &ConvertOptions,
+    up_axis: UpAxis,
+) -> Result<json::Index<json::Node>, ConvertError> {
+    let node = match quantization {
+        // The instances carry the dequantization instead
+        Some(_) if options.instances.is_some() => gltf.push_node(mesh),
+        Some(quantization) => gltf.push_node_with_transform(
+            mesh,
+            quantization.offset,
+            [0.0, 0.0, 0.0, 1.0],
+            [quantization.scale; 3],
+        ),
+        None => gltf.push_node(mesh),
+    };
+    gltf.set_node_name(node, Some(name.to_string()))?;
+    if options.bounding_sphere {
+        gltf.set_node_bounding_sphere(node)?;
+    }
+    if let Some(instances) = &options.instances {
+        let instances = instances
+            .iter()
+            .map(|it| output_instance(it, options.scale, up_axis, quantization))
+            .collect::<Vec<_>>();
+        gltf.set_node_instances(node, &instances)?;
+    }
+    Ok(node)
+}
+
+/// Transform of an instance in the glTF frame, with its translation scaled like the geometry.
+/// The dequantization of the positions is applied first: the instance is `T * R * S * Q`.
+fn output_instance(
+    instance: &InstanceTransform,
+    scale: f32,
+    up_axis: UpAxis,
+    quantization: Option<PositionQuantization>,
+) -> InstanceTransform {
+    let translation = instance.translation.map(|it| it * scale);
+    let [qx, qy, qz, qw] = instance.rotation;
+    let [sx, sy, sz] = instance.scale;
+    let (mut translation, rotation, mut scale) = match up_axis {
+        UpAxis::Z => {
+            let [qx, qy, qz] = z_up_to_y_up([qx, qy, qz]);
+            (z_up_to_y_up(translation), [qx, qy, qz, qw], [sx, sz, sy])
+        }
+        UpAxis::Y => (translation, instance.rotation, instance.scale),
+    };
+    if let Some(quantization) = quantization {
+        let offset = rotate_by_quaternion(
+            rotation,
+            [0, 1, 2].map(|k| scale[k] * quantization.offset[k]),
+        );
+        translation = [0, 1, 2].map(|k| translation[k] + offset[k]);
+        scale = scale.map(|it| it * quantization.scale);
+    }
+    InstanceTransform {
+        translation,
+        rotation,
+        scale,
+    }
+}
+
+/// Push VEC3 values in [-1, 1] as normalized integers, padded to 4 components for alignment
+fn push_normalized_vec3(
+    gltf: &mut GltfBuilder,
+    name: &str,
+    values: &[[f32; 3]],
+    bits: Quantization,
+    with_bounds: bool,
+) -> Result<json::Index<json::Accessor>, ConvertError> {
+    let q_max = match bits {
+        Quantization::I8 => i8::MAX as f32,
+        Quantization::I16 => i16::MAX as f32,
+    };
+    let quantized = values
+        .iter()
+        .map(|v| v.map(|it| (it.clamp(-1.0, 1.0) * q_max).round() as i32))
+        .collect::<Vec<_>>();
+    let bounds = with_bounds.then(|| {
+        quantized
+            .iter()
+            .fold(([i32::MAX; 3], [i32::MIN; 3]), |(min, max), q| {
+                (
+                    [0, 1, 2].map(|k| min[k].min(q[k])),
+                    [0, 1, 2].map(|k| max[k].max(q[k])),
+                )
+            })
+    });
+    let name = Some(name.to_string());
+    let (view, component_type) = match bits {
+        Quantization::I8 => (
+            gltf.push_buffer_with_view(
+                name.clone(),
+                quantized
+                    .iter()
+                    .map(|q| [q[0] as i8, q[1] as i8, q[2] as i8, 0])
+                    .collect(),
+                Some(1),
+                None,
+            )?,
+            json::accessor::ComponentType::I8,
+        ),
+        Quantization::I16 => (
+            gltf.push_buffer_with_view(
+                name.clone(),
+                quantized
+                    .iter()
+                    .map(|q| [q[0] as i16, q[1] as i16, q[2] as i16, 0])
+                    .collect(),
+                Some(1),
+                None,
+            )?,
+            json::accessor::ComponentType::I16,
+        ),
+    };
+    Ok(gltf.push_accessor_vec3_normalized(
+        name,
+        view,
+        component_type,
+        values.len(),
+        bounds.map(|it| it.0),
+        bounds.map(|it| it.1),
+    ))
+}
+
+/// Compute the vertex normals by averaging the normals of the incident faces
+fn compute_vertex_normals(stl: &IndexedMesh, mode: NormalMode) -> Vec<V3> {
+    let vertex_count = stl.vertices.len();
+    let (mut normals, normals_count) = if stl.faces.len() < PARALLEL_NORMALS_MIN_FACES {
+        let mut normals = vec![V3 { v: [0.0, 0.0, 0.0] }; vertex_count];
+        let mut normals_count = vec![0; vertex_count];
+        accumulate_face_normals(stl, &stl.faces, mode, &mut normals, &mut normals_count);
+        (normals, normals_count)
+    } else {
+        // One partial accumulator per chunk of faces, summed at the end
+        let chunk_len = stl.faces.len().div_ceil(rayon::current_num_threads());
+        stl.faces
+            .par_chunks(chunk_len)
+            .map(|faces| {
+                let mut normals = vec![V3 { v: [0.0, 0.0, 0.0] }; vertex_count];
+                let mut normals_count = vec![0; vertex_count];
+                accumulate_face_normals(stl, faces, mode, &mut normals, &mut normals_count);
+                (normals, normals_count)
+            })
+            .reduce_with(|(mut normals, mut normals_count), (other, other_count)| {
+                for (n, o) in normals.iter_mut().zip(other) {
+                    for (value, other_value) in n.v.iter_mut().zip(o.v) {
+                        *value += other_value;
+                    }
+                }
+                for (count, other_count) in normals_count.iter_mut().zip(other_count) {
+                    *count += other_count;
+                }
+                (normals, normals_count)
+            })
+            .unwrap_or_default()
+    };
+
+    // normalize
+    // Vertices not used by any face (or whose face normals cancel out) get a default normal
+    let mut isolated_count = 0;
+    for i in 0..normals.len() {
+        let n = normalize(normals[i].v);
+        if normals_count[i] == 0 || n == [0.0, 0.0, 0.0] {
+            if normals_count[i] == 0 {
+                isolated_count += 1;
+            }
+            normals[i].v = [0.0, 0.0, 1.0];
+        } else {
+            normals[i].v = n;
+        }
+    }
+    if isolated_count > 0 {
+        log::warn!("Warning: {isolated_count} isolated vertices");
+    }
+
+    normals
+}
+
+/// Copy of the mesh where each face has its own three vertices
+fn unweld_faces(stl: &IndexedMesh) -> IndexedMesh {
+    IndexedMesh {
+        vertices: stl
+            .faces
+            .iter()
+            .flat_map(|it| it.vertices)
+            .map(|vi| stl.vertices[vi])
+            .collect(),
+        faces: stl
+            .faces
+            .iter()
+            .enumerate()
+            .map(|(i, face)| IndexedTriangle {
+                normal: face.normal,
+                vertices: [3 * i, 3 * i + 1, 3 * i + 2],
+            })
+            .collect(),
+    }
+}
+
+/// Push the POSITION buffer and accessor.
+/// The min/max of the accessor are computed from the very buffer it points into.
+fn push_positions(
+    gltf: &mut GltfBuilder,
+    positions: Vec<V3>,
+) -> Result<json::Index<json::Accessor>, ConvertError> {
+    let (min, max) = bounding_coords(&positions);
+    log::debug!("min: {min:?} max: {max:?}");
+    let vcount = positions.len();
+    let positions_view =
+        gltf.push_buffer_with_view(Some("positions".to_string()), positions, Some(1), None)?;
+    Ok(gltf.push_accessor_vec3(
+        Some("positions".to_string()),
+        positions_view,
+        0,
+        vcount,
+        Some(min),
+        Some(max),
+    ))
+}
+
+/// Push the POSITION and NORMAL accessors over one buffer view each.
+/// The normals get a min/max with `normal_bounds`. With `sparse_normals`, when most of them
+/// are equal, they are stored as a base view filled with that modal normal and a sparse
+/// accessor of the others.
+fn push_separate_vertices(
+    gltf: &mut GltfBuilder,
+    positions: Vec<V3>,
+    normals: Option<Vec<V3>>,
+    quantization: Option<PositionQuantization>,
+    normal_bounds: bool,
+    sparse_normals: bool,
+) -> Result<
+    (
+        json::Index<json::Accessor>,
+        Option<json::Index<json::Accessor>>,
+    ),
+    ConvertError,
+> {
+    let vcount = positions.len();
+    let positions = match quantization {
+        Some(quantization) => {
+            gltf.use_extension("KHR_mesh_quantization", true);
+            let normalized = positions
+                .iter()
+                .map(|p| [0, 1, 2].map(|k| (p.v[k] - quantization.offset[k]) / quantization.scale))
+                .collect::<Vec<_>>();
+            push_normalized_vec3(gltf, "positions", &normalized, quantization.bits, true)?
+        }
+        None => push_positions(gltf, positions)?,
+    };
+
+    let normals = normals
+        .map(|normals| -> Result<_, ConvertError> {
+            if let Some(quantization) = quantization {
+                let normals = normals.iter().map(|it| it.v).collect::<Vec<_>>();
+                return push_normalized_vec3(
+                    gltf,
+                    "normals",
+                    &normals,
+                    quantization.bits,
+                    normal_bounds,
+                );
+            }
+            let bounds = normal_bounds.then(|| bounding_coords(&normals));
+            if sparse_normals {
+                let modal = modal_vec3(&normals);
+                let indices = (0..vcount as u32)
+                    .filter(|&vi| normals[vi as usize].v != modal)
+                    .collect::<Vec<_>>();
+                if !indices.is_empty() && indices.len() < vcount / 2 {
+                    let values = indices.iter().map(|&vi| normals[vi as usize].v).collect();
+                    // Zeros are the default of the sparse accessors
+                    let base = (modal != [0.0; 3])
+                        .then(|| {
+                            gltf.push_buffer_with_view(None, vec![modal; vcount], Some(1), None)
+                        })
+                        .transpose()?;
+                    return gltf.push_sparse_accessor_vec3(
+                        Some("normals".to_string()),
+                        base,
+                        vcount,
+                        &indices,
+                        values,
+                        bounds.map(|it| it.0),
+                        bounds.map(|it| it.1),
+                    );
+                }
+            }
+            let normals_view =
+                gltf.push_buffer_with_view(Some("normals".to_string()), normals, Some(1), None)?;
+            Ok(gltf.push_accessor_vec3(
+                Some("normals".to_string()),
+                normals_view,
+                0,
+                vcount,
+                bounds.map(|it| it.0),
+                bounds.map(|it| it.1),
+            ))
+        })
+        .transpose()?;
+    Ok((positions, normals))
+}
+
+/// Most frequent value (zero when `values` is empty)
+fn modal_vec3(values: &[V3]) -> [f32; 3] {
+    let mut counts = HashMap::<[u32; 3], usize>::new();
+    let mut modal = ([0.0; 3], 0);
+    for value in values {
+        let count = counts.entry(value.v.map(f32::to_bits)).or_default();
+        *count += 1;
+        if *count > modal.1 {
+            modal = (value.v, *count);
+        }
+    }
+    modal.0
+}
+
+/// Push the POSITION and NORMAL accessors over a single buffer view, where the normal of
+/// each vertex follows its position. The normals get a min/max with `normal_bounds`.
+fn push_interleaved_vertices(
+    gltf: &mut GltfBuilder,
+    positions: Vec<V3>,
+    normals: Vec<V3>,
+    normal_bounds: bool,
+) -> Result<(json::Index<json::Accessor>, json::Index<json::Accessor>), ConvertError> {
+    let (min, max) = bounding_coords(&positions);
+    let bounds = normal_bounds.then(|| bounding_coords(&normals));
+    log::debug!("min: {min:?} max: {max:?}");
+    let vcount = positions.len();
+    let vertices = positions
+        .iter()
+        .zip(&normals)
+        .map(|(p, n)| [p.v, n.v])
+        .collect::<Vec<_>>();
+    let view = gltf.push_buffer_with_view(Some("vertices".to_string()), vertices, Some(1), None)?;
+    let positions = gltf.push_accessor_vec3(
+        Some("positions".to_string()),
+        view,
+        0,
+        vcount,
+        Some(min),
+        Some(max),
+    );
+    let normals = gltf.push_accessor_vec3(
+        Some("normals".to_string()),
+        view,
+        3,
+        vcount,
+        bounds.map(|it| it.0),
+        bounds.map(|it| it.1),
+    );
+    Ok((positions, normals))
+}
+
+/// Add the normal of each face to the normals of its vertices
+fn accumulate_face_normals(
+    stl: &IndexedMesh,
+    faces: &[IndexedTriangle],
+    mode: NormalMode,
+    normals: &mut [V3],
+    normals_count: &mut [u32],
+) {
+    // Degenerate faces have no direction: their normal would poison their neighbors
+    for face in faces.iter().filter(|face| !is_degenerate(stl, face)) {
+        for (k, vi) in face.vertices.into_iter().enumerate() {
+            let contribution = corner_normal_contribution(stl, face, k, mode);
+            for (n, value) in normals[vi].v.iter_mut().zip(contribution) {
+                *n += value;
+            }
+            normals_count[vi] += 1;
+        }
+    }
+}
+
+/// Whether the face has a (near) zero area: collinear or coincident vertices
+fn is_degenerate(stl: &IndexedMesh, face: &IndexedTriangle) -> bool {
+    let [a, b, c] = face.vertices.map(|vi| stl.vertices[vi].0);
+    let (ab, ac) = (sub(b, a), sub(c, a));
+    let n = cross(ab, ac);
+    // |ab x ac| = |ab| |ac| sin(angle)
+    let area_sq = dot(n, n);
+    area_sq.is_nan() || area_sq <= DEGENERATE_SIN_SQ * dot(ab, ab) * dot(ac, ac)
+}
+
+/// Weighted normal of a face at its corner `k`
+fn corner_normal_contribution(
+    stl: &IndexedMesh,
+    face: &IndexedTriangle,
+    k: usize,
+    mode: NormalMode,
+) -> [f32; 3] {
+    let corners = face.vertices.map(|vi| stl.vertices[vi].0);
+    let [a, b, c] = corners;
+    // The magnitude of the cross product is twice the area of the triangle
+    let face_cross = cross(sub(b, a), sub(c, a));
+    match mode {
+        NormalMode::Simple => {
+            // Some exporters write zero face normals, to be recomputed from the winding
+            let n = face.normal.0;
+            if n == [0.0, 0.0, 0.0] || !n.iter().all(|it| it.is_finite()) {
+                normalize(face_cross)
+            } else {
+                n
+            }
+        }
+        NormalMode::Area => face_cross,
+        NormalMode::Angle => {
+            let p = corners[k];
+            let angle = angle_between(sub(corners[(k + 1) % 3], p), sub(corners[(k + 2) % 3], p));
+            normalize(face_cross).map(|it| it * angle)
+        }
+    }
+}
+
+/// Compute the vertex normals, only averaging the faces whose normals are within `crease_angle`
+/// (in degrees) of each other. Vertices on a hard edge are split, one per distinct normal.
+/// Returns the new mesh, its normals, and the original index of each new vertex.
+fn split_creases(
+    stl: &IndexedMesh,
+    mode: NormalMode,
+    crease_angle: f32,
+) -> (IndexedMesh, Vec<V3>, Vec<usize>) {
+    let face_normals = stl
+        .faces
+        .iter()
+        .map(|face| {
+            let [a, b, c] = face.vertices.map(|vi| stl.vertices[vi].0);
+            normalize(cross(sub(b, a), sub(c, a)))
+        })
+        .collect::<Vec<_>>();
+    let degenerate = stl
+        .faces
+        .iter()
+        .map(|face| is_degenerate(stl, face))
+        .collect::<Vec<_>>();
+    let mut incident = vec![Vec::new(); stl.vertices.len()];
+    for (fi, face) in stl.faces.iter().enumerate() {
+        for (k, vi) in face.vertices.into_iter().enumerate() {
+            incident[vi].push((fi, k));
+        }
+    }
+    // Small tolerance so that coplanar faces are always smoothed together
+    let min_cos = crease_angle.to_radians().cos() - 1e-6;
+
+    let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut origins = Vec::new();
+    let mut new_index = HashMap::<(usize, [u32; 3]), usize>::new();
+    let mut faces = stl.faces.clone();
+    for (vi, corners) in incident.iter().enumerate() {
+        for &(fi, k) in corners {
+            let mut sum = [0.0f32; 3];
+            for &(other, other_k) in corners {
+                if degenerate[other] {
+                    continue;
+                }
+                if other == fi || dot(face_normals[fi], face_normals[other]) >= min_cos {
+                    let contribution =
+                        corner_normal_contribution(stl, &stl.faces[other], other_k, mode);
+                    for (s, value) in sum.iter_mut().zip(contribution) {
+                        *s += value;
+                    }
+                }
+            }
+            let mut normal = normalize(sum);
+            if normal == [0.0, 0.0, 0.0] {
+                normal = [0.0, 0.0, 1.0];
+            }
+            let index = *new_index
+                .entry((vi, normal.map(f32::to_bits)))
+                .or_insert_with(|| {
+                    vertices.push(stl.vertices[vi]);
+                    normals.push(V3 { v: normal });
+                    origins.push(vi);
+                    vertices.len() - 1
+                });
+            faces[fi].vertices[k] = index;
+        }
+    }
+    (IndexedMesh { vertices, faces }, normals, origins)
+}
+
+/// Translation moving the points according to the center mode.
+/// `up_axis` is the up axis of the points.
+fn center_offset(
+    points: impl Iterator<Item = [f32; 3]>,
+    mode: CenterMode,
+    up_axis: UpAxis,
+) -> [f32; 3] {
+    if mode == CenterMode::None {
+        return [0.0, 0.0, 0.0];
+    }
+    let (min, max) = points.fold(([f32::MAX; 3], [f32::MIN; 3]), |(min, max), p| {
+        (
+            [0, 1, 2].map(|k| min[k].min(p[k])),
+            [0, 1, 2].map(|k| max[k].max(p[k])),
+        )
+    });
+    if min[0] > max[0] {
+        // No points
+        return [0.0, 0.0, 0.0];
+    }
+    match mode {
+        CenterMode::None => [0.0, 0.0, 0.0],
+        CenterMode::Bbox => [0, 1, 2].map(|k| -(min[k] + max[k]) / 2.0),
+        CenterMode::Base => match up_axis {
+            UpAxis::Z => [0.0, 0.0, -min[2]],
+            UpAxis::Y => [0.0, -min[1], 0.0],
+        },
+    }
+}
+
+/// Copy of the options whose translation also applies the center mode to the input points
+fn centered_options(
+    options: &ConvertOptions,
+    points: impl Iterator<Item = [f32; 3]>,
+    up_axis: UpAxis,
+) -> ConvertOptions {
+    let offset = center_offset(
+        points.map(|p| options.transform_point(p)),
+        options.center,
+        up_axis,
+    );
+    ConvertOptions {
+        translate: [0, 1, 2].map(|k| options.translate[k] + offset[k]),
+        center: CenterMode::None,
+        ..options.clone()
+    }
+}
+
+/// Generate texture coordinates by projecting the vertices on a plane aligned with the axes.
+/// Each coordinate is scaled to [0, 1] over the bounding box of the mesh.
+/// `normals` are only used by the box projection.
+fn project_uvs(positions: &[V3], normals: &[V3], mode: UvMode) -> Vec<[f32; 2]> {
+    let (min, max) = bounding_coords(positions);
+    let extent = [0, 1, 2].map(|k| max[k] - min[k]);
+    let scale = |p: [f32; 3], k: usize| {
+        if extent[k] > 0.0 {
+            (p[k] - min[k]) / extent[k]
+        } else {
+            0.0
+        }
+    };
+    // Axes of the projection plane for a given normal axis
+    let plane_axes = |axis: usize| match axis {
+        0 => (2, 1),
+        1 => (0, 2),
+        _ => (0, 1),
+    };
+    let smallest_axis = (0..3)
+        .min_by(|&a, &b| extent[a].total_cmp(&extent[b]))
+        .unwrap_or(2);
+    positions
+        .iter()
+        .enumerate()
+        .map(|(vi, p)| {
+            let axis = match mode {
+                UvMode::Planar => smallest_axis,
+                UvMode::Box => (0..3)
+                    .max_by(|&a, &b| {
+                        let n = normals[vi].v;
+                        n[a].abs().total_cmp(&n[b].abs()).then(b.cmp(&a))
+                    })
+                    .unwrap_or(2),
+            };
+            let (u, v) = plane_axes(axis);
+            // The V axis of glTF points down
+            [scale(p.v, u), 1.0 - scale(p.v, v)]
+        })
+        .collect()
+}
+
+/// Push the material described by the options, if any, with its base color texture
+fn push_options_material(
+    gltf: &mut GltfBuilder,
+    options: &ConvertOptions,
+    texture: Option<json::Index<json::Texture>>,
+) -> Result<Option<json::Index<json::Material>>, ConvertError> {
+    if options.material.is_none() && texture.is_none() {
+        return Ok(None);
+    }
+    let material = gltf.push_material(None, &options.material.unwrap_or_default());
+    if let Some(texture) = texture {
+        gltf.set_base_color_texture(material, texture)?;
+    }
+    Ok(Some(material))
+}
+
+/// Push the material variants of the options, each with a material named after it.
+/// The other parameters of the materials and the texture come from the options.
+/// Returns the `(variant, material)` pairs of the meshes.
+fn push_variant_materials(
+    gltf: &mut GltfBuilder,
+    options: &ConvertOptions,
+    texture: Option<json::Index<json::Texture>>,
+) -> Result<Vec<(u32, json::Index<json::Material>)>, ConvertError> {
+    let base = options.material.unwrap_or_default();
+    let mut variants = Vec::with_capacity(options.variants.len());
+    for variant in &options.variants {
+        let material = MaterialOptions {
+            base_color: variant.base_color,
+            ..base
+        };
+        let material = gltf.push_material(Some(variant.name.clone()), &material);
+        if let Some(texture) = texture {
+            gltf.set_base_color_texture(material, texture)?;
+        }
+        variants.push((gltf.push_material_variant(&variant.name), material));
+    }
+    Ok(variants)
+}
+
+/// Push the texture of the options, if any
+fn push_options_texture(
+    gltf: &mut GltfBuilder,
+    options: &ConvertOptions,
+) -> Result<Option<json::Index<json::Texture>>, ConvertError> {
+    let Some(path) = &options.texture else {
+        return Ok(None);
+    };
+    let image = gltf.push_image_from_file(path, true)?;
+    let sampler = gltf.push_sampler(
+        Some(json::texture::MagFilter::Linear),
+        Some(json::texture::MinFilter::LinearMipmapLinear),
+        json::texture::WrappingMode::Repeat,
+    );
+    Ok(Some(gltf.push_texture(image, Some(sampler))))
+}
+
+/// Push one material per group, named after it, with the base colors of `GROUP_PALETTE`.
+/// The other parameters of the material and the texture come from the options.
+fn push_group_materials(
+    gltf: &mut GltfBuilder,
+    options: &ConvertOptions,
+    names: &[String],
+    texture: Option<json::Index<json::Texture>>,
+) -> Result<Vec<json::Index<json::Material>>, ConvertError> {
+    let base = options.material.unwrap_or_default();
+    let mut materials = Vec::with_capacity(names.len());
+    for (i, name) in names.iter().enumerate() {
+        let material = MaterialOptions {
+            base_color: GROUP_PALETTE[i % GROUP_PALETTE.len()],
+            ..base
+        };
+        let material = gltf.push_material(Some(name.clone()), &material);
+        if let Some(texture) = texture {
+            gltf.set_base_color_texture(material, texture)?;
+        }
+        materials.push(material);
+    }
+    Ok(materials)
+}
+
+/// Average the colors of the faces incident to each vertex
+fn face_colors_to_vertex_colors(stl: &IndexedMesh, face_colors: &[[f32; 4]]) -> Vec<[f32; 4]> {
+    let mut colors = vec![[0.0f32; 4]; stl.vertices.len()];
+    let mut counts = vec![0u32; stl.vertices.len()];
+    for (face, face_color) in stl.faces.iter().zip(face_colors) {
+        for vi in face.vertices {
+            for (c, value) in colors[vi].iter_mut().zip(face_color) {
+                *c += value;
+            }
+            counts[vi] += 1;
+        }
+    }
+    for (color, count) in colors.iter_mut().zip(counts) {
+        if count > 0 {
+            *color = color.map(|it| it / count as f32);
+        } else {
+            *color = [1.0, 1.0, 1.0, 1.0];
+        }
+    }
+    colors
+}
+
+/// Ambient occlusion of each vertex, see `ao::bake_ambient_occlusion`
+#[cfg(feature = "ao")]
+fn bake_occlusion(
+    stl: &IndexedMesh,
+    positions: &[V3],
+    normals: Option<&[V3]>,
+    samples: u32,
+) -> Result<Vec<f32>, ConvertError> {
+    let normals = normals.ok_or_else(|| {
+        ConvertError::InvalidData("Ambient occlusion requires the vertex normals".to_string())
+    })?;
+    let faces = stl.faces.iter().map(|it| it.vertices).collect::<Vec<_>>();
+    Ok(crate::ao::bake_ambient_occlusion(
+        positions, normals, &faces, samples,
+    ))
+}
+
+#[cfg(not(feature = "ao"))]
+fn bake_occlusion(
+    _stl: &IndexedMesh,
+    _positions: &[V3],
+    _normals: Option<&[V3]>,
+    _samples: u32,
+) -> Result<Vec<f32>, ConvertError> {
+    Err(ConvertError::UnsupportedFormat(
+        "Ambient occlusion baking requires the `ao` feature".to_string(),
+    ))
+}
+
+/// Values of the three corners of each face, in the order of the faces
+fn face_corners<T: Copy + Send + Sync>(stl: &IndexedMesh, values: &[T]) -> Vec<T> {
+    stl.faces
+        .par_iter()
+        .flat_map_iter(|it| it.vertices.map(|vi| values[vi]))
+        .collect()
+}
+
+/// Push the buffers and accessors of a triangle mesh, and return the primitive using them.
+/// When `normals` is `None`, the vertex normals are computed from the faces,
+/// unless `options.no_normals` is set.
+/// `uvs` (one per vertex) are emitted as a `TEXCOORD_0` attribute, unless generated by `options.uv`.
+/// `face_colors` (one per face) or `vertex_colors` are emitted as a `COLOR_0` attribute.
+/// The geometry is rotated to Y-up according to `up_axis`.
+/// With a `quantization`, the positions and normals are stored as normalized integers.
+#[allow(clippy::too_many_arguments)]
+fn push_primitive(
+    gltf: &mut GltfBuilder,
+    stl: IndexedMesh,
+    normals: Option<Vec<V3>>,
+    uvs: Option<Vec<[f32; 2]>>,
+    face_colors: Option<Vec<[f32; 4]>>,
+    vertex_colors: Option<Vec<[f32; 4]>>,
+    options: &ConvertOptions,
+    up_axis: UpAxis,
+    quantization: Option<PositionQuantization>,
+) -> Result<json::mesh::Primitive, ConvertError> {
+    let mut stl = stl;
+    let mut normals = normals;
+    let mut uvs = uvs;
+    let mut face_colors = face_colors;
+    let mut vertex_colors = vertex_colors;
+    if let Some(precision) = options.reindex {
+        let removed = reindex_vertices(&mut stl, precision);
+        log::info!("Reindexed: {} duplicate vertices removed", removed);
+        // The vertices changed, the provided vertex attributes do not match anymore
+        if removed > 0 {
+            normals = None;
+            uvs = None;
+            vertex_colors = None;
+        }
+    }
+    if let Some(tolerance) = options.weld_tolerance {
+        let welded = if options.weld_within_components {
+            weld_vertices_within_components(&mut stl, tolerance)
+        } else {
+            weld_vertices(&mut stl, tolerance)
+        };
+        log::info!("Welded {} vertices", welded);
+        // The vertices changed, the provided vertex attributes do not match anymore
+        if welded > 0 {
+            normals = None;
+            uvs = None;
+            vertex_colors = None;
+        }
+    }
+
+    if let Some(ratio) = options.simplify {
+        let before = stl.faces.len();
+        let kept = simplify_mesh_faces(&mut stl, ratio);
+        log::info!("Simplified: {} -> {} triangles", before, stl.faces.len());
+        // The vertices changed, the provided vertex attributes do not match anymore
+        normals = None;
+        uvs = None;
+        vertex_colors = None;
+        face_colors = face_colors.map(|colors| kept.iter().map(|&fi| colors[fi]).collect());
+    }
+
+    // Done after welding, which can collapse faces
+    let degenerate = stl
+        .faces
+        .iter()
+        .map(|face| is_degenerate(&stl, face))
+        .collect::<Vec<_>>();
+    let degenerate_count = degenerate.iter().filter(|&&it| it).count();
+    if options.drop_degenerate && degenerate_count > 0 {
+        let mut keep = degenerate.iter().map(|&it| !it);
+        stl.faces.retain(|_| keep.next().unwrap_or(true));
+        face_colors = face_colors.map(|colors| {
+            colors
+                .into_iter()
+                .zip(&degenerate)
+                .filter_map(|(color, &degenerate)| (!degenerate).then_some(color))
+                .collect()
+        });
+        log::info!("Dropped {} degenerate triangles", degenerate_count);
+    } else if degenerate_count > 0 {
+        log::warn!("Warning: {} degenerate triangles", degenerate_count);
+    }
+    if degenerate_count == degenerate.len() {
+        return Err(ConvertError::EmptyMesh);
+    }
+    if options.fix_winding {
+        let flipped = fix_winding(&mut stl);
+        log::info!("Fixed the winding of {} triangles", flipped);
+    }
+
+    // Box projection and ambient occlusion need the normals even when they are not emitted
+    let need_normals = !options.no_normals
+        || options.uv == Some(UvMode::Box)
+        || options.ambient_occlusion.is_some();
+    if !need_normals {
+        normals = None;
+    }
+
+    // Flat shading: every face corner is a distinct vertex with the normal of its face
+    let flat = options.shading == ShadingMode::Flat || options.stl_normals;
+    if flat {
+        uvs = uvs.map(|uvs| face_corners(&stl, &uvs));
+        vertex_colors = vertex_colors.map(|colors| face_corners(&stl, &colors));
+        normals = need_normals.then(|| {
+            stl.faces
+                .iter()
+                .flat_map(|face| {
+                    if options.stl_normals {
+                        return [V3 { v: face.normal.0 }; 3];
+                    }
+                    let [a, b, c] = face.vertices.map(|vi| stl.vertices[vi].0);
+                    let mut n = normalize(face.normal.0);
+                    // Some exporters do not write the face normals
+                    if n == [0.0, 0.0, 0.0] {
+                        n = normalize(cross(sub(b, a), sub(c, a)));
+                    }
+                    // Degenerate faces have no normal
+                    if n == [0.0, 0.0, 0.0] {
+                        n = [0.0, 0.0, 1.0];
+                    }
+                    [V3 { v: n }; 3]
+                })
+                .collect()
+        });
+        stl = unweld_faces(&stl);
+    }
+
+    if let (Some(crease_angle), None, false, true) =
+        (options.crease_angle, &normals, flat, need_normals)
+    {
+        let (split, split_normals, origins) = split_creases(&stl, options.normals, crease_angle);
+        uvs = uvs.map(|uvs| origins.iter().map(|&vi| uvs[vi]).collect());
+        vertex_colors = vertex_colors.map(|colors| origins.iter().map(|&vi| colors[vi]).collect());
+        normals = Some(split_normals);
+        stl = split;
+    }
+
+    let mut normals = need_normals
+        .then(|| normals.unwrap_or_else(|| compute_vertex_normals(&stl, options.normals)));
+    let mut positions = stl
+        .vertices
+        .iter()
+        .map(|it| V3 {
+            v: options.transform_point(it.0),
+        })
+        .collect::<Vec<V3>>();
+
+    // Bake the axis conversion into the geometry so the node transform stays identity
+    if up_axis == UpAxis::Z {
+        for p in &mut positions {
+            p.v = z_up_to_y_up(p.v);
+        }
+        for n in normals.iter_mut().flatten() {
+            n.v = z_up_to_y_up(n.v);
+        }
+    }
+
+    if options.flip_normals {
+        for n in normals.iter_mut().flatten() {
+            n.v = n.v.map(|it| -it);
+        }
+    }
+    // Done after computing the normals so both flips stay independent
+    if options.flip_winding {
+        for face in &mut stl.faces {
+            face.vertices.swap(1, 2);
+        }
+    }
+
+    if let Some(mode) = options.uv {
+        uvs = Some(project_uvs(
+            &positions,
+            normals.as_deref().unwrap_or(&[]),
+            mode,
+        ));
+    }
+    let occlusion = options
+        .ambient_occlusion
+        .map(|ao| bake_occlusion(&stl, &positions, normals.as_deref(), ao.samples))
+        .transpose()?;
+    if options.no_normals {
+        normals = None;
+    }
+
+    let (positions, normals, uvs, colors, occlusion, indices) = if options.indexed && !flat {
+        // Collected in order: the triangle order is kept
+        let indices = stl
+            .faces
+            .par_iter()
+            .flat_map_iter(|it| it.vertices.map(|vi| vi as u32))
+            .collect::<Vec<_>>();
+        let colors = face_colors
+            .map(|it| face_colors_to_vertex_colors(&stl, &it))
+            .or(vertex_colors);
+        if options.optimize {
+            let indices = optimize_vertex_cache(&indices, positions.len());
+            let remap = vertex_fetch_remap(&indices, positions.len());
+            let indices = indices.iter().map(|&vi| remap[vi as usize]).collect();
+            (
+                remap_vertices(&positions, &remap),
+                normals.map(|it| remap_vertices(&it, &remap)),
+                uvs.map(|it| remap_vertices(&it, &remap)),
+                colors.map(|it| remap_vertices(&it, &remap)),
+                occlusion.map(|it| remap_vertices(&it, &remap)),
+                Some(indices),
+            )
+        } else {
+            (positions, normals, uvs, colors, occlusion, Some(indices))
+        }
+    } else {
+        // Triangle soup: one vertex per face corner
+        let positions_noind = face_corners(&stl, &positions);
+        let normals_noind = normals.map(|normals| face_corners(&stl, &normals));
+        let uvs_noind = uvs.map(|uvs| face_corners(&stl, &uvs));
+        let occlusion_noind = occlusion.map(|occlusion| face_corners(&stl, &occlusion));
+        let colors_noind = face_colors
+            .map(|it| {
+                it.par_iter()
+                    .flat_map_iter(|&color| [color; 3])
+                    .collect::<Vec<_>>()
+            })
+            .or_else(|| vertex_colors.map(|colors| face_corners(&stl, &colors)));
+        (
+            positions_noind,
+            normals_noind,
+            uvs_noind,
+            colors_noind,
+            occlusion_noind,
+            None,
+        )
+    };
+
+    // Gray colors, or colors darkened by the occlusion
+    let (colors, occlusion) = match (options.ambient_occlusion, occlusion) {
+        (Some(ao), Some(occlusion)) if ao.target == AoTarget::Color => {
+            let colors = match colors {
+                Some(colors) => colors
+                    .iter()
+                    .zip(&occlusion)
+                    .map(|(c, ao)| [c[0] * ao, c[1] * ao, c[2] * ao, c[3]])
+                    .collect(),
+                None => occlusion.iter().map(|&ao| [ao, ao, ao, 1.0]).collect(),
+            };
+            (Some(colors), None)
+        }
+        (_, occlusion) => (colors, occlusion),
+    };
+
+    let tangents = match (options.tangents, &uvs, &normals) {
+        (false, _, _) => None,
+        (true, None, _) => {
+            log::warn!("Warning: no texture coordinates, tangents are not computed");
+            None
+        }
+        (true, _, None) => {
+            log::warn!("Warning: no normals, tangents are not computed");
+            None
+        }
+        (true, Some(uvs), Some(normals)) => Some(match &indices {
+            Some(indices) => compute_tangents(
+                &positions,
+                normals,
+                uvs,
+                indices
+                    .chunks_exact(3)
+                    .map(|it| [it[0], it[1], it[2]].map(|vi| vi as usize)),
+            ),
+            None => compute_tangents(
+                &positions,
+                normals,
+                uvs,
+                (0..positions.len() / 3).map(|it| [3 * it, 3 * it + 1, 3 * it + 2]),
+            ),
+        }),
+    };
+
+    let vcount = positions.len();
+    let (positions, normals) = match (options.layout, quantization, normals) {
+        // Quantized vertices are always separate
+        (VertexLayout::Interleaved, None, Some(normals)) => {
+            let (positions, normals) =
+                push_interleaved_vertices(gltf, positions, normals, options.full_bounds)?;
+            (positions, Some(normals))
+        }
+        (_, quantization, normals) => push_separate_vertices(
+            gltf,
+            positions,
+            normals,
+            quantization,
+            options.full_bounds,
+            options.sparse_normals,
+        )?,
+    };
+
+    let colors = colors
+        .map(|colors| -> Result<_, ConvertError> {
+            let colors_view =
+                gltf.push_buffer_with_view(Some("colors".to_string()), colors, Some(1), None)?;
+            Ok(gltf.push_accessor_vec4(Some("colors".to_string()), colors_view, 0, vcount))
+        })
+        .transpose()?;
+
+    let occlusion = occlusion
+        .map(|occlusion| -> Result<_, ConvertError> {
+            let name = Some("ambient occlusion".to_string());
+            let view = gltf.push_buffer_with_view(name.clone(), occlusion, Some(1), None)?;
+            Ok(gltf.push_accessor_f32(name, view, 0, vcount))
+        })
+        .transpose()?;
+
+    let uvs = uvs
+        .map(|uvs| -> Result<_, ConvertError> {
+            let uvs_view =
+                gltf.push_buffer_with_view(Some("uvs".to_string()), uvs, Some(1), None)?;
+            Ok(gltf.push_accessor_vec2(Some("uvs".to_string()), uvs_view, 0, vcount))
+        })
+        .transpose()?;
+
+    let tangents = tangents
+        .map(|tangents| -> Result<_, ConvertError> {
+            let tangents_view =
+                gltf.push_buffer_with_view(Some("tangents".to_string()), tangents, Some(1), None)?;
+            Ok(gltf.push_accessor_vec4(Some("tangents".to_string()), tangents_view, 0, vcount))
+        })
+        .transpose()?;
+
+    let indices = indices
+        .map(|indices| -> Result<_, ConvertError> {
+            let nb_indices = indices.len();
+            let name = Some("indices".to_string());
+            // 65535 is the primitive restart value, it cannot be used as an index
+            let fits_u16 = vcount <= u16::MAX as usize;
+            let use_u16 = match options.index_type {
+                IndexType::Auto => fits_u16,
+                IndexType::U16 if !fits_u16 => {
+                    log::warn!(
+                        "Warning: {} vertices do not fit u16 indices, using u32",
+                        vcount
+                    );
+                    false
+                }
+                IndexType::U16 => true,
+                IndexType::U32 => false,
+            };
+            let (min, max) = if options.full_bounds {
+                (indices.iter().min().copied(), indices.iter().max().copied())
+            } else {
+                (None, None)
+            };
+            if use_u16 {
+                let indices = indices.into_iter().map(|it| it as u16).collect::<Vec<_>>();
+                let view = gltf.push_buffer_with_view(name.clone(), indices, None, None)?;
+                let [min, max] = [min, max].map(|it| it.map(|it| it as u16));
+                Ok(gltf.push_accessor_u16(name, view, 0, nb_indices, min, max))
+            } else {
+                let view = gltf.push_buffer_with_view(name.clone(), indices, None, None)?;
+                Ok(gltf.push_accessor_u32(name, view, 0, nb_indices, min, max))
+            }
+        })
+        .transpose()?;
+
+    Ok(json::mesh::Primitive {
+        attributes: {
+            let mut map = std::collections::BTreeMap::new();
+            map.insert(Valid(json::mesh::Semantic::Positions), positions);
+            if let Some(normals) = normals {
+                map.insert(Valid(json::mesh::Semantic::Normals), normals);
+            }
+            if let Some(uvs) = uvs {
+                map.insert(Valid(json::mesh::Semantic::TexCoords(0)), uvs);
+            }
+            if let Some(tangents) = tangents {
+                map.insert(Valid(json::mesh::Semantic::Tangents), tangents);
+            }
+            if let Some(colors) = colors {
+                map.insert(Valid(json::mesh::Semantic::Colors(0)), colors);
+            }
+            if let Some(occlusion) = occlusion {
+                let semantic = json::mesh::Semantic::Extras("AMBIENT_OCCLUSION".to_string());
+                map.insert(Valid(semantic), occlusion);
+            }
+            map
+        },
+        extensions: Default::default(),
+        extras: Default::default(),
+        indices,
+        material: None,
+        mode: Valid(json::mesh::Mode::Triangles),
+        targets: None,
+    })
+}
+
+/// Append the triangles described by `indices` (relative to `first_vertex`) to `stl`.
+/// The face normals are computed from the winding.
+fn push_triangles(
+    stl: &mut IndexedMesh,
+    first_vertex: usize,
+    indices: &[usize],
+) -> Result<(), ConvertError> {
+    if !indices.len().is_multiple_of(3) {
+        return Err(ConvertError::InvalidData(
+            "Triangle indices count is not a multiple of 3".to_string(),
+        ));
+    }
+    let vcount = stl.vertices.len() - first_vertex;
+    for tri in indices.chunks_exact(3) {
+        if tri.iter().any(|&i| i >= vcount) {
+            return Err(ConvertError::InvalidData(
+                "Triangle index out of bounds".to_string(),
+            ));
+        }
+        let vertices = [
+            first_vertex + tri[0],
+            first_vertex + tri[1],
+            first_vertex + tri[2],
+        ];
+        let [a, b, c] = vertices.map(|i| stl.vertices[i].0);
+        let normal = normalize(cross(sub(b, a), sub(c, a)));
+        stl.faces.push(stl_io::IndexedTriangle {
+            normal: stl_io::Normal::new(normal),
+            vertices,
+        });
+    }
+    Ok(())
+}
+
+/// Append the triangles of every primitive of `mesh` to `stl`, transformed by `transform`
+fn append_gltf_mesh(
+    stl: &mut IndexedMesh,
+    mesh: gltf::Mesh,
+    buffers: &[gltf::buffer::Data],
+    transform: &[[f32; 4]; 4],
+    up_axis: UpAxis,
+    face_normals: StlNormalMode,
+) -> Result<(), ConvertError> {
+    for primitive in mesh.primitives() {
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            return Err(ConvertError::UnsupportedFormat(format!(
+                "Unsupported primitive mode {:?} in mesh {}",
+                primitive.mode(),
+                mesh.name().unwrap_or(&mesh.index().to_string())
+            )));
+        }
+        let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|it| &it.0[..]));
+        let positions = reader
+            .read_positions()
+            .ok_or_else(|| ConvertError::InvalidData("Primitive has no positions".to_string()))?;
+
+        let first_vertex = stl.vertices.len();
+        for p in positions {
+            let p = mat4_transform_point(transform, p);
+            let p = if up_axis == UpAxis::Z {
+                y_up_to_z_up(p)
+            } else {
+                p
+            };
+            stl.vertices.push(stl_io::Vertex::new(p));
+        }
+        let vcount = stl.vertices.len() - first_vertex;
+
+        // Non-indexed primitives are a plain triangle soup
+        let indices = match reader.read_indices() {
+            Some(indices) => indices.into_u32().map(|it| it as usize).collect::<Vec<_>>(),
+            None => (0..vcount).collect(),
+        };
+        // STL stores face normals: they are recomputed from the winding
+        let first_face = stl.faces.len();
+        push_triangles(stl, first_vertex, &indices)?;
+
+        let normals = match face_normals {
+            StlNormalMode::Winding => None,
+            StlNormalMode::Average => reader.read_normals(),
+        };
+        if let Some(normals) = normals {
+            let normals = normals
+                .map(|n| {
+                    let n = mat4_transform_normal(transform, n);
+                    match up_axis {
+                        UpAxis::Z => y_up_to_z_up(n),
+                        UpAxis::Y => n,
+                    }
+                })
+                .collect::<Vec<_>>();
+            if normals.len() != vcount {
+                return Err(ConvertError::InvalidData(format!(
+                    "Expected {} normals, got {}",
+                    vcount,
+                    normals.len()
+                )));
+            }
+            for face in &mut stl.faces[first_face..] {
+                let normal = normalize(face.vertices.iter().fold([0.0; 3], |sum, &vi| {
+                    let n = normalize(normals[vi - first_vertex]);
+                    [0, 1, 2].map(|k| sum[k] + n[k])
+                }));
+                // The winding normal is kept when the vertex normals cancel out
+                if normal != [0.0, 0.0, 0.0] {
+                    face.normal = stl_io::Normal::new(normal);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn append_gltf_node(
+    stl: &mut IndexedMesh,
+    node: gltf::Node,
+    buffers: &[gltf::buffer::Data],
+    parent_transform: &[[f32; 4]; 4],
+    up_axis: UpAxis,
+    face_normals: StlNormalMode,
+) -> Result<(), ConvertError> {
+    let transform = mat4_mul(parent_transform, &node.transform().matrix());
+    if let Some(mesh) = node.mesh() {
+        append_gltf_mesh(stl, mesh, buffers, &transform, up_axis, face_normals)?;
+    }
+    for child in node.children() {
+        append_gltf_node(stl, child, buffers, &transform, up_axis, face_normals)?;
+    }
+    Ok(())
+}
+
+/// Reverse the winding of the faces, and their normals with it
+fn flip_faces(stl: &mut IndexedMesh) {
+    for face in &mut stl.faces {
+        face.vertices.swap(1, 2);
+        face.normal.0 = face.normal.0.map(|it| -it);
+    }
+}
+
+/// Load a glTF/GLB file and flatten the triangles of its default scene into an STL mesh
+pub fn gltf_to_stl(
+    input_filename: impl AsRef<Path>,
+    options: ConvertOptions,
+) -> Result<IndexedMesh, ConvertError> {
+    let up_axis = options.up_axis;
+    let input_filename = input_filename.as_ref();
+    let gltf = gltf::Gltf::open(input_filename)?;
+    let buffers = gltf::import_buffers(&gltf.document, input_filename.parent(), gltf.blob)?;
+
+    let mut stl = IndexedMesh {
+        vertices: Vec::new(),
+        faces: Vec::new(),
+    };
+    let identity = [
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ];
+    let document = &gltf.document;
+    match document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        Some(scene) => {
+            for node in scene.nodes() {
+                append_gltf_node(
+                    &mut stl,
+                    node,
+                    &buffers,
+                    &identity,
+                    up_axis,
+                    options.stl_face_normals,
+                )?;
+            }
+        }
+        // Without any scene, export the meshes untransformed
+        None => {
+            for mesh in document.meshes() {
+                append_gltf_mesh(
+                    &mut stl,
+                    mesh,
+                    &buffers,
+                    &identity,
+                    up_axis,
+                    options.stl_face_normals,
+                )?;
+            }
+        }
+    }
+
+    if stl.faces.is_empty() && !options.allow_empty {
+        return Err(ConvertError::EmptyMesh);
+    }
+    for vertex in &mut stl.vertices {
+        vertex.0 = options.transform_point(vertex.0);
+    }
+    if options.fix_winding {
+        let flipped = fix_winding(&mut stl);
+        log::info!("Fixed the winding of {} triangles", flipped);
+    }
+    if options.flip_winding {
+        flip_faces(&mut stl);
+    }
+    for face in &mut stl.faces {
+        if options.flip_normals {
+            face.normal.0 = face.normal.0.map(|it| -it);
+        }
+    }
+    let offset = center_offset(
+        stl.vertices.iter().map(|it| it.0),
+        options.center,
+        options.up_axis,
+    );
+    for vertex in &mut stl.vertices {
+        vertex.0 = [0, 1, 2].map(|k| vertex.0[k] + offset[k]);
+    }
+    Ok(stl)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::MeshStats;
+
+    /// Empty directory of the temporary files of a test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("conv3d-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Write `mesh`, converted with the default options, as a GLB file of `dir`
+    fn write_glb(dir: &Path, name: &str, mesh: IndexedMesh) -> PathBuf {
+        let gltf = stl_to_gltf(mesh, name, ConvertOptions::default()).unwrap();
+        let path = dir.join(format!("{}.glb", name));
+        std::fs::write(&path, gltf.to_glb_bytes().unwrap()).unwrap();
+        path
+    }
+
+    /// Normal of a face from its winding
+    fn winding_normal(stl: &IndexedMesh, face: &IndexedTriangle) -> [f32; 3] {
+        let [a, b, c] = face.vertices.map(|vi| stl.vertices[vi].0);
+        normalize(cross(sub(b, a), sub(c, a)))
+    }
+
+    #[test]
+    fn flip_faces_twice() {
+        let cube = crate::geometry::tests::indexed_cube();
+        let mut flipped = cube.clone();
+        flip_faces(&mut flipped);
+        for face in &flipped.faces {
+            assert_eq!(face.normal.0, winding_normal(&flipped, face));
+        }
+        flip_faces(&mut flipped);
+        for (face, original) in flipped.faces.iter().zip(&cube.faces) {
+            assert_eq!(face.vertices, original.vertices);
+            assert_eq!(face.normal.0, original.normal.0);
+        }
+    }
+
+    #[test]
+    fn gltf_to_stl_flip_winding() {
+        let dir = temp_dir("flip-winding");
+        let path = write_glb(&dir, "cube", crate::geometry::tests::indexed_cube());
+        let options = ConvertOptions::builder()
+            .flip_winding(true)
+            .build()
+            .unwrap();
+        let stl = gltf_to_stl(&path, options).unwrap();
+        assert_eq!(stl.faces.len(), 12);
+        for face in &stl.faces {
+            let normal = winding_normal(&stl, face);
+            assert!(sub(face.normal.0, normal).iter().all(|it| it.abs() < 1e-6));
+        }
+        // Inside out
+        assert!(MeshStats::from_indexed_mesh(&stl).volume < 0.0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Values of a float VEC3 accessor of a GLB, applying its sparse values
+    fn read_vec3(glb: &[u8], accessor: usize) -> Vec<[f32; 3]> {
+        let gltf = gltf::Gltf::from_slice(glb).unwrap();
+        let blob = gltf.blob.clone().unwrap_or_default();
+        let accessor = gltf.document.accessors().nth(accessor).unwrap();
+        gltf::accessor::Iter::<[f32; 3]>::new(accessor, |_| Some(&blob[..]))
+            .unwrap()
+            .collect()
+    }
+
+    #[test]
+    fn sparse_normals_match_dense() {
+        let positions = (0..20)
+            .map(|i| V3 {
+                v: [i as f32, 0.0, 0.0],
+            })
+            .collect::<Vec<_>>();
+        // 90% of the normals are the modal one
+        let normals = (0..20)
+            .map(|i| V3 {
+                v: if i % 10 == 3 {
+                    [1.0, 0.0, 0.0]
+                } else {
+                    [0.0, 0.0, 1.0]
+                },
+            })
+            .collect::<Vec<_>>();
+        let mut gltf = GltfBuilder::new();
+        let (_, accessor) = push_separate_vertices(
+            &mut gltf,
+            positions,
+            Some(normals.clone()),
+            None,
+            false,
+            true,
+        )
+        .unwrap();
+        let accessor = accessor.unwrap().value();
+        let glb = gltf.to_glb_bytes().unwrap();
+
+        let document = gltf::Gltf::from_slice(&glb).unwrap().document;
+        let json = document.accessors().nth(accessor).unwrap();
+        assert!(json.view().is_some());
+        assert_eq!(json.sparse().unwrap().count(), 2);
+        let dense = normals.iter().map(|it| it.v).collect::<Vec<_>>();
+        assert_eq!(read_vec3(&glb, accessor), dense);
+    }
+
+    #[test]
+    fn variant_materials() {
+        let variant = |name: &str, base_color| crate::MaterialVariant {
+            name: name.to_string(),
+            base_color,
+        };
+        let options = ConvertOptions::builder()
+            .material(Some(MaterialOptions {
+                roughness: 0.5,
+                ..Default::default()
+            }))
+            .variants(vec![
+                variant("red", [1.0, 0.0, 0.0, 1.0]),
+                variant("blue", [0.0, 0.0, 1.0, 1.0]),
+            ])
+            .build()
+            .unwrap();
+        let mut gltf = GltfBuilder::new();
+        let variants = push_variant_materials(&mut gltf, &options, None).unwrap();
+        assert_eq!(
+            variants,
+            [(0, json::Index::new(0)), (1, json::Index::new(1))]
+        );
+
+        let root: json::Root = json::deserialize::from_str(&gltf.to_json().unwrap()).unwrap();
+        let names = root.materials.iter().map(|it| it.name.as_deref());
+        assert!(names.eq([Some("red"), Some("blue")]));
+        let blue = &root.materials[1].pbr_metallic_roughness;
+        assert_eq!(blue.base_color_factor.0, [0.0, 0.0, 1.0, 1.0]);
+        // The other parameters come from the material of the options
+        assert_eq!(blue.roughness_factor.0, 0.5);
+        let root_variants = root.extensions.unwrap().khr_materials_variants.unwrap();
+        let names = root_variants.variants.iter().map(|it| it.name.as_str());
+        assert!(names.eq(["red", "blue"]));
+    }
+
+    #[test]
+    fn split_cube_creases() {
+        let cube = crate::geometry::tests::indexed_cube();
+        // Each corner gets one vertex per adjacent side
+        let (split, normals, origins) = split_creases(&cube, NormalMode::Angle, 30.0);
+        assert_eq!(split.vertices.len(), 24);
+        assert_eq!(normals.len(), 24);
+        assert_eq!(origins.len(), 24);
+        for (vertex, &origin) in split.vertices.iter().zip(&origins) {
+            assert_eq!(vertex.0, cube.vertices[origin].0);
+        }
+        for n in &normals {
+            let mut n = n.v.map(f32::abs);
+            n.sort_by(f32::total_cmp);
+            assert!(n[0] < 1e-6 && n[1] < 1e-6 && (n[2] - 1.0).abs() < 1e-6);
+        }
+        // Smooth: the corners stay shared
+        let (split, _, _) = split_creases(&cube, NormalMode::Angle, 180.0);
+        assert_eq!(split.vertices.len(), 8);
+    }
+
+    #[test]
+    fn isolated_vertex_normals() {
+        let mut stl = crate::geometry::tests::indexed_cube();
+        stl.vertices.push(stl_io::Vertex::new([5.0, 5.0, 5.0]));
+        // Two opposite faces: the normals of their vertices cancel out
+        let first = stl.vertices.len();
+        for p in [[0.0, 0.0, 3.0], [1.0, 0.0, 3.0], [0.0, 1.0, 3.0]] {
+            stl.vertices.push(stl_io::Vertex::new(p));
+        }
+        for vertices in [[first, first + 1, first + 2], [first, first + 2, first + 1]] {
+            stl.faces.push(IndexedTriangle {
+                normal: stl_io::Normal::new([0.0; 3]),
+                vertices,
+            });
+        }
+        for mode in [NormalMode::Simple, NormalMode::Area, NormalMode::Angle] {
+            let normals = compute_vertex_normals(&stl, mode);
+            assert_eq!(normals.len(), stl.vertices.len());
+            for n in &normals {
+                assert!(n.v.iter().all(|it| it.is_finite()), "{:?}", mode);
+                assert!((dot(n.v, n.v) - 1.0).abs() < 1e-5, "{:?}", mode);
+            }
+        }
+    }
+
+    #[test]
+    fn weighted_fan_normal() {
+        // Two faces around the origin:
+        // +z with an area of 1 and an angle of 90°, +y with an area of 0.5 and an angle of
+        // atan(2) at the origin
+        let stl = IndexedMesh {
+            vertices: [
+                [0.0, 0.0, 0.0],
+                [2.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, 0.0, 1.0],
+                [1.0, 0.0, 0.5],
+            ]
+            .map(stl_io::Vertex::new)
+            .to_vec(),
+            faces: vec![
+                IndexedTriangle {
+                    normal: stl_io::Normal::new([0.0, 0.0, 1.0]),
+                    vertices: [0, 1, 2],
+                },
+                IndexedTriangle {
+                    normal: stl_io::Normal::new([0.0, 1.0, 0.0]),
+                    vertices: [0, 3, 4],
+                },
+            ],
+        };
+        let angle = 2f32.atan();
+        for (mode, expected) in [
+            (NormalMode::Simple, [0.0, 1.0, 1.0]),
+            (NormalMode::Area, [0.0, 0.5, 1.0]),
+            (NormalMode::Angle, [0.0, angle, std::f32::consts::FRAC_PI_2]),
+        ] {
+            let normal = compute_vertex_normals(&stl, mode)[0].v;
+            let expected = normalize(expected);
+            assert!(
+                sub(normal, expected).iter().all(|it| it.abs() < 1e-6),
+                "{:?}: {:?}",
+                mode,
+                normal
+            );
+        }
+    }
+
+    /// Binary STL of `stl` in the Materialise format: red faces, and a default white color
+    fn colored_stl_bytes(stl: &IndexedMesh) -> Vec<u8> {
+        let mut data = b"COLOR=".to_vec();
+        data.extend([255; 4]);
+        data.resize(80, b' ');
+        data.extend((stl.faces.len() as u32).to_le_bytes());
+        for (i, face) in stl.faces.iter().enumerate() {
+            let corners = face.vertices.map(|vi| stl.vertices[vi].0);
+            for v in [face.normal.0].iter().chain(&corners) {
+                data.extend(v.iter().flat_map(|it| it.to_le_bytes()));
+            }
+            // The last face uses the default color
+            let attribute: u16 = if i + 1 == stl.faces.len() {
+                0x8000
+            } else {
+                0x1f
+            };
+            data.extend(attribute.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn stl_colors_to_color_0() {
+        let data = colored_stl_bytes(&crate::geometry::tests::indexed_cube());
+        let colors = read_stl_colors_bytes(&data).unwrap().unwrap();
+        assert_eq!(colors.len(), 12);
+        assert_eq!(colors[0], [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(colors[11], [1.0; 4]);
+        for indexed in [true, false] {
+            let stl = read_stl_bytes(&data).unwrap();
+            let options = ConvertOptions::builder().indexed(indexed).build().unwrap();
+            let gltf = stl_to_gltf_with_colors(stl, Some(colors.clone()), "cube", options);
+            let glb = gltf.unwrap().to_glb_bytes().unwrap();
+
+            let document = gltf::Gltf::from_slice(&glb).unwrap().document;
+            let primitive = document
+                .meshes()
+                .next()
+                .unwrap()
+                .primitives()
+                .next()
+                .unwrap();
+            let colors = primitive.get(&gltf::Semantic::Colors(0)).unwrap();
+            let positions = primitive.get(&gltf::Semantic::Positions).unwrap();
+            assert_eq!(colors.count(), positions.count());
+            assert_eq!(positions.count(), if indexed { 8 } else { 36 });
+        }
+        // One color per face
+        let stl = read_stl_bytes(&data).unwrap();
+        let options = ConvertOptions::default();
+        assert!(stl_to_gltf_with_colors(stl, Some(colors[1..].to_vec()), "cube", options).is_err());
+    }
+
+    /// Allocator keeping track of the peak of the heap memory allocated by each thread
+    struct PeakAllocator;
+
+    thread_local! {
+        /// Bytes currently allocated by the thread, and their peak
+        static ALLOCATED: std::cell::Cell<(isize, isize)> = const { std::cell::Cell::new((0, 0)) };
+    }
+
+    impl PeakAllocator {
+        fn track(delta: isize) {
+            let _ = ALLOCATED.try_with(|it| {
+                let (current, peak) = it.get();
+                it.set((current + delta, peak.max(current + delta)));
+            });
+        }
+
+        /// Peak of the bytes allocated by the thread while running `f`, above its start
+        fn peak<T>(f: impl FnOnce() -> T) -> (T, usize) {
+            let start = ALLOCATED.with(|it| {
+                let (current, _) = it.get();
+                it.set((current, current));
+                current
+            });
+            let result = f();
+            let (_, peak) = ALLOCATED.with(|it| it.get());
+            (result, (peak - start) as usize)
+        }
+    }
+
+    unsafe impl std::alloc::GlobalAlloc for PeakAllocator {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            Self::track(layout.size() as isize);
+            std::alloc::System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            Self::track(-(layout.size() as isize));
+            std::alloc::System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(
+            &self,
+            ptr: *mut u8,
+            layout: std::alloc::Layout,
+            new_size: usize,
+        ) -> *mut u8 {
+            Self::track(new_size as isize - layout.size() as isize);
+            std::alloc::System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: PeakAllocator = PeakAllocator;
+
+    /// Heap peak of reading an STL file through a memory map, compared with reading it
+    #[test]
+    fn mmap_peak_memory() {
+        let dir = temp_dir("mmap-peak");
+        let path = dir.join("grid.stl");
+        let stl = grid(200);
+        let mut file = std::fs::File::create(&path).unwrap();
+        stl_io::write_stl(&mut file, triangles(&stl)).unwrap();
+        drop(file);
+        let file_len = std::fs::metadata(&path).unwrap().len() as usize;
+
+        let (read, read_peak) = PeakAllocator::peak(|| read_stl_file(&path).unwrap());
+        let (mapped, mmap_peak) = PeakAllocator::peak(|| read_stl_file_mmap(&path).unwrap());
+        let (bytes, bytes_peak) =
+            PeakAllocator::peak(|| read_stl_bytes(&std::fs::read(&path).unwrap()).unwrap());
+        // The mapped file is not on the heap: on this 4 MB file (80 000 triangles), the peak is
+        // 7.7 MB with --mmap, as when streaming the file, and 11.7 MB when it is read in memory
+        // first
+        assert!(mmap_peak <= read_peak);
+        assert!(mmap_peak + file_len <= bytes_peak);
+        assert_eq!(read.vertices.len(), mapped.vertices.len());
+        assert_eq!(bytes.faces.len(), mapped.faces.len());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// glTF JSON of the cube converted with `options`
+    fn cube_root(options: ConvertOptions) -> json::Root {
+        let cube = crate::geometry::tests::indexed_cube();
+        let gltf = stl_to_gltf(cube, "cube", options).unwrap();
+        json::deserialize::from_str(&gltf.to_json().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn options_material() {
+        assert!(cube_root(ConvertOptions::default()).materials.is_empty());
+
+        let material = MaterialOptions {
+            base_color: [0.2, 0.4, 0.6, 1.0],
+            metallic: 0.0,
+            roughness: 0.25,
+            ..Default::default()
+        };
+        let options = ConvertOptions::builder()
+            .material(Some(material))
+            .build()
+            .unwrap();
+        let root = cube_root(options);
+        assert_eq!(root.materials.len(), 1);
+        let pbr = &root.materials[0].pbr_metallic_roughness;
+        assert_eq!(pbr.base_color_factor.0, material.base_color);
+        assert_eq!(pbr.metallic_factor.0, 0.0);
+        assert_eq!(pbr.roughness_factor.0, 0.25);
+        let primitive = &root.meshes[0].primitives[0];
+        assert_eq!(primitive.material, Some(json::Index::new(0)));
+    }
+
+    #[test]
+    fn cube_uvs_in_unit_square() {
+        let cube = crate::geometry::tests::indexed_cube();
+        let positions = cube
+            .vertices
+            .iter()
+            .map(|it| V3 {
+                v: it.0.map(|x| x * 3.0 - 1.0),
+            })
+            .collect::<Vec<_>>();
+        let normals = compute_vertex_normals(&cube, NormalMode::Area);
+        for mode in [UvMode::Planar, UvMode::Box] {
+            let uvs = project_uvs(&positions, &normals, mode);
+            assert_eq!(uvs.len(), 8);
+            assert!(
+                uvs.iter().flatten().all(|it| (0.0..=1.0).contains(it)),
+                "{:?}",
+                mode
+            );
+            // The box covers the whole square
+            for k in 0..2 {
+                assert!(uvs.iter().any(|uv| uv[k] == 0.0), "{:?}", mode);
+                assert!(uvs.iter().any(|uv| uv[k] == 1.0), "{:?}", mode);
+            }
+        }
+        let options = ConvertOptions::builder()
+            .uv(Some(UvMode::Box))
+            .build()
+            .unwrap();
+        let attributes = &cube_root(options).meshes[0].primitives[0].attributes;
+        assert!(attributes.contains_key(&Valid(json::mesh::Semantic::TexCoords(0))));
+    }
+
+    #[test]
+    fn emissive_material() {
+        let options = ConvertOptions::builder()
+            .material(Some(MaterialOptions {
+                emissive: [1.0, 0.5, 0.0],
+                emissive_strength: Some(4.0),
+                ..Default::default()
+            }))
+            .build()
+            .unwrap();
+        let gltf = stl_to_gltf(crate::geometry::tests::indexed_cube(), "cube", options).unwrap();
+        let text = gltf.to_json().unwrap();
+        let value: json::Value = json::deserialize::from_str(&text).unwrap();
+        let material = &value["materials"][0];
+        assert_eq!(
+            material["emissiveFactor"],
+            json::Value::from(vec![1.0, 0.5, 0.0])
+        );
+        let strength = &material["extensions"]["KHR_materials_emissive_strength"];
+        assert_eq!(strength["emissiveStrength"], 4.0);
+        assert!(value["extensionsUsed"]
+            .as_array()
+            .unwrap()
+            .contains(&json::Value::from("KHR_materials_emissive_strength")));
+
+        let root: json::Root = json::deserialize::from_str(&text).unwrap();
+        assert_eq!(root.materials[0].emissive_factor.0, [1.0, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn unlit_without_normals() {
+        let options = ConvertOptions::builder()
+            .material(Some(MaterialOptions {
+                unlit: true,
+                ..Default::default()
+            }))
+            .no_normals(true)
+            .build()
+            .unwrap();
+        let root = cube_root(options);
+        let extensions = root.materials[0].extensions.as_ref().unwrap();
+        assert!(extensions.unlit.is_some());
+        assert!(root
+            .extensions_used
+            .contains(&"KHR_materials_unlit".to_string()));
+        let attributes = &root.meshes[0].primitives[0].attributes;
+        assert!(!attributes.contains_key(&Valid(json::mesh::Semantic::Normals)));
+        assert!(attributes.contains_key(&Valid(json::mesh::Semantic::Positions)));
+
+        // Lit by default
+        let root = cube_root(ConvertOptions::default());
+        let attributes = &root.meshes[0].primitives[0].attributes;
+        assert!(attributes.contains_key(&Valid(json::mesh::Semantic::Normals)));
+        assert!(root.extensions_used.is_empty());
+    }
+
+    /// Bounds of the POSITION accessor of the first primitive
+    fn position_bounds(root: &json::Root) -> ([f32; 3], [f32; 3]) {
+        let primitive = &root.meshes[0].primitives[0];
+        let positions = primitive.attributes[&Valid(json::mesh::Semantic::Positions)];
+        let accessor = &root.accessors[positions.value()];
+        let coords = |value: &Option<json::Value>| -> [f32; 3] {
+            let values = value.as_ref().unwrap().as_array().unwrap();
+            std::array::from_fn(|k| values[k].as_f64().unwrap() as f32)
+        };
+        (coords(&accessor.min), coords(&accessor.max))
+    }
+
+    #[test]
+    fn scale_doubles_the_bounds() {
+        let (min, max) = position_bounds(&cube_root(ConvertOptions::default()));
+        let options = ConvertOptions::builder().scale(2.0).build().unwrap();
+        let (scaled_min, scaled_max) = position_bounds(&cube_root(options));
+        for k in 0..3 {
+            assert_eq!(scaled_min[k], 2.0 * min[k]);
+            assert_eq!(scaled_max[k], 2.0 * max[k]);
+            assert_eq!(scaled_max[k] - scaled_min[k], 2.0);
+        }
+    }
+
+    #[test]
+    fn center_modes() {
+        // Cube from (2, 3, 4) to (3, 4, 5)
+        let points = || {
+            let cube = crate::geometry::tests::indexed_cube();
+            cube.vertices
+                .into_iter()
+                .map(|it| [0, 1, 2].map(|k| it.0[k] + 2.0 + k as f32))
+        };
+        assert_eq!(
+            center_offset(points(), CenterMode::None, UpAxis::Z),
+            [0.0; 3]
+        );
+        assert_eq!(
+            center_offset(points(), CenterMode::Bbox, UpAxis::Z),
+            [-2.5, -3.5, -4.5]
+        );
+        assert_eq!(
+            center_offset(points(), CenterMode::Base, UpAxis::Z),
+            [0.0, 0.0, -4.0]
+        );
+        assert_eq!(
+            center_offset(points(), CenterMode::Base, UpAxis::Y),
+            [0.0, -3.0, 0.0]
+        );
+        assert_eq!(
+            center_offset(std::iter::empty(), CenterMode::Bbox, UpAxis::Z),
+            [0.0; 3]
+        );
+
+        let translated = |center| {
+            ConvertOptions::builder()
+                .translate([2.0, 3.0, 4.0])
+                .center(center)
+                .build()
+                .unwrap()
+        };
+        let (min, max) = position_bounds(&cube_root(translated(CenterMode::Bbox)));
+        assert_eq!(min, max.map(|it| -it));
+        // On the floor of the Y-up output
+        let (min, max) = position_bounds(&cube_root(translated(CenterMode::Base)));
+        assert_eq!(min[1], 0.0);
+        assert_eq!(max[1], 1.0);
+    }
+
+    /// Triangles of an indexed mesh, for `stl_io::write_stl`
+    fn triangles(stl: &IndexedMesh) -> impl ExactSizeIterator<Item = stl_io::Triangle> + '_ {
+        stl.faces.iter().map(|face| stl_io::Triangle {
+            normal: face.normal,
+            vertices: face.vertices.map(|vi| stl.vertices[vi]),
+        })
+    }
+
+    /// ASCII STL of a mesh
+    fn ascii_stl(stl: &IndexedMesh, name: &str) -> String {
+        let mut ascii = format!("solid {}\n", name);
+        for triangle in triangles(stl) {
+            let [x, y, z] = triangle.normal.0;
+            ascii += &format!("facet normal {} {} {}\nouter loop\n", x, y, z);
+            for [x, y, z] in triangle.vertices.map(|it| it.0) {
+                ascii += &format!("vertex {} {} {}\n", x, y, z);
+            }
+            ascii += "endloop\nendfacet\n";
+        }
+        ascii + &format!("endsolid {}\n", name)
+    }
+
+    #[test]
+    fn gzipped_stl() {
+        use std::io::Write;
+        let cube = crate::geometry::tests::indexed_cube();
+        let mut binary = Vec::new();
+        stl_io::write_stl(&mut binary, triangles(&cube)).unwrap();
+        let ascii = ascii_stl(&cube, "cube");
+
+        let dir = temp_dir("gzipped-stl");
+        for (name, data) in [("binary", binary), ("ascii", ascii.into_bytes())] {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&data).unwrap();
+            let compressed = encoder.finish().unwrap();
+            assert!(is_gzip(&compressed));
+            let path = dir.join(format!("{}.stl.gz", name));
+            std::fs::write(&path, &compressed).unwrap();
+
+            for stl in [
+                read_stl_file(&path).unwrap(),
+                read_stl_file_mmap(&path).unwrap(),
+                read_stl_bytes(&compressed).unwrap(),
+            ] {
+                assert_eq!(stl.faces.len(), 12, "{}", name);
+                assert_eq!(stl.vertices.len(), 8, "{}", name);
+                let volume = MeshStats::from_indexed_mesh(&stl).volume;
+                assert!((volume - 1.0).abs() < 1e-9, "{}", name);
+            }
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn position_bounds_are_the_extremes() {
+        for indexed in [true, false] {
+            let options = ConvertOptions::builder()
+                .indexed(indexed)
+                .translate([0.5, -2.0, 1.0])
+                .build()
+                .unwrap();
+            let gltf = stl_to_gltf(grid(12), "grid", options).unwrap();
+            let glb = gltf.to_glb_bytes().unwrap();
+            let root: json::Root = json::deserialize::from_str(&gltf.to_json().unwrap()).unwrap();
+            let primitive = &root.meshes[0].primitives[0];
+            let accessor = primitive.attributes[&Valid(json::mesh::Semantic::Positions)];
+
+            let positions = read_vec3(&glb, accessor.value());
+            let mut min = [f32::MAX; 3];
+            let mut max = [f32::MIN; 3];
+            for p in &positions {
+                for k in 0..3 {
+                    min[k] = min[k].min(p[k]);
+                    max[k] = max[k].max(p[k]);
+                }
+            }
+            assert_eq!(position_bounds(&root), (min, max), "indexed: {}", indexed);
+        }
+    }
+
+    /// Values of the float VEC3 attribute of the first primitive of a GLB
+    fn read_attribute(glb: &[u8], semantic: gltf::Semantic) -> Vec<[f32; 3]> {
+        let document = gltf::Gltf::from_slice(glb).unwrap().document;
+        let primitive = document
+            .meshes()
+            .next()
+            .unwrap()
+            .primitives()
+            .next()
+            .unwrap();
+        read_vec3(glb, primitive.get(&semantic).unwrap().index())
+    }
+
+    #[test]
+    fn flat_shading_uses_the_face_normals() {
+        let mut stl = grid(6);
+        // Stored normals off the winding and not normalized, as written by some exporters,
+        // and a missing one
+        for face in &mut stl.faces {
+            let [x, y, z] = face.normal.0;
+            face.normal = stl_io::Normal::new([2.0 * x + 0.02, 2.0 * y, 2.0 * z]);
+        }
+        stl.faces[5].normal = stl_io::Normal::new([0.0; 3]);
+        let options = ConvertOptions::builder()
+            .up_axis(UpAxis::Y)
+            .shading(ShadingMode::Flat)
+            .build()
+            .unwrap();
+        let glb = stl_to_gltf(stl.clone(), "grid", options)
+            .unwrap()
+            .to_glb_bytes()
+            .unwrap();
+        let normals = read_attribute(&glb, gltf::Semantic::Normals);
+        assert_eq!(normals.len(), 3 * stl.faces.len());
+        for (face, corners) in stl.faces.iter().zip(normals.chunks(3)) {
+            let normal = match normalize(face.normal.0) {
+                [0.0, 0.0, 0.0] => winding_normal(&stl, face),
+                normal => normal,
+            };
+            assert_eq!(corners, [normal; 3]);
+        }
+    }
+
+    #[test]
+    fn raw_stl_normals() {
+        let mut stl = grid(4);
+        // Kept as is, even when they are not unit or disagree with the winding
+        for (i, face) in stl.faces.iter_mut().enumerate() {
+            face.normal = stl_io::Normal::new([i as f32, -2.0, 0.5]);
+        }
+        let options = ConvertOptions::builder()
+            .up_axis(UpAxis::Y)
+            .stl_normals(true)
+            .build()
+            .unwrap();
+        let glb = stl_to_gltf(stl.clone(), "grid", options)
+            .unwrap()
+            .to_glb_bytes()
+            .unwrap();
+        let normals = read_attribute(&glb, gltf::Semantic::Normals);
+        assert_eq!(normals.len(), 3 * stl.faces.len());
+        for (face, corners) in stl.faces.iter().zip(normals.chunks(3)) {
+            assert_eq!(corners, [face.normal.0; 3]);
+        }
+    }
+
+    #[test]
+    fn obj_group_materials() {
+        let dir = temp_dir("obj-groups");
+        let path = dir.join("parts.obj");
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\n\
+                   g lid\nf 1 2 3\n\
+                   g base\nf 1 3 4\nf 1 4 2\n";
+        std::fs::write(&path, obj).unwrap();
+        let options = ConvertOptions::builder()
+            .group_materials(true)
+            .build()
+            .unwrap();
+        let gltf = obj_to_gltf(&path, "parts", options).unwrap();
+        let root: json::Root = json::deserialize::from_str(&gltf.to_json().unwrap()).unwrap();
+
+        let names = root.materials.iter().map(|it| it.name.as_deref());
+        assert!(names.eq([Some("lid"), Some("base")]));
+        let colors = root
+            .materials
+            .iter()
+            .map(|it| it.pbr_metallic_roughness.base_color_factor.0);
+        assert!(colors.eq(GROUP_PALETTE[..2].iter().copied()));
+        let primitives = &root.meshes[0].primitives;
+        assert_eq!(primitives.len(), 2);
+        let materials = primitives.iter().map(|it| it.material);
+        assert!(materials.eq([Some(json::Index::new(0)), Some(json::Index::new(1))]));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn empty_mesh() {
+        let mut binary = Vec::new();
+        stl_io::write_stl(&mut binary, std::iter::empty::<stl_io::Triangle>()).unwrap();
+        let empty = read_stl_bytes(&binary).unwrap();
+        assert!(empty.faces.is_empty());
+        // Only a degenerate triangle
+        let mut degenerate = crate::geometry::tests::indexed_cube();
+        degenerate.faces.truncate(1);
+        degenerate.faces[0].vertices = [0, 1, 1];
+
+        for stl in [empty, degenerate] {
+            let result = stl_to_gltf(stl.clone(), "part", ConvertOptions::default());
+            assert!(matches!(result, Err(ConvertError::EmptyMesh)));
+
+            let options = ConvertOptions::builder().allow_empty(true).build().unwrap();
+            let gltf = stl_to_gltf(stl, "part", options).unwrap();
+            assert_eq!(gltf.mesh_count(), 0);
+            let glb = gltf.to_glb_bytes().unwrap();
+            let document = gltf::Gltf::from_slice(&glb).unwrap().document;
+            let scene = document.default_scene().unwrap();
+            let nodes = scene.nodes().collect::<Vec<_>>();
+            assert_eq!(nodes.len(), 1);
+            assert_eq!(nodes[0].name(), Some("part"));
+            assert!(nodes[0].mesh().is_none());
+        }
+    }
+
+    /// Component type and view length of the index accessor of the first primitive
+    fn index_layout(root: &json::Root) -> (json::accessor::ComponentType, u64) {
+        let indices = root.meshes[0].primitives[0].indices.unwrap();
+        let accessor = &root.accessors[indices.value()];
+        let view = &root.buffer_views[accessor.buffer_view.unwrap().value()];
+        let Valid(component_type) = accessor.component_type else {
+            panic!("invalid component type");
+        };
+        (component_type.0, view.byte_length.0)
+    }
+
+    #[test]
+    fn index_types() {
+        use json::accessor::ComponentType::{U16, U32};
+        let index_type = |index_type| {
+            ConvertOptions::builder()
+                .index_type(index_type)
+                .build()
+                .unwrap()
+        };
+        assert_eq!(
+            index_layout(&cube_root(index_type(IndexType::Auto))),
+            (U16, 72)
+        );
+        assert_eq!(
+            index_layout(&cube_root(index_type(IndexType::U32))),
+            (U32, 144)
+        );
+        assert_eq!(
+            index_layout(&cube_root(index_type(IndexType::U16))),
+            (U16, 72)
+        );
+
+        // 257 x 257 vertices do not fit u16 indices
+        let gltf = stl_to_gltf(grid(256), "grid", index_type(IndexType::Auto)).unwrap();
+        let root = json::deserialize::from_str(&gltf.to_json().unwrap()).unwrap();
+        assert_eq!(index_layout(&root), (U32, 256 * 256 * 6 * 4));
+    }
+
+    #[test]
+    fn drop_collinear_triangle() {
+        let mut stl = crate::geometry::tests::indexed_cube();
+        // Along the edge from (0, 0, 0) to (1, 0, 0)
+        stl.vertices.push(stl_io::Vertex::new([2.0, 0.0, 0.0]));
+        stl.faces.push(IndexedTriangle {
+            normal: stl_io::Normal::new([0.0; 3]),
+            vertices: [0, 1, 8],
+        });
+        let options = ConvertOptions::builder()
+            .drop_degenerate(true)
+            .build()
+            .unwrap();
+        let glb = stl_to_gltf(stl, "cube", options)
+            .unwrap()
+            .to_glb_bytes()
+            .unwrap();
+        let document = gltf::Gltf::from_slice(&glb).unwrap().document;
+        let primitive = document
+            .meshes()
+            .next()
+            .unwrap()
+            .primitives()
+            .next()
+            .unwrap();
+        assert_eq!(primitive.indices().unwrap().count(), 36);
+        let normals = read_attribute(&glb, gltf::Semantic::Normals);
+        assert!(!normals.is_empty());
+        for n in normals {
+            assert!(n.iter().all(|it| it.is_finite()), "{:?}", n);
+            assert!((dot(n, n) - 1.0).abs() < 1e-5, "{:?}", n);
+        }
+    }
+
+    #[test]
+    fn quantized_positions_round_trip() {
+        let stl = grid(8);
+        let glb = stl_to_gltf(stl.clone(), "grid", ConvertOptions::default())
+            .unwrap()
+            .to_glb_bytes()
+            .unwrap();
+        let expected = read_attribute(&glb, gltf::Semantic::Positions);
+        for (bits, q_max) in [(Quantization::I8, 127.0), (Quantization::I16, 32767.0)] {
+            let options = ConvertOptions::builder()
+                .quantize(Some(bits))
+                .build()
+                .unwrap();
+            let glb = stl_to_gltf(stl.clone(), "grid", options)
+                .unwrap()
+                .to_glb_bytes()
+                .unwrap();
+            // The gltf crate does not know KHR_mesh_quantization
+            let gltf = gltf::Gltf::from_slice_without_validation(&glb).unwrap();
+            let blob = gltf.blob.clone().unwrap();
+            let node = gltf.document.nodes().next().unwrap();
+            let (offset, _, [scale, ..]) = node.transform().decomposed();
+            let primitive = node.mesh().unwrap().primitives().next().unwrap();
+            let accessor = primitive.get(&gltf::Semantic::Positions).unwrap();
+            assert!(accessor.normalized());
+            let quantized: Vec<[f32; 3]> = match bits {
+                Quantization::I8 => gltf::accessor::Iter::<[i8; 3]>::new(accessor, |_| Some(&blob))
+                    .unwrap()
+                    .map(|q| q.map(f32::from))
+                    .collect(),
+                Quantization::I16 => {
+                    gltf::accessor::Iter::<[i16; 3]>::new(accessor, |_| Some(&blob))
+                        .unwrap()
+                        .map(|q| q.map(f32::from))
+                        .collect()
+                }
+            };
+
+            assert_eq!(quantized.len(), expected.len());
+            // Half a quantization step, and the rounding of the floats
+            let tolerance = 0.5 * scale / q_max + 1e-5;
+            for (q, p) in quantized.iter().zip(&expected) {
+                for k in 0..3 {
+                    let dequantized = offset[k] + scale * (q[k] / q_max).max(-1.0);
+                    assert!((dequantized - p[k]).abs() <= tolerance, "{:?}", bits);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn solid_name() {
+        let cube = crate::geometry::tests::indexed_cube();
+        let dir = temp_dir("solid-name");
+        let path = dir.join("part.stl");
+        std::fs::write(&path, ascii_stl(&cube, " bracket v2 ")).unwrap();
+        let name = read_stl_solid_name(&path).unwrap().unwrap();
+        assert_eq!(name, "bracket v2");
+        let root = cube_root(ConvertOptions::default());
+        assert_eq!(root.nodes[0].name.as_deref(), Some("cube"));
+        assert_eq!(root.meshes[0].name.as_deref(), Some("cube"));
+        let gltf = stl_to_gltf(read_stl_file(&path).unwrap(), &name, Default::default());
+        let root: json::Root =
+            json::deserialize::from_str(&gltf.unwrap().to_json().unwrap()).unwrap();
+        assert_eq!(root.nodes[0].name.as_deref(), Some("bracket v2"));
+        assert_eq!(root.meshes[0].name.as_deref(), Some("bracket v2"));
+
+        // Unnamed solid
+        std::fs::write(&path, ascii_stl(&cube, "")).unwrap();
+        assert_eq!(read_stl_solid_name(&path).unwrap(), None);
+        // Binary file whose header starts with "solid"
+        let mut binary = Vec::new();
+        stl_io::write_stl(&mut binary, triangles(&cube)).unwrap();
+        binary[..12].copy_from_slice(b"solid header");
+        std::fs::write(&path, binary).unwrap();
+        assert_eq!(read_stl_solid_name(&path).unwrap(), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Wavy `n` x `n` grid of quads split in two triangles, with outward face normals
+    fn grid(n: usize) -> IndexedMesh {
+        let vertices = (0..=n)
+            .flat_map(|y| (0..=n).map(move |x| (x as f32, y as f32)))
+            .map(|(x, y)| stl_io::Vertex::new([x, y, (x * 0.3).sin() * (y * 0.2).cos()]))
+            .collect();
+        let mut stl = IndexedMesh {
+            vertices,
+            faces: (0..n)
+                .flat_map(|y| (0..n).map(move |x| y * (n + 1) + x))
+                .flat_map(|i| [[i, i + 1, i + n + 2], [i, i + n + 2, i + n + 1]])
+                .map(|vertices| IndexedTriangle {
+                    normal: stl_io::Normal::new([0.0; 3]),
+                    vertices,
+                })
+                .collect(),
+        };
+        for i in 0..stl.faces.len() {
+            stl.faces[i].normal = stl_io::Normal::new(winding_normal(&stl, &stl.faces[i]));
+        }
+        stl
+    }
+
+    /// Run `f` on a pool of `threads` threads
+    fn with_threads<T: Send>(threads: usize, f: impl FnOnce() -> T + Send) -> T {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap()
+            .install(f)
+    }
+
+    #[test]
+    fn parallel_vertex_normals_match_serial() {
+        let stl = grid(363);
+        assert!(stl.faces.len() > PARALLEL_NORMALS_MIN_FACES);
+        for mode in [NormalMode::Simple, NormalMode::Area, NormalMode::Angle] {
+            let vertex_count = stl.vertices.len();
+            let mut serial = vec![V3 { v: [0.0; 3] }; vertex_count];
+            let mut counts = vec![0; vertex_count];
+            accumulate_face_normals(&stl, &stl.faces, mode, &mut serial, &mut counts);
+            let parallel = with_threads(4, || compute_vertex_normals(&stl, mode));
+            assert_eq!(parallel.len(), vertex_count);
+            for (p, s) in parallel.iter().zip(&serial) {
+                // Only the summation order differs
+                let s = normalize(s.v);
+                assert!(sub(p.v, s).iter().all(|it| it.abs() < 1e-5), "{:?}", mode);
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_buffers_are_deterministic() {
+        let stl = grid(100);
+        for indexed in [true, false] {
+            let glb = |threads| {
+                let options = ConvertOptions::builder().indexed(indexed).build().unwrap();
+                with_threads(threads, || stl_to_gltf(stl.clone(), "grid", options))
+                    .unwrap()
+                    .to_glb_bytes()
+                    .unwrap()
+            };
+            assert!(glb(1) == glb(8), "indexed: {}", indexed);
+        }
+    }
+
+    #[test]
+    fn interleaved_layout() {
+        let positions = [[0.0, 1.0, 2.0], [3.0, 4.0, 5.0]].map(|v| V3 { v });
+        let normals = [[0.0, 0.0, 1.0], [1.0, 0.0, 0.0]].map(|v| V3 { v });
+        let mut gltf = GltfBuilder::new();
+        let (positions_accessor, normals_accessor) =
+            push_interleaved_vertices(&mut gltf, positions.to_vec(), normals.to_vec(), false)
+                .unwrap();
+        let glb = gltf.to_glb_bytes().unwrap();
+
+        let document = gltf::Gltf::from_slice(&glb).unwrap().document;
+        let accessor =
+            |index: json::Index<json::Accessor>| document.accessors().nth(index.value()).unwrap();
+        let (p, n) = (accessor(positions_accessor), accessor(normals_accessor));
+        assert_eq!(p.view().unwrap().index(), n.view().unwrap().index());
+        assert_eq!(p.view().unwrap().stride(), Some(24));
+        assert_eq!((p.offset(), n.offset()), (0, 12));
+        assert_eq!(
+            read_vec3(&glb, positions_accessor.value()),
+            positions.map(|it| it.v)
+        );
+        assert_eq!(
+            read_vec3(&glb, normals_accessor.value()),
+            normals.map(|it| it.v)
+        );
+    }
+
+    #[test]
+    fn vertex_layouts() {
+        // View, offset and count of the POSITION and NORMAL accessors
+        let attributes = |root: &json::Root| {
+            let attributes = &root.meshes[0].primitives[0].attributes;
+            [gltf::Semantic::Positions, gltf::Semantic::Normals].map(|semantic| {
+                let accessor = &root.accessors[attributes[&Valid(semantic)].value()];
+                let view = accessor.buffer_view.unwrap();
+                (
+                    view,
+                    accessor.byte_offset.unwrap_or_default().0,
+                    accessor.count.0,
+                )
+            })
+        };
+
+        let root = cube_root(ConvertOptions::default());
+        let [(positions, 0, count), (normals, 0, normals_count)] = attributes(&root) else {
+            panic!("the accessors do not start their views");
+        };
+        assert_eq!(count, normals_count);
+        assert_ne!(positions, normals);
+        for view in [positions, normals] {
+            let view = &root.buffer_views[view.value()];
+            assert_eq!(view.byte_stride.map(|it| it.0), Some(12));
+            assert_eq!(view.byte_length.0, count * 12);
+        }
+
+        let interleaved = ConvertOptions::builder()
+            .layout(VertexLayout::Interleaved)
+            .build()
+            .unwrap();
+        let root = cube_root(interleaved);
+        let [(positions, 0, count), (normals, 12, _)] = attributes(&root) else {
+            panic!("the normals do not follow the positions");
+        };
+        assert_eq!(positions, normals);
+        let view = &root.buffer_views[positions.value()];
+        assert_eq!(view.byte_stride.map(|it| it.0), Some(24));
+        assert_eq!(view.byte_length.0, count * 24);
+    }
+
+    #[test]
+    fn options_builder() {
+        let options = ConvertOptions::builder()
+            .up_axis(UpAxis::Y)
+            .indexed(false)
+            .scale(2.0)
+            .translate([1.0, 0.0, 0.0])
+            .no_normals(true)
+            .material(Some(MaterialOptions::default()))
+            .build()
+            .unwrap();
+        let root = cube_root(options);
+        assert_eq!(position_bounds(&root), ([1.0, 0.0, 0.0], [3.0, 2.0, 2.0]));
+        let primitive = &root.meshes[0].primitives[0];
+        assert!(primitive.indices.is_none());
+        assert!(!primitive
+            .attributes
+            .contains_key(&Valid(gltf::Semantic::Normals)));
+        assert_eq!(
+            root.accessors[primitive.attributes[&Valid(gltf::Semantic::Positions)].value()]
+                .count
+                .0,
+            36
+        );
+        assert_eq!(root.materials.len(), 1);
+
+        let invalid = |builder: crate::ConvertOptionsBuilder| {
+            assert!(matches!(builder.build(), Err(ConvertError::InvalidData(_))));
+        };
+        invalid(ConvertOptions::builder().scale(0.0));
+        invalid(ConvertOptions::builder().scale(f32::NAN));
+        invalid(ConvertOptions::builder().scale(f32::INFINITY));
+        invalid(ConvertOptions::builder().simplify(Some(0.0)));
+        invalid(ConvertOptions::builder().simplify(Some(1.5)));
+        let variant = |name: &str| crate::MaterialVariant {
+            name: name.to_string(),
+            base_color: [1.0; 4],
+        };
+        invalid(ConvertOptions::builder().variants(vec![variant("")]));
+        invalid(ConvertOptions::builder().variants(vec![variant("red"), variant("red")]));
+        invalid(ConvertOptions::builder().instances(Some(Vec::new())));
+        assert!(ConvertOptions::builder()
+            .simplify(Some(1.0))
+            .variants(vec![variant("red"), variant("blue")])
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn full_bounds() {
+        let root = cube_root(ConvertOptions::default());
+        let primitive = &root.meshes[0].primitives[0];
+        let indices = &root.accessors[primitive.indices.unwrap().value()];
+        assert!(indices.min.is_none() && indices.max.is_none());
+
+        let options = ConvertOptions::builder().full_bounds(true).build().unwrap();
+        let root = cube_root(options);
+        let primitive = &root.meshes[0].primitives[0];
+        let indices = &root.accessors[primitive.indices.unwrap().value()];
+        let positions =
+            &root.accessors[primitive.attributes[&Valid(gltf::Semantic::Positions)].value()];
+        let vcount = positions.count.0;
+        assert_eq!(indices.min, Some(json::Value::from(vec![0])));
+        assert_eq!(indices.max, Some(json::Value::from(vec![vcount - 1])));
+        let normals =
+            &root.accessors[primitive.attributes[&Valid(gltf::Semantic::Normals)].value()];
+        assert!(normals.min.is_some() && normals.max.is_some());
+    }
+
+    #[test]
+    fn zero_stored_normals() {
+        let mut stl = crate::geometry::tests::indexed_cube();
+        for face in &mut stl.faces {
+            face.normal = stl_io::Normal::new([0.0; 3]);
+        }
+        let modes = [NormalMode::Simple, NormalMode::Area, NormalMode::Angle]
+            .map(|normals| (normals, ShadingMode::Smooth));
+        for (normals, shading) in modes
+            .into_iter()
+            .chain([(NormalMode::Simple, ShadingMode::Flat)])
+        {
+            let options = ConvertOptions::builder()
+                .normals(normals)
+                .shading(shading)
+                .build()
+                .unwrap();
+            let gltf = stl_to_gltf(stl.clone(), "cube", options).unwrap();
+            let glb = gltf.to_glb_bytes().unwrap();
+            let normals = read_attribute(&glb, gltf::Semantic::Normals);
+            assert!(!normals.is_empty());
+            for n in normals {
+                assert!(n.iter().all(|it| it.is_finite()), "{n:?}");
+                assert!((dot(n, n) - 1.0).abs() < 1e-5, "{n:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn split_two_cubes() {
+        let mut stl = crate::geometry::tests::indexed_cube();
+        let second = crate::geometry::tests::indexed_cube();
+        let offset = stl.vertices.len();
+        stl.vertices.extend(
+            second
+                .vertices
+                .iter()
+                .map(|it| stl_io::Vertex::new([it.0[0] + 3.0, it.0[1], it.0[2]])),
+        );
+        stl.faces
+            .extend(second.faces.iter().map(|face| IndexedTriangle {
+                normal: face.normal,
+                vertices: face.vertices.map(|vi| vi + offset),
+            }));
+        let options = ConvertOptions::builder()
+            .split_components(true)
+            .build()
+            .unwrap();
+        let gltf = stl_to_gltf(stl, "cubes", options).unwrap();
+        let root: json::Root = json::deserialize::from_str(&gltf.to_json().unwrap()).unwrap();
+
+        let scene = &root.scenes[root.scene.unwrap().value()];
+        assert_eq!(scene.nodes.len(), 2);
+        for (i, node) in scene.nodes.iter().enumerate() {
+            let node = &root.nodes[node.value()];
+            assert_eq!(node.name, Some(format!("cubes_{i}")));
+            let mesh = &root.meshes[node.mesh.unwrap().value()];
+            assert_eq!(mesh.primitives.len(), 1);
+            let indices = mesh.primitives[0].indices.unwrap();
+            assert_eq!(root.accessors[indices.value()].count.0, 12 * 3);
+        }
+    }
+
+    #[test]
+    fn stl_error_messages() {
+        let parse_error = |data: &[u8]| match parse_stl(&mut std::io::Cursor::new(data)) {
+            Err(ConvertError::Parse(message)) => message,
+            result => panic!("unexpected result {result:?}"),
+        };
+        let stl = crate::geometry::tests::indexed_cube();
+
+        let mut binary = Vec::new();
+        stl_io::write_stl(&mut binary, triangles(&stl)).unwrap();
+        binary.truncate(binary.len() - 20);
+        let message = parse_error(&binary);
+        assert!(message.starts_with("looks like binary STL"), "{message}");
+        assert!(
+            message.contains("announces 12 triangles (684 bytes)"),
+            "{message}"
+        );
+        assert!(message.contains("664 bytes"), "{message}");
+
+        let ascii = ascii_stl(&stl, "cube").replacen("vertex 0 0 0", "vertex 0 zero 0", 1);
+        assert!(ascii.contains("zero"));
+        let message = parse_error(ascii.as_bytes());
+        assert!(message.starts_with("looks like ASCII STL"), "{message}");
+
+        let message = parse_error(b"not an stl");
+        assert!(message.starts_with("10 bytes, shorter than"), "{message}");
+    }
+
+    #[test]
+    fn stl_face_normal_modes() {
+        let dir = temp_dir("stl_face_normal_modes");
+        // Counterclockwise around +Z, then clockwise, with a stored normal along +X that the
+        // glTF vertex normals inherit
+        let corners = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        let triangles = IndexedMesh {
+            vertices: corners
+                .iter()
+                .chain(&corners.map(|[x, y, _]| [x, y, 2.0]))
+                .map(|&p| stl_io::Vertex::new(p))
+                .collect(),
+            faces: [[0, 1, 2], [3, 5, 4]]
+                .map(|vertices| IndexedTriangle {
+                    normal: stl_io::Normal::new([1.0, 0.0, 0.0]),
+                    vertices,
+                })
+                .to_vec(),
+        };
+        let glb = write_glb(&dir, "triangles", triangles);
+
+        let face_normals = |mode| {
+            let options = ConvertOptions::builder()
+                .stl_face_normals(mode)
+                .build()
+                .unwrap();
+            let stl = gltf_to_stl(&glb, options).unwrap();
+            stl.faces.iter().map(|it| it.normal.0).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            face_normals(StlNormalMode::Winding),
+            [[0.0, 0.0, 1.0], [0.0, 0.0, -1.0]]
+        );
+        assert_eq!(face_normals(StlNormalMode::Average), [[1.0, 0.0, 0.0]; 2]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn cube_bounding_sphere() {
+        let options = ConvertOptions::builder()
+            .bounding_sphere(true)
+            .build()
+            .unwrap();
+        let root = cube_root(options);
+        let extras = root.nodes[0].extras.as_ref().unwrap();
+        let extras: json::Value = json::deserialize::from_str(extras.get()).unwrap();
+        let sphere = &extras["boundingSphere"];
+        let radius = sphere["radius"].as_f64().unwrap() as f32;
+        assert!((radius - 3f32.sqrt() / 2.0).abs() < 1e-5, "{radius}");
+
+        // Around the center of the cube
+        let (min, max) = position_bounds(&root);
+        let center = sphere["center"].as_array().unwrap();
+        for k in 0..3 {
+            let expected = (min[k] + max[k]) / 2.0;
+            assert!((center[k].as_f64().unwrap() as f32 - expected).abs() < 1e-5);
+        }
+    }
+}
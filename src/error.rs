@@ -0,0 +1,57 @@
+use std::fmt;
+
+/// Error returned by the conversion functions
+#[derive(Debug)]
+pub enum ConvertError {
+    /// Reading or writing a file failed
+    Io(std::io::Error),
+    /// The input file could not be parsed
+    Parse(String),
+    /// The input uses a feature that cannot be converted
+    UnsupportedFormat(String),
+    /// The input or generated data is inconsistent
+    InvalidData(String),
+    /// The output size (in bytes) exceeds the limit of the format
+    TooLarge(u64),
+    /// The mesh has no triangles, or only degenerate ones
+    EmptyMesh,
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConvertError::Io(err) => write!(f, "I/O error: {}", err),
+            ConvertError::Parse(err) => write!(f, "Parse error: {}", err),
+            ConvertError::UnsupportedFormat(err) => write!(f, "Unsupported format: {}", err),
+            ConvertError::InvalidData(err) => write!(f, "Invalid data: {}", err),
+            ConvertError::TooLarge(size) => {
+                write!(f, "Output size of {} bytes exceeds the format limit", size)
+            }
+            ConvertError::EmptyMesh => write!(f, "The mesh has no non-degenerate triangles"),
+        }
+    }
+}
+
+impl std::error::Error for ConvertError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConvertError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ConvertError {
+    fn from(err: std::io::Error) -> Self {
+        ConvertError::Io(err)
+    }
+}
+
+impl From<gltf::Error> for ConvertError {
+    fn from(err: gltf::Error) -> Self {
+        match err {
+            gltf::Error::Io(err) => ConvertError::Io(err),
+            err => ConvertError::Parse(err.to_string()),
+        }
+    }
+}
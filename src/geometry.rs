@@ -0,0 +1,1124 @@
+use std::collections::HashMap;
+
+use stl_io::{IndexedMesh, IndexedTriangle};
+
+use crate::{bvh::Bvh, gltf_builder::ToLeBytes};
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub(crate) struct V3 {
+    pub v: [f32; 3],
+}
+
+impl ToLeBytes for V3 {
+    fn write_le_bytes(&self, out: &mut Vec<u8>) {
+        self.v.write_le_bytes(out);
+    }
+}
+
+/// Calculate bounding coordinates of a list of vertices, used for the clipping distance of the model
+pub(crate) fn bounding_coords(points: &[V3]) -> ([f32; 3], [f32; 3]) {
+    let mut min = [f32::MAX, f32::MAX, f32::MAX];
+    let mut max = [f32::MIN, f32::MIN, f32::MIN];
+
+    for point in points {
+        let p = point.v;
+        for i in 0..3 {
+            min[i] = f32::min(min[i], p[i]);
+            max[i] = f32::max(max[i], p[i]);
+        }
+    }
+    (min, max)
+}
+
+/// Bounding sphere (center, radius) of a list of points, with Ritter's algorithm: a sphere
+/// around two distant points, grown to include the points outside of it. It is at most about
+/// 5% larger than the smallest bounding sphere.
+pub(crate) fn bounding_sphere(points: &[[f32; 3]]) -> ([f32; 3], f32) {
+    let Some(&first) = points.first() else {
+        return ([0.0; 3], 0.0);
+    };
+    let distance2 = |a: [f32; 3], b: [f32; 3]| dot(sub(a, b), sub(a, b));
+    let farthest = |from: [f32; 3]| {
+        points
+            .iter()
+            .copied()
+            .max_by(|&a, &b| distance2(from, a).total_cmp(&distance2(from, b)))
+            .unwrap_or(from)
+    };
+    let a = farthest(first);
+    let b = farthest(a);
+    let mut center = [0, 1, 2].map(|k| (a[k] + b[k]) / 2.0);
+    let mut radius = distance2(a, b).sqrt() / 2.0;
+    for &p in points {
+        let distance = distance2(center, p).sqrt();
+        if distance > radius {
+            // Move the center toward the point, so that the sphere just reaches it
+            let new_radius = (radius + distance) / 2.0;
+            let shift = (new_radius - radius) / distance;
+            center = [0, 1, 2].map(|k| center[k] + (p[k] - center[k]) * shift);
+            radius = new_radius;
+        }
+    }
+    (center, radius)
+}
+
+/// Rotate a Z-up vector into the Y-up frame used by glTF: (x, y, z) -> (x, z, -y)
+pub(crate) fn z_up_to_y_up(v: [f32; 3]) -> [f32; 3] {
+    [v[0], v[2], -v[1]]
+}
+
+/// Inverse of `z_up_to_y_up`: (x, y, z) -> (x, -z, y)
+pub(crate) fn y_up_to_z_up(v: [f32; 3]) -> [f32; 3] {
+    [v[0], -v[2], v[1]]
+}
+
+/// Rotate a vector by a unit quaternion (x, y, z, w)
+pub(crate) fn rotate_by_quaternion(q: [f32; 4], v: [f32; 3]) -> [f32; 3] {
+    let axis = [q[0], q[1], q[2]];
+    let t = cross(axis, v).map(|it| 2.0 * it);
+    let u = cross(axis, t);
+    [0, 1, 2].map(|k| v[k] + q[3] * t[k] + u[k])
+}
+
+pub(crate) fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub(crate) fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub(crate) fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+/// Normalize a vector, returns a zero vector if its length is zero
+pub(crate) fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let len = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if len > 0.0 {
+        [v[0] / len, v[1] / len, v[2] / len]
+    } else {
+        [0.0, 0.0, 0.0]
+    }
+}
+
+/// Angle between two vectors, in radians
+pub(crate) fn angle_between(a: [f32; 3], b: [f32; 3]) -> f32 {
+    dot(normalize(a), normalize(b)).clamp(-1.0, 1.0).acos()
+}
+
+/// Multiply two column-major 4x4 matrices
+pub(crate) fn mat4_mul(a: &[[f32; 4]; 4], b: &[[f32; 4]; 4]) -> [[f32; 4]; 4] {
+    let mut result = [[0.0; 4]; 4];
+    for (col, b_col) in b.iter().enumerate() {
+        for row in 0..4 {
+            result[col][row] = (0..4).map(|k| a[k][row] * b_col[k]).sum();
+        }
+    }
+    result
+}
+
+/// Transform a point by a column-major 4x4 matrix
+pub(crate) fn mat4_transform_point(m: &[[f32; 4]; 4], p: [f32; 3]) -> [f32; 3] {
+    let mut result = [0.0; 3];
+    for (row, r) in result.iter_mut().enumerate() {
+        *r = m[0][row] * p[0] + m[1][row] * p[1] + m[2][row] * p[2] + m[3][row];
+    }
+    result
+}
+
+/// Transform a normal by the cofactor matrix of the upper 3x3 of a column-major matrix: the
+/// inverse transpose scaled by the determinant, so that a mirrored normal keeps agreeing with
+/// the winding of its mirrored triangle. The result is not normalized.
+pub(crate) fn mat4_transform_normal(m: &[[f32; 4]; 4], n: [f32; 3]) -> [f32; 3] {
+    let [c0, c1, c2] = [0, 1, 2].map(|col| [m[col][0], m[col][1], m[col][2]]);
+    let cofactors = [cross(c1, c2), cross(c2, c0), cross(c0, c1)];
+    [0, 1, 2].map(|k| (0..3).map(|i| n[i] * cofactors[i][k]).sum())
+}
+
+/// Compute the per-vertex tangents of a triangle mesh from its texture coordinates.
+/// The tangents are orthogonal to the normals, and the w component holds the handedness of the bitangent.
+pub(crate) fn compute_tangents(
+    positions: &[V3],
+    normals: &[V3],
+    uvs: &[[f32; 2]],
+    triangles: impl Iterator<Item = [usize; 3]>,
+) -> Vec<[f32; 4]> {
+    let mut tangents = vec![[0.0f32; 3]; positions.len()];
+    let mut bitangents = vec![[0.0f32; 3]; positions.len()];
+    for [i0, i1, i2] in triangles {
+        let e1 = sub(positions[i1].v, positions[i0].v);
+        let e2 = sub(positions[i2].v, positions[i0].v);
+        let (du1, dv1) = (uvs[i1][0] - uvs[i0][0], uvs[i1][1] - uvs[i0][1]);
+        let (du2, dv2) = (uvs[i2][0] - uvs[i0][0], uvs[i2][1] - uvs[i0][1]);
+        let det = du1 * dv2 - du2 * dv1;
+        if det == 0.0 {
+            continue;
+        }
+        let r = 1.0 / det;
+        let tangent: [f32; 3] = std::array::from_fn(|k| (e1[k] * dv2 - e2[k] * dv1) * r);
+        let bitangent: [f32; 3] = std::array::from_fn(|k| (e2[k] * du1 - e1[k] * du2) * r);
+        for vi in [i0, i1, i2] {
+            for k in 0..3 {
+                tangents[vi][k] += tangent[k];
+                bitangents[vi][k] += bitangent[k];
+            }
+        }
+    }
+
+    tangents
+        .iter()
+        .zip(&bitangents)
+        .zip(normals)
+        .map(|((&t, &b), n)| {
+            let n = n.v;
+            // Gram-Schmidt orthogonalization against the normal
+            let d = dot(n, t);
+            let mut t = normalize([t[0] - n[0] * d, t[1] - n[1] * d, t[2] - n[2] * d]);
+            if t == [0.0, 0.0, 0.0] {
+                // No usable texture mapping: any direction orthogonal to the normal
+                let axis = if n[0].abs() < 0.9 {
+                    [1.0, 0.0, 0.0]
+                } else {
+                    [0.0, 1.0, 0.0]
+                };
+                t = normalize(cross(axis, n));
+            }
+            let w = if dot(cross(n, t), b) < 0.0 { -1.0 } else { 1.0 };
+            [t[0], t[1], t[2], w]
+        })
+        .collect()
+}
+
+/// Reorder the triangles to improve the reuse of the GPU post-transform vertex cache
+/// (greedy scoring from Tom Forsyth's "Linear-Speed Vertex Cache Optimisation").
+/// The triangles and their winding are kept, only their order changes.
+pub(crate) fn optimize_vertex_cache(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    const CACHE_SIZE: usize = 32;
+    let face_count = indices.len() / 3;
+
+    // Triangles using each vertex
+    let mut offsets = vec![0usize; vertex_count + 1];
+    for &vi in indices {
+        offsets[vi as usize + 1] += 1;
+    }
+    for i in 0..vertex_count {
+        offsets[i + 1] += offsets[i];
+    }
+    let mut vertex_faces = vec![0usize; indices.len()];
+    let mut fill = offsets.clone();
+    for (i, &vi) in indices.iter().enumerate() {
+        vertex_faces[fill[vi as usize]] = i / 3;
+        fill[vi as usize] += 1;
+    }
+    let mut valence = (0..vertex_count)
+        .map(|vi| offsets[vi + 1] - offsets[vi])
+        .collect::<Vec<_>>();
+
+    let vertex_score = |cache_position: Option<usize>, valence: usize| -> f32 {
+        if valence == 0 {
+            return -1.0;
+        }
+        let cache_score = match cache_position {
+            // The last triangle's vertices get a fixed score, to avoid favoring one of them
+            Some(position) if position < 3 => 0.75,
+            Some(position) => (1.0 - (position - 3) as f32 / (CACHE_SIZE - 3) as f32).powf(1.5),
+            None => 0.0,
+        };
+        cache_score + 2.0 / (valence as f32).sqrt()
+    };
+    let mut scores = (0..vertex_count)
+        .map(|vi| vertex_score(None, valence[vi]))
+        .collect::<Vec<_>>();
+    let face_score = |scores: &[f32], face: usize| -> f32 {
+        (0..3).map(|k| scores[indices[3 * face + k] as usize]).sum()
+    };
+
+    let mut emitted = vec![false; face_count];
+    let mut result = Vec::with_capacity(indices.len());
+    let mut cache = Vec::<u32>::with_capacity(CACHE_SIZE + 3);
+    let mut best_face = None;
+    let mut cursor = 0;
+    while result.len() < face_count * 3 {
+        let face = match best_face {
+            Some(face) => face,
+            None => {
+                // Nothing in the cache: continue with the next remaining triangle
+                while emitted[cursor] {
+                    cursor += 1;
+                }
+                cursor
+            }
+        };
+        emitted[face] = true;
+        let face_vertices = [
+            indices[3 * face],
+            indices[3 * face + 1],
+            indices[3 * face + 2],
+        ];
+        result.extend_from_slice(&face_vertices);
+
+        for vi in face_vertices {
+            valence[vi as usize] -= 1;
+        }
+        let mut new_cache = face_vertices.to_vec();
+        new_cache.extend(cache.iter().filter(|it| !face_vertices.contains(it)));
+        let evicted = new_cache.split_off(new_cache.len().min(CACHE_SIZE));
+        cache = new_cache;
+
+        for &vi in &evicted {
+            scores[vi as usize] = vertex_score(None, valence[vi as usize]);
+        }
+        for (position, &vi) in cache.iter().enumerate() {
+            scores[vi as usize] = vertex_score(Some(position), valence[vi as usize]);
+        }
+
+        // The best candidate is a remaining triangle using a vertex of the cache
+        best_face = None;
+        let mut best_score = f32::MIN;
+        for &vi in &cache {
+            let vi = vi as usize;
+            for &candidate in &vertex_faces[offsets[vi]..offsets[vi + 1]] {
+                if !emitted[candidate] {
+                    let score = face_score(&scores, candidate);
+                    if score > best_score {
+                        best_score = score;
+                        best_face = Some(candidate);
+                    }
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Remap of the vertices in the order of their first use by `indices`, to improve the memory
+/// locality of the vertex fetches. Unused vertices are moved to the end.
+/// `remap[old_index]` is the new index of the vertex.
+pub(crate) fn vertex_fetch_remap(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let mut remap = vec![u32::MAX; vertex_count];
+    let mut next = 0;
+    for &vi in indices {
+        if remap[vi as usize] == u32::MAX {
+            remap[vi as usize] = next;
+            next += 1;
+        }
+    }
+    for new_index in remap.iter_mut().filter(|it| **it == u32::MAX) {
+        *new_index = next;
+        next += 1;
+    }
+    remap
+}
+
+/// Reorder per-vertex data according to a remap from `vertex_fetch_remap`
+pub(crate) fn remap_vertices<T: Copy>(data: &[T], remap: &[u32]) -> Vec<T> {
+    let mut result = data.to_vec();
+    for (item, &new_index) in data.iter().zip(remap) {
+        result[new_index as usize] = *item;
+    }
+    result
+}
+
+/// Merge the vertices whose positions are equal once quantized to a grid of `precision`
+/// (bitwise equal when `precision` is 0), and remap the faces accordingly.
+/// Merged vertices keep the position of the first one. Faces are kept (in order).
+/// Returns the number of vertices removed.
+pub fn reindex_vertices(stl: &mut IndexedMesh, precision: f32) -> usize {
+    let key_of = |p: [f32; 3]| {
+        if precision > 0.0 {
+            p.map(|it| (it / precision).round() as i64)
+        } else {
+            // Adding 0 turns -0 into +0
+            p.map(|it| (it + 0.0).to_bits() as i64)
+        }
+    };
+
+    let mut keys = HashMap::<[i64; 3], usize>::with_capacity(stl.vertices.len());
+    let mut vertices = Vec::new();
+    let remap = stl
+        .vertices
+        .iter()
+        .map(|vertex| {
+            *keys.entry(key_of(vertex.0)).or_insert_with(|| {
+                vertices.push(*vertex);
+                vertices.len() - 1
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let removed = stl.vertices.len() - vertices.len();
+    stl.vertices = vertices;
+    for face in &mut stl.faces {
+        face.vertices = face.vertices.map(|vi| remap[vi]);
+    }
+    removed
+}
+
+/// Merge the vertices that are within `tolerance` of each other, and remap the faces accordingly.
+/// Welded vertices are replaced by their average position. Faces are kept (in order) even if collapsed by the welding.
+/// Returns the number of vertices removed.
+pub fn weld_vertices(stl: &mut IndexedMesh, tolerance: f32) -> usize {
+    weld_vertices_in(stl, tolerance, None)
+}
+
+/// Like [`weld_vertices`], but only the vertices of the same connected component are merged
+/// (see [`connected_components`]): separate shells closer than `tolerance` stay distinct.
+pub fn weld_vertices_within_components(stl: &mut IndexedMesh, tolerance: f32) -> usize {
+    let components = connected_components(stl);
+    weld_vertices_in(stl, tolerance, Some(&components))
+}
+
+/// Connected component of each vertex, the faces sharing a vertex being connected.
+/// The components are numbered from 0, in the order of their first vertex.
+pub fn connected_components(stl: &IndexedMesh) -> Vec<usize> {
+    // Union-find over the vertices, with path halving
+    let mut parents = (0..stl.vertices.len()).collect::<Vec<_>>();
+    fn find(parents: &mut [usize], mut vi: usize) -> usize {
+        while parents[vi] != vi {
+            parents[vi] = parents[parents[vi]];
+            vi = parents[vi];
+        }
+        vi
+    }
+    for face in &stl.faces {
+        let [a, b, c] = face.vertices.map(|vi| find(&mut parents, vi));
+        parents[b] = a;
+        parents[c] = a;
+    }
+
+    let mut labels = HashMap::new();
+    (0..stl.vertices.len())
+        .map(|vi| {
+            let root = find(&mut parents, vi);
+            let next = labels.len();
+            *labels.entry(root).or_insert(next)
+        })
+        .collect()
+}
+
+/// Make the winding of the faces consistent: from a seed face of each edge-connected shell,
+/// the neighbours whose winding disagrees across their shared edge are flipped. Each shell is
+/// then turned outward-facing (positive volume), which is only meaningful for closed shells.
+/// The normals of the flipped faces are recomputed from their new winding.
+/// Returns the number of faces flipped.
+pub fn fix_winding(stl: &mut IndexedMesh) -> usize {
+    // The first vertex at each position represents it. Adding 0 turns -0 into +0.
+    let mut firsts = HashMap::<[u32; 3], usize>::with_capacity(stl.vertices.len());
+    let id_of = stl
+        .vertices
+        .iter()
+        .enumerate()
+        .map(|(vi, p)| {
+            *firsts
+                .entry(p.0.map(|it| (it + 0.0).to_bits()))
+                .or_insert(vi)
+        })
+        .collect::<Vec<_>>();
+    let directed_edges = |face: &IndexedTriangle| {
+        let [a, b, c] = face.vertices.map(|vi| id_of[vi]);
+        [(a, b), (b, c), (c, a)]
+    };
+
+    // Faces of each edge, with whether they use it from the lower to the higher vertex
+    let mut edges =
+        HashMap::<[usize; 2], Vec<(usize, bool)>>::with_capacity(stl.faces.len() * 3 / 2);
+    for (fi, face) in stl.faces.iter().enumerate() {
+        for (a, b) in directed_edges(face) {
+            if a != b {
+                edges
+                    .entry([a.min(b), a.max(b)])
+                    .or_default()
+                    .push((fi, a < b));
+            }
+        }
+    }
+
+    let mut flipped = vec![false; stl.faces.len()];
+    let mut visited = vec![false; stl.faces.len()];
+    let mut shell = Vec::new();
+    for seed in 0..stl.faces.len() {
+        if visited[seed] {
+            continue;
+        }
+        visited[seed] = true;
+        shell.clear();
+        shell.push(seed);
+        let mut next = 0;
+        while let Some(&fi) = shell.get(next) {
+            next += 1;
+            for (a, b) in directed_edges(&stl.faces[fi]) {
+                // Only manifold edges tell which side is which
+                let Some([first, second]) = edges.get(&[a.min(b), a.max(b)]).map(Vec::as_slice)
+                else {
+                    continue;
+                };
+                let (neighbour, forward) = if first.0 == fi { *second } else { *first };
+                if visited[neighbour] {
+                    continue;
+                }
+                visited[neighbour] = true;
+                // Consistent neighbours use the shared edge in opposite directions
+                flipped[neighbour] = flipped[fi] ^ ((a < b) == forward);
+                shell.push(neighbour);
+            }
+        }
+
+        // In f64: the sums of many small terms lose too much precision in f32
+        let volume = shell
+            .iter()
+            .map(|&fi| {
+                let [a, b, c] = stl.faces[fi]
+                    .vertices
+                    .map(|vi| stl.vertices[vi].0.map(f64::from));
+                let volume = a[0] * (b[1] * c[2] - b[2] * c[1])
+                    + a[1] * (b[2] * c[0] - b[0] * c[2])
+                    + a[2] * (b[0] * c[1] - b[1] * c[0]);
+                if flipped[fi] {
+                    -volume
+                } else {
+                    volume
+                }
+            })
+            .sum::<f64>();
+        if volume < 0.0 {
+            for &fi in &shell {
+                flipped[fi] = !flipped[fi];
+            }
+        }
+    }
+
+    let mut count = 0;
+    for (face, _) in stl.faces.iter_mut().zip(&flipped).filter(|(_, &it)| it) {
+        face.vertices.swap(1, 2);
+        let [a, b, c] = face.vertices.map(|vi| stl.vertices[vi].0);
+        face.normal = stl_io::Normal::new(normalize(cross(sub(b, a), sub(c, a))));
+        count += 1;
+    }
+    count
+}
+
+/// Weld the vertices, only within the same component when `components` is given
+fn weld_vertices_in(stl: &mut IndexedMesh, tolerance: f32, components: Option<&[usize]>) -> usize {
+    if tolerance <= 0.0 || stl.vertices.is_empty() {
+        return 0;
+    }
+    let component_of = |vi: usize| components.map_or(0, |it| it[vi]);
+    let cell_of = |p: [f32; 3]| p.map(|it| (it / tolerance).floor() as i64);
+    let tolerance_sq = tolerance * tolerance;
+
+    // Each cluster is represented by the first vertex that created it
+    let mut grid = HashMap::<[i64; 3], Vec<usize>>::new();
+    let mut representatives = Vec::<[f32; 3]>::new();
+    let mut cluster_components = Vec::new();
+    let mut remap = Vec::with_capacity(stl.vertices.len());
+    for (vi, vertex) in stl.vertices.iter().enumerate() {
+        let p = vertex.0;
+        let component = component_of(vi);
+        let cell = cell_of(p);
+        let mut found = None;
+        'search: for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let neighbour = [cell[0] + dx, cell[1] + dy, cell[2] + dz];
+                    for &cluster in grid.get(&neighbour).into_iter().flatten() {
+                        let d = sub(p, representatives[cluster]);
+                        if d[0] * d[0] + d[1] * d[1] + d[2] * d[2] <= tolerance_sq
+                            && cluster_components[cluster] == component
+                        {
+                            found = Some(cluster);
+                            break 'search;
+                        }
+                    }
+                }
+            }
+        }
+        let cluster = found.unwrap_or_else(|| {
+            representatives.push(p);
+            cluster_components.push(component);
+            grid.entry(cell)
+                .or_default()
+                .push(representatives.len() - 1);
+            representatives.len() - 1
+        });
+        remap.push(cluster);
+    }
+
+    // Average the positions of the welded vertices
+    let mut sums = vec![[0.0f32; 3]; representatives.len()];
+    let mut counts = vec![0u32; representatives.len()];
+    for (vertex, &cluster) in stl.vertices.iter().zip(&remap) {
+        for (sum, value) in sums[cluster].iter_mut().zip(vertex.0) {
+            *sum += value;
+        }
+        counts[cluster] += 1;
+    }
+    let removed = stl.vertices.len() - representatives.len();
+    stl.vertices = sums
+        .iter()
+        .zip(&counts)
+        .map(|(sum, &count)| stl_io::Vertex::new(sum.map(|it| it / count as f32)))
+        .collect();
+
+    for face in &mut stl.faces {
+        face.vertices = face.vertices.map(|vi| remap[vi]);
+    }
+    removed
+}
+
+/// Size and topology of a triangle mesh
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshStats {
+    pub triangles: usize,
+    pub vertices: usize,
+    /// Bounding box of the vertices
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    /// Sum of the signed volumes of the tetrahedra between the origin and each triangle.
+    /// It is the enclosed volume of a watertight mesh, negative when its winding is inverted.
+    pub volume: f64,
+    /// Sum of the areas of the triangles
+    pub area: f64,
+    /// See [`is_watertight`]. The seams of the normals and texture coordinates are ignored.
+    pub watertight: bool,
+}
+
+impl MeshStats {
+    pub fn from_indexed_mesh(stl: &IndexedMesh) -> Self {
+        let positions = stl.vertices.iter().map(|it| it.0).collect::<Vec<_>>();
+        let triangles = stl.faces.iter().map(|it| it.vertices).collect::<Vec<_>>();
+        Self::new(&positions, &triangles)
+    }
+
+    pub(crate) fn new(positions: &[[f32; 3]], triangles: &[[usize; 3]]) -> Self {
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        for p in positions {
+            for k in 0..3 {
+                min[k] = min[k].min(p[k]);
+                max[k] = max[k].max(p[k]);
+            }
+        }
+        if positions.is_empty() {
+            min = [0.0; 3];
+            max = [0.0; 3];
+        }
+
+        // In f64: the sums of many small terms lose too much precision in f32
+        let mut volume = 0.0;
+        let mut area = 0.0;
+        for triangle in triangles {
+            let [a, b, c] = triangle.map(|vi| positions[vi].map(f64::from));
+            let ab = [0, 1, 2].map(|k| b[k] - a[k]);
+            let ac = [0, 1, 2].map(|k| c[k] - a[k]);
+            let n = [
+                ab[1] * ac[2] - ab[2] * ac[1],
+                ab[2] * ac[0] - ab[0] * ac[2],
+                ab[0] * ac[1] - ab[1] * ac[0],
+            ];
+            area += (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt() / 2.0;
+            // a . (b x c) / 6
+            volume += (a[0] * (b[1] * c[2] - b[2] * c[1])
+                + a[1] * (b[2] * c[0] - b[0] * c[2])
+                + a[2] * (b[0] * c[1] - b[1] * c[0]))
+                / 6.0;
+        }
+
+        Self {
+            triangles: triangles.len(),
+            vertices: positions.len(),
+            min,
+            max,
+            volume,
+            area,
+            watertight: manifold_report(positions, triangles).is_watertight(),
+        }
+    }
+
+    /// Dimensions of the bounding box
+    pub fn size(&self) -> [f32; 3] {
+        [0, 1, 2].map(|k| self.max[k] - self.min[k])
+    }
+}
+
+/// Number of offending edges listed in a [`ManifoldReport`]
+const MANIFOLD_EXAMPLES: usize = 8;
+
+/// Edges of a mesh that prevent it from being a closed manifold
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ManifoldReport {
+    /// Number of distinct edges
+    pub edges: usize,
+    /// Edges used by a single face (holes)
+    pub boundary_edges: usize,
+    /// Edges shared by more than two faces
+    pub non_manifold_edges: usize,
+    /// Faces using the same vertices as a previous face
+    pub duplicate_faces: usize,
+    /// A few boundary edges, as pairs of vertex indices
+    pub boundary_examples: Vec<[usize; 2]>,
+    /// A few non-manifold edges, as pairs of vertex indices
+    pub non_manifold_examples: Vec<[usize; 2]>,
+}
+
+impl ManifoldReport {
+    /// Every edge is shared by exactly two faces, without duplicate faces
+    pub fn is_watertight(&self) -> bool {
+        self.edges > 0
+            && self.boundary_edges == 0
+            && self.non_manifold_edges == 0
+            && self.duplicate_faces == 0
+    }
+}
+
+/// Check that every edge of the mesh is shared by exactly two faces.
+/// Vertices with the same position are the same vertex. The mesh is not modified.
+pub fn is_watertight(mesh: &IndexedMesh) -> ManifoldReport {
+    let positions = mesh.vertices.iter().map(|it| it.0).collect::<Vec<_>>();
+    let triangles = mesh.faces.iter().map(|it| it.vertices).collect::<Vec<_>>();
+    manifold_report(&positions, &triangles)
+}
+
+/// Ray directions tried in turn by [`InsideTest::contains`] until one is unambiguous
+const INSIDE_RAY_ATTEMPTS: u32 = 16;
+
+/// Inside/outside test of points against a closed mesh, by the parity of the number of times
+/// a ray from the point crosses the surface. The triangles are stored in a BVH: build the test
+/// once to query many points.
+pub struct InsideTest {
+    bvh: Bvh,
+}
+
+impl InsideTest {
+    pub fn new(mesh: &IndexedMesh) -> Self {
+        let triangles = mesh
+            .faces
+            .iter()
+            .map(|face| face.vertices.map(|vi| mesh.vertices[vi].0))
+            .collect();
+        Self {
+            bvh: Bvh::new(triangles),
+        }
+    }
+
+    /// Whether `p` is inside the mesh. When the ray grazes an edge, a vertex or the plane of
+    /// a triangle, its direction is jittered. The points on the surface may be either, and the
+    /// result is meaningless for meshes that are not watertight (see [`is_watertight`]).
+    pub fn contains(&self, p: [f32; 3]) -> bool {
+        let Some((min, max)) = self.bvh.bounds() else {
+            return false;
+        };
+        if (0..3).any(|k| p[k] < min[k] || p[k] > max[k]) {
+            return false;
+        }
+        for attempt in 0..INSIDE_RAY_ATTEMPTS {
+            // Directions spread over the sphere with the golden angle, none along an axis
+            let z = 1.0 - (2 * attempt + 1) as f32 / INSIDE_RAY_ATTEMPTS as f32;
+            let r = (1.0 - z * z).sqrt();
+            let phi = 0.5 + 2.399_963 * attempt as f32;
+            if let Some(count) = self.bvh.crossings(p, [r * phi.cos(), r * phi.sin(), z]) {
+                return count % 2 == 1;
+            }
+        }
+        false
+    }
+}
+
+/// Whether `p` is inside the closed mesh. See [`InsideTest`] to test several points.
+pub fn point_inside(mesh: &IndexedMesh, p: [f32; 3]) -> bool {
+    InsideTest::new(mesh).contains(p)
+}
+
+fn manifold_report(positions: &[[f32; 3]], triangles: &[[usize; 3]]) -> ManifoldReport {
+    // The first vertex at each position represents it. Adding 0 turns -0 into +0.
+    let mut firsts = HashMap::<[u32; 3], usize>::with_capacity(positions.len());
+    let id_of = positions
+        .iter()
+        .enumerate()
+        .map(|(vi, p)| *firsts.entry(p.map(|it| (it + 0.0).to_bits())).or_insert(vi))
+        .collect::<Vec<_>>();
+
+    let mut edges = HashMap::<[usize; 2], usize>::with_capacity(triangles.len() * 3 / 2);
+    let mut faces = HashMap::<[usize; 3], usize>::with_capacity(triangles.len());
+    for triangle in triangles {
+        let [a, b, c] = triangle.map(|vi| id_of[vi]);
+        for (a, b) in [(a, b), (b, c), (c, a)] {
+            *edges.entry([a.min(b), a.max(b)]).or_default() += 1;
+        }
+        let mut face = [a, b, c];
+        face.sort_unstable();
+        *faces.entry(face).or_default() += 1;
+    }
+
+    let offending = |wanted: fn(usize) -> bool| {
+        let mut found = edges
+            .iter()
+            .filter(|(_, &count)| wanted(count))
+            .map(|(edge, _)| *edge)
+            .collect::<Vec<_>>();
+        found.sort_unstable();
+        let count = found.len();
+        found.truncate(MANIFOLD_EXAMPLES);
+        (count, found)
+    };
+    let (boundary_edges, boundary_examples) = offending(|count| count == 1);
+    let (non_manifold_edges, non_manifold_examples) = offending(|count| count > 2);
+    ManifoldReport {
+        edges: edges.len(),
+        boundary_edges,
+        non_manifold_edges,
+        duplicate_faces: faces.values().map(|count| count - 1).sum(),
+        boundary_examples,
+        non_manifold_examples,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Corners of the unit cube, vertex `x + 2y + 4z` at `(x, y, z)`
+    const CUBE_CORNERS: [[f32; 3]; 8] = [
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [0.0, 0.0, 1.0],
+        [1.0, 0.0, 1.0],
+        [0.0, 1.0, 1.0],
+        [1.0, 1.0, 1.0],
+    ];
+
+    /// Outward-facing triangles of the unit cube
+    const CUBE_FACES: [[usize; 3]; 12] = [
+        [0, 2, 3],
+        [0, 3, 1],
+        [4, 5, 7],
+        [4, 7, 6],
+        [0, 1, 5],
+        [0, 5, 4],
+        [2, 6, 7],
+        [2, 7, 3],
+        [0, 4, 6],
+        [0, 6, 2],
+        [1, 3, 7],
+        [1, 7, 5],
+    ];
+
+    /// Unit cube with its 8 corners shared by the faces
+    pub(crate) fn indexed_cube() -> IndexedMesh {
+        IndexedMesh {
+            vertices: CUBE_CORNERS.map(stl_io::Vertex::new).to_vec(),
+            faces: CUBE_FACES
+                .map(|vertices| {
+                    let [a, b, c] = vertices.map(|vi| CUBE_CORNERS[vi]);
+                    IndexedTriangle {
+                        normal: stl_io::Normal::new(normalize(cross(sub(b, a), sub(c, a)))),
+                        vertices,
+                    }
+                })
+                .to_vec(),
+        }
+    }
+
+    /// Unit cube as read from an STL file: three distinct vertices per face
+    fn unindexed_cube() -> IndexedMesh {
+        let mut mesh = IndexedMesh {
+            vertices: Vec::new(),
+            faces: Vec::new(),
+        };
+        for face in CUBE_FACES {
+            let [a, b, c] = face.map(|vi| CUBE_CORNERS[vi]);
+            mesh.faces.push(IndexedTriangle {
+                normal: stl_io::Normal::new(normalize(cross(sub(b, a), sub(c, a)))),
+                vertices: [0, 1, 2].map(|k| mesh.vertices.len() + k),
+            });
+            mesh.vertices.extend([a, b, c].map(stl_io::Vertex::new));
+        }
+        mesh
+    }
+
+    #[test]
+    fn reindex_cube() {
+        let original = unindexed_cube();
+        let mut cube = original.clone();
+        assert_eq!(cube.vertices.len(), 36);
+        assert_eq!(reindex_vertices(&mut cube, 0.0), 28);
+        assert_eq!(cube.vertices.len(), 8);
+        assert_eq!(cube.faces.len(), 12);
+        assert!(is_watertight(&cube).is_watertight());
+        // Each face keeps its corners, in order
+        let corners =
+            |stl: &IndexedMesh, face: &IndexedTriangle| face.vertices.map(|vi| stl.vertices[vi].0);
+        for (face, original_face) in cube.faces.iter().zip(&original.faces) {
+            assert_eq!(corners(&cube, face), corners(&original, original_face));
+            assert_eq!(face.normal.0, original_face.normal.0);
+        }
+    }
+
+    #[test]
+    fn cube_volume_and_area() {
+        let mut cube = indexed_cube();
+        let stats = MeshStats::from_indexed_mesh(&cube);
+        assert!((stats.volume - 1.0).abs() < 1e-9);
+        assert!((stats.area - 6.0).abs() < 1e-9);
+        assert!(stats.watertight);
+        // The volume is signed by the winding
+        for face in &mut cube.faces {
+            face.vertices.swap(1, 2);
+        }
+        let stats = MeshStats::from_indexed_mesh(&cube);
+        assert!((stats.volume + 1.0).abs() < 1e-9);
+        assert!((stats.area - 6.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fix_cube_winding() {
+        let mut cube = indexed_cube();
+        for fi in [1, 4, 9] {
+            cube.faces[fi].vertices.swap(1, 2);
+        }
+        assert_eq!(fix_winding(&mut cube), 3);
+        assert!((MeshStats::from_indexed_mesh(&cube).volume - 1.0).abs() < 1e-9);
+        for (face, expected) in cube.faces.iter().zip(CUBE_FACES) {
+            let [a, b, c] = face.vertices;
+            assert!([[a, b, c], [b, c, a], [c, a, b]].contains(&expected));
+        }
+        assert_eq!(fix_winding(&mut cube), 0);
+
+        // Inside out
+        for face in &mut cube.faces {
+            face.vertices.swap(1, 2);
+        }
+        assert_eq!(fix_winding(&mut cube), 12);
+        assert!((MeshStats::from_indexed_mesh(&cube).volume - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fix_unindexed_cube_winding() {
+        // The faces are adjacent by the positions of their vertices
+        let mut cube = unindexed_cube();
+        cube.faces[5].vertices.swap(0, 1);
+        assert_eq!(fix_winding(&mut cube), 1);
+        assert!((MeshStats::from_indexed_mesh(&cube).volume - 1.0).abs() < 1e-9);
+        assert_eq!(cube.faces[5].normal.0, [0.0, -1.0, 0.0]);
+    }
+
+    #[test]
+    fn weld_cube() {
+        let mut cube = unindexed_cube();
+        for (i, vertex) in cube.vertices.iter_mut().enumerate() {
+            vertex.0[0] += (i % 3) as f32 * 1e-5;
+        }
+        assert_eq!(weld_vertices(&mut cube, 1e-3), 28);
+        assert_eq!(cube.vertices.len(), 8);
+    }
+
+    #[test]
+    fn flat_quad_tangents() {
+        // Quad on the tilted plane z = x / 2, with slightly perturbed vertex normals
+        let positions = [
+            [0.0, 0.0, 0.0],
+            [2.0, 0.0, 1.0],
+            [2.0, 1.0, 1.0],
+            [0.0, 1.0, 0.0],
+        ]
+        .map(|v| V3 { v });
+        let face_normal = normalize([-1.0, 0.0, 2.0]);
+        let normals = [0.0, 0.05, -0.05, 0.1].map(|dy| V3 {
+            v: normalize([face_normal[0], dy, face_normal[2]]),
+        });
+        let uvs = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+        let triangles = [[0, 1, 2], [0, 2, 3]];
+        let check = |tangents: &[[f32; 4]]| {
+            for (t, n) in tangents.iter().zip(&normals) {
+                let t3 = [t[0], t[1], t[2]];
+                assert!(dot(t3, n.v).abs() < 1e-6, "{:?}", t);
+                assert!((dot(t3, t3) - 1.0).abs() < 1e-5, "{:?}", t);
+                assert_eq!(t[3].abs(), 1.0);
+            }
+        };
+
+        let tangents = compute_tangents(&positions, &normals, &uvs, triangles.into_iter());
+        check(&tangents);
+        // Along the U axis
+        assert!(
+            dot(
+                [tangents[0][0], tangents[0][1], tangents[0][2]],
+                [2.0, 0.0, 1.0]
+            ) > 0.9
+        );
+        // Without a usable mapping
+        let uvs = [[0.5, 0.5]; 4];
+        check(&compute_tangents(
+            &positions,
+            &normals,
+            &uvs,
+            triangles.into_iter(),
+        ));
+    }
+
+    #[test]
+    fn optimize_keeps_the_triangles() {
+        // Triangles as sorted position triples, each rotated to start at its smallest corner so
+        // that the winding is kept
+        let triangles = |positions: &[[f32; 3]], indices: &[u32]| {
+            let mut triangles = indices
+                .chunks(3)
+                .map(|it| {
+                    let mut corners =
+                        [0, 1, 2].map(|k| positions[it[k] as usize].map(f32::to_bits));
+                    let first = (0..3).min_by_key(|&k| corners[k]).unwrap();
+                    corners.rotate_left(first);
+                    corners
+                })
+                .collect::<Vec<_>>();
+            triangles.sort();
+            triangles
+        };
+        let mut positions = CUBE_CORNERS.to_vec();
+        // Unused vertex
+        positions.insert(3, [9.0; 3]);
+        let indices = CUBE_FACES
+            .iter()
+            .flatten()
+            .map(|&vi| if vi >= 3 { vi + 1 } else { vi } as u32)
+            .collect::<Vec<_>>();
+
+        let optimized = optimize_vertex_cache(&indices, positions.len());
+        assert_eq!(optimized.len(), indices.len());
+        let remap = vertex_fetch_remap(&optimized, positions.len());
+        let remapped_positions = remap_vertices(&positions, &remap);
+        let remapped = optimized
+            .iter()
+            .map(|&vi| remap[vi as usize])
+            .collect::<Vec<_>>();
+        assert_eq!(
+            triangles(&remapped_positions, &remapped),
+            triangles(&positions, &indices)
+        );
+        // In the order of their first use, the unused vertex last
+        assert_eq!(remapped[0], 0);
+        assert_eq!(remapped_positions[8], [9.0; 3]);
+    }
+
+    #[test]
+    fn watertight_cube_and_open_plane() {
+        let report = is_watertight(&indexed_cube());
+        assert!(report.is_watertight());
+        assert_eq!(report.edges, 18);
+        // The vertices are matched by position
+        assert!(is_watertight(&unindexed_cube()).is_watertight());
+
+        // Square of two triangles: the diagonal is shared, the 4 sides are open
+        let mut plane = indexed_cube();
+        plane.faces.truncate(2);
+        let report = is_watertight(&plane);
+        assert!(!report.is_watertight());
+        assert_eq!((report.edges, report.boundary_edges), (5, 4));
+        assert_eq!(report.boundary_examples.len(), 4);
+        for edge in &report.boundary_examples {
+            assert!(edge.iter().all(|&vi| vi < 4));
+        }
+
+        // A third face on the diagonal of the bottom square
+        let mut fin = indexed_cube();
+        fin.vertices.push(stl_io::Vertex::new([0.5, 0.5, -1.0]));
+        fin.faces.push(IndexedTriangle {
+            normal: stl_io::Normal::new([0.0; 3]),
+            vertices: [0, 3, 8],
+        });
+        let report = is_watertight(&fin);
+        assert!(!report.is_watertight());
+        assert_eq!(report.non_manifold_edges, 1);
+        assert_eq!(report.non_manifold_examples, [[0, 3]]);
+        assert_eq!(report.boundary_edges, 2);
+
+        let mut duplicate = indexed_cube();
+        duplicate.faces.push(duplicate.faces[4]);
+        let report = is_watertight(&duplicate);
+        assert!(!report.is_watertight());
+        assert_eq!(report.duplicate_faces, 1);
+        assert_eq!(report.boundary_edges, 0);
+    }
+
+    #[test]
+    fn weld_keeps_near_components_apart() {
+        // Two cubes 1e-4 apart, the first with a corner split in two close vertices
+        let two_cubes = || {
+            let mut mesh = indexed_cube();
+            let second = indexed_cube();
+            mesh.vertices.extend(
+                second
+                    .vertices
+                    .iter()
+                    .map(|it| stl_io::Vertex::new([it.0[0] + 1.0001, it.0[1], it.0[2]])),
+            );
+            mesh.faces
+                .extend(second.faces.iter().map(|face| IndexedTriangle {
+                    normal: face.normal,
+                    vertices: face.vertices.map(|vi| vi + 8),
+                }));
+            mesh.vertices.push(stl_io::Vertex::new([1e-5, 0.0, 0.0]));
+            let face = mesh.faces.iter_mut().find(|it| it.vertices.contains(&0));
+            let face = face.unwrap();
+            for vi in &mut face.vertices {
+                if *vi == 0 {
+                    *vi = 16;
+                }
+            }
+            mesh
+        };
+
+        let mut mesh = two_cubes();
+        assert_eq!(weld_vertices_within_components(&mut mesh, 1e-3), 1);
+        assert_eq!(mesh.vertices.len(), 16);
+        assert_eq!(connected_components(&mesh).iter().max(), Some(&1));
+        let stats = MeshStats::from_indexed_mesh(&mesh);
+        assert!((stats.volume - 2.0).abs() < 1e-4);
+
+        // Without the components, the touching faces are welded together
+        let mut mesh = two_cubes();
+        assert_eq!(weld_vertices(&mut mesh, 1e-3), 5);
+        assert_eq!(connected_components(&mesh).iter().max(), Some(&0));
+    }
+
+    #[test]
+    fn points_inside_cube() {
+        let cube = indexed_cube();
+        let test = InsideTest::new(&cube);
+        let inside = [
+            [0.5, 0.5, 0.5],
+            [0.01, 0.01, 0.01],
+            [0.99, 0.5, 0.25],
+            [0.5, 0.5, 0.999],
+        ];
+        for p in inside {
+            assert!(test.contains(p), "{p:?}");
+            assert!(point_inside(&cube, p), "{p:?}");
+        }
+        let outside = [
+            [1.5, 0.5, 0.5],
+            [-0.01, 0.5, 0.5],
+            [0.5, 0.5, 2.0],
+            [-1.0, -1.0, -1.0],
+            [0.5, 1.001, 0.5],
+        ];
+        for p in outside {
+            assert!(!test.contains(p), "{p:?}");
+            assert!(!point_inside(&cube, p), "{p:?}");
+        }
+
+        // Nothing is inside an empty mesh
+        let empty = IndexedMesh {
+            vertices: Vec::new(),
+            faces: Vec::new(),
+        };
+        assert!(!point_inside(&empty, [0.0; 3]));
+    }
+}
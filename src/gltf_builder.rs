@@ -1,8 +1,8 @@
 use std::{
     borrow::Cow,
-    fs::OpenOptions,
+    fs::{File, OpenOptions},
     io::{BufWriter, Write},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 use gltf::{
@@ -14,25 +14,87 @@ use gltf::{
         mesh::Primitive,
         root::Get,
         texture,
-        validation::{Checked, USize64},
+        validation::{Checked, USize64, Validate},
         Accessor, Animation, Buffer, Camera, Extras, Image, Index, Material, Mesh, Node, Root,
         Scene, Skin, Texture,
     },
     Glb,
 };
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 
-#[derive(Debug, Clone, Default)]
+use crate::{
+    geometry::{bounding_sphere, mat4_mul, mat4_transform_point, MeshStats},
+    ConvertError, InstanceTransform, MaterialOptions,
+};
+
+/// Extensions the builder writes itself and that gltf-json cannot validate
+const WRITTEN_EXTENSIONS: &[&str] = &["KHR_mesh_quantization"];
+
+const GPU_INSTANCING: &str = "EXT_mesh_gpu_instancing";
+
+const MATERIALS_VARIANTS: &str = "KHR_materials_variants";
+
+/// Problem found by [`GltfBuilder::validate`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// JSON path of the invalid element, e.g. `accessors[2].count`
+    pub path: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct GltfBuilder {
     root: Root,
     blobs: Vec<Vec<u8>>,
 }
 
+impl Default for GltfBuilder {
+    /// Empty glTF, generated by `conv3d <version>`
+    fn default() -> Self {
+        let mut root = Root::default();
+        root.asset.generator = Some(format!("conv3d {}", env!("CARGO_PKG_VERSION")));
+        Self {
+            root,
+            blobs: Vec::new(),
+        }
+    }
+}
+
 impl GltfBuilder {
     /// Create a new gltf builder in binary mode
     pub fn new() -> Self {
         Default::default()
     }
 
+    /// Load a glTF or GLB file. The external, embedded and GLB buffers are read into the builder,
+    /// so they can be merged or written elsewhere. Images keep their URIs, and the asset keeps
+    /// its generator.
+    pub fn from_gltf(path: impl AsRef<Path>) -> Result<Self, ConvertError> {
+        let path = path.as_ref();
+        let gltf = gltf::Gltf::open(path)?;
+        let buffers = gltf::import_buffers(&gltf.document, path.parent(), gltf.blob)?;
+        let mut root = gltf.document.into_json();
+        let blobs = root
+            .buffers
+            .iter_mut()
+            .zip(buffers)
+            .map(|(buffer, data)| {
+                // The data is written wherever the builder is written
+                buffer.uri = None;
+                let mut blob = data.0;
+                blob.truncate(buffer.byte_length.0 as usize);
+                blob
+            })
+            .collect();
+        Ok(Self { root, blobs })
+    }
+
     #[track_caller]
     /// Push a gltf element to the builder
     pub fn push<T>(&mut self, value: T) -> Index<T>
@@ -42,13 +104,13 @@ impl GltfBuilder {
         Index::push(self.as_mut(), value)
     }
 
-    pub fn push_buffer<T>(
+    pub fn push_buffer<T: ToLeBytes>(
         &mut self,
         name: Option<String>,
         buffer: Vec<T>,
         uri: Option<String>,
     ) -> Index<Buffer> {
-        let byte_buffer = vec_to_u8_vec(buffer);
+        let byte_buffer = to_le_byte_vec(&buffer);
         let index = self.root.push(Buffer {
             byte_length: USize64::from(byte_buffer.len()),
             name,
@@ -60,18 +122,32 @@ impl GltfBuilder {
         index
     }
 
-    pub fn push_buffer_with_view<T>(
+    /// Push a buffer and a view over the whole buffer.
+    ///
+    /// `stride` is expressed in elements of `T`: the view's `byteStride` is `stride * size_of::<T>()`.
+    /// Use `Some(1)` for a vertex buffer of `T` records, and `None` for tightly packed data
+    /// that must not define a `byteStride` (e.g. indices).
+    pub fn push_buffer_with_view<T: ToLeBytes>(
         &mut self,
         name: Option<String>,
         buffer: Vec<T>,
         stride: Option<usize>,
         uri: Option<String>,
-    ) -> Index<View> {
+    ) -> Result<Index<View>, ConvertError> {
         let t_size = core::mem::size_of::<T>();
+        let byte_stride = stride.map(|stride| stride * t_size);
+        if let Some(byte_stride) = byte_stride {
+            if !(4..=252).contains(&byte_stride) || byte_stride % 4 != 0 {
+                return Err(ConvertError::InvalidData(format!(
+                    "Invalid byte stride {} (must be a multiple of 4 in 4..=252)",
+                    byte_stride
+                )));
+            }
+        }
         let buffer_length = buffer.len() * t_size;
         let buffer = self.push_buffer(None, buffer, uri);
-        let byte_stride = Some(Stride(stride.unwrap_or(1) * t_size));
-        self.push_view(View {
+        let byte_stride = byte_stride.map(Stride);
+        Ok(self.push_view(View {
             buffer,
             byte_length: USize64::from(buffer_length),
             byte_offset: None,
@@ -80,7 +156,7 @@ impl GltfBuilder {
             extras: Default::default(),
             name,
             target: Some(Checked::Valid(Target::ArrayBuffer)),
-        })
+        }))
     }
 
     // fn get_buffer_offset(&self, buffer: Index<Buffer>) -> u64 {
@@ -99,86 +175,289 @@ impl GltfBuilder {
         self.root.push(view)
     }
 
-    pub fn push_accessor_vec3(
+    /// Push an accessor of `count` elements of `element_type`, starting `offset_components`
+    /// components (not elements) into the view: the second of two interleaved float VEC3 starts
+    /// at 3. `min` and `max` hold one value per component of the elements.
+    #[allow(clippy::too_many_arguments)]
+    pub fn push_accessor(
         &mut self,
         name: Option<String>,
         buffer_view: Index<View>,
-        offset: usize,
+        offset_components: usize,
         count: usize,
-        min: Option<[f32; 3]>,
-        max: Option<[f32; 3]>,
+        component_type: json::accessor::ComponentType,
+        element_type: json::accessor::Type,
+        min: Option<json::Value>,
+        max: Option<json::Value>,
+        normalized: bool,
     ) -> Index<Accessor> {
-        let t_size = core::mem::size_of::<f32>();
         self.push(json::Accessor {
             buffer_view: Some(buffer_view),
-            byte_offset: Some(USize64::from(offset * t_size)),
+            byte_offset: Some(USize64::from(offset_components * component_type.size())),
             count: USize64::from(count),
-            component_type: Checked::Valid(json::accessor::GenericComponentType(
-                json::accessor::ComponentType::F32,
-            )),
+            component_type: Checked::Valid(json::accessor::GenericComponentType(component_type)),
             extensions: Default::default(),
             extras: Default::default(),
-            type_: Checked::Valid(json::accessor::Type::Vec3),
-            min: min.map(|min| json::Value::from(Vec::from(min))),
-            max: max.map(|max| json::Value::from(Vec::from(max))),
+            type_: Checked::Valid(element_type),
+            min,
+            max,
             name,
-            normalized: false,
+            normalized,
             sparse: None,
         })
     }
 
-    #[allow(dead_code)]
-    pub fn push_accessor_vec3_u32(
+    pub fn push_accessor_vec3(
         &mut self,
         name: Option<String>,
         buffer_view: Index<View>,
-        offset: usize,
+        offset_components: usize,
         count: usize,
+        min: Option<[f32; 3]>,
+        max: Option<[f32; 3]>,
     ) -> Index<Accessor> {
-        let t_size = core::mem::size_of::<u32>();
-        self.push(json::Accessor {
-            buffer_view: Some(buffer_view),
-            byte_offset: Some(USize64::from(offset * t_size)),
+        self.push_accessor(
+            name,
+            buffer_view,
+            offset_components,
+            count,
+            json::accessor::ComponentType::F32,
+            json::accessor::Type::Vec3,
+            min.map(|min| json::Value::from(Vec::from(min))),
+            max.map(|max| json::Value::from(Vec::from(max))),
+            false,
+        )
+    }
+
+    /// Push a float VEC3 sparse accessor: its `count` elements are those of the `base` view
+    /// (zeros without it), except at the strictly increasing `indices` where they are `values`.
+    /// The indices are stored as `U16` when `count` allows it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn push_sparse_accessor_vec3(
+        &mut self,
+        name: Option<String>,
+        base: Option<Index<View>>,
+        count: usize,
+        indices: &[u32],
+        values: Vec<[f32; 3]>,
+        min: Option<[f32; 3]>,
+        max: Option<[f32; 3]>,
+    ) -> Result<Index<Accessor>, ConvertError> {
+        if indices.is_empty() || indices.len() != values.len() {
+            return Err(ConvertError::InvalidData(
+                "A sparse accessor needs one value per index, and at least one".to_string(),
+            ));
+        }
+        use json::accessor::{sparse, ComponentType};
+        let sparse_count = indices.len();
+        let (indices_buffer, component_type) = if count <= u16::MAX as usize + 1 {
+            let indices = indices.iter().map(|&it| it as u16).collect::<Vec<_>>();
+            (self.push_buffer(None, indices, None), ComponentType::U16)
+        } else {
+            (
+                self.push_buffer(None, indices.to_vec(), None),
+                ComponentType::U32,
+            )
+        };
+        let values_buffer = self.push_buffer(None, values, None);
+        // The views of the sparse data have neither a target nor a stride
+        let mut push_view = |buffer: Index<Buffer>, byte_length: usize| {
+            self.push_view(View {
+                buffer,
+                byte_length: USize64::from(byte_length),
+                byte_offset: None,
+                byte_stride: None,
+                extensions: Default::default(),
+                extras: Default::default(),
+                name: None,
+                target: None,
+            })
+        };
+        let indices_view = push_view(indices_buffer, sparse_count * component_type.size());
+        let values_view = push_view(values_buffer, sparse_count * 12);
+        Ok(self.push(json::Accessor {
+            buffer_view: base,
+            byte_offset: base.map(|_| USize64(0)),
             count: USize64::from(count),
             component_type: Checked::Valid(json::accessor::GenericComponentType(
-                json::accessor::ComponentType::U32,
+                ComponentType::F32,
             )),
             extensions: Default::default(),
             extras: Default::default(),
             type_: Checked::Valid(json::accessor::Type::Vec3),
-            min: None,
-            max: None,
+            min: min.map(|min| json::Value::from(Vec::from(min))),
+            max: max.map(|max| json::Value::from(Vec::from(max))),
             name,
             normalized: false,
-            sparse: None,
-        })
+            sparse: Some(sparse::Sparse {
+                count: USize64::from(sparse_count),
+                indices: sparse::Indices {
+                    buffer_view: indices_view,
+                    byte_offset: USize64(0),
+                    component_type: Checked::Valid(json::accessor::IndexComponentType(
+                        component_type,
+                    )),
+                    extensions: Default::default(),
+                    extras: Default::default(),
+                },
+                values: sparse::Values {
+                    buffer_view: values_view,
+                    byte_offset: USize64(0),
+                    extensions: Default::default(),
+                    extras: Default::default(),
+                },
+                extensions: Default::default(),
+                extras: Default::default(),
+            }),
+        }))
+    }
+
+    /// Push a VEC3 accessor of normalized integers (`I8` or `I16`), as allowed by
+    /// `KHR_mesh_quantization`. `min` and `max` are the stored integer values.
+    pub fn push_accessor_vec3_normalized(
+        &mut self,
+        name: Option<String>,
+        buffer_view: Index<View>,
+        component_type: json::accessor::ComponentType,
+        count: usize,
+        min: Option<[i32; 3]>,
+        max: Option<[i32; 3]>,
+    ) -> Index<Accessor> {
+        self.push_accessor(
+            name,
+            buffer_view,
+            0,
+            count,
+            component_type,
+            json::accessor::Type::Vec3,
+            min.map(|min| json::Value::from(Vec::from(min))),
+            max.map(|max| json::Value::from(Vec::from(max))),
+            true,
+        )
+    }
+
+    pub fn push_accessor_vec2(
+        &mut self,
+        name: Option<String>,
+        buffer_view: Index<View>,
+        offset_components: usize,
+        count: usize,
+    ) -> Index<Accessor> {
+        self.push_accessor(
+            name,
+            buffer_view,
+            offset_components,
+            count,
+            json::accessor::ComponentType::F32,
+            json::accessor::Type::Vec2,
+            None,
+            None,
+            false,
+        )
+    }
+
+    pub fn push_accessor_vec4(
+        &mut self,
+        name: Option<String>,
+        buffer_view: Index<View>,
+        offset_components: usize,
+        count: usize,
+    ) -> Index<Accessor> {
+        self.push_accessor(
+            name,
+            buffer_view,
+            offset_components,
+            count,
+            json::accessor::ComponentType::F32,
+            json::accessor::Type::Vec4,
+            None,
+            None,
+            false,
+        )
+    }
+
+    pub fn push_accessor_vec3_u32(
+        &mut self,
+        name: Option<String>,
+        buffer_view: Index<View>,
+        offset_components: usize,
+        count: usize,
+    ) -> Index<Accessor> {
+        self.push_accessor(
+            name,
+            buffer_view,
+            offset_components,
+            count,
+            json::accessor::ComponentType::U32,
+            json::accessor::Type::Vec3,
+            None,
+            None,
+            false,
+        )
     }
 
-    #[allow(dead_code)]
     pub fn push_accessor_u32(
         &mut self,
         name: Option<String>,
         buffer_view: Index<View>,
-        offset: usize,
+        offset_components: usize,
         count: usize,
+        min: Option<u32>,
+        max: Option<u32>,
     ) -> Index<Accessor> {
-        let t_size = core::mem::size_of::<u32>();
-        self.push(json::Accessor {
-            buffer_view: Some(buffer_view),
-            byte_offset: Some(USize64::from(offset * t_size)),
-            count: USize64::from(count),
-            component_type: Checked::Valid(json::accessor::GenericComponentType(
-                json::accessor::ComponentType::U32,
-            )),
-            extensions: Default::default(),
-            extras: Default::default(),
-            type_: Checked::Valid(json::accessor::Type::Scalar),
-            min: None,
-            max: None,
+        self.push_accessor(
             name,
-            normalized: false,
-            sparse: None,
-        })
+            buffer_view,
+            offset_components,
+            count,
+            json::accessor::ComponentType::U32,
+            json::accessor::Type::Scalar,
+            min.map(|min| json::Value::from(vec![min])),
+            max.map(|max| json::Value::from(vec![max])),
+            false,
+        )
+    }
+
+    pub fn push_accessor_u16(
+        &mut self,
+        name: Option<String>,
+        buffer_view: Index<View>,
+        offset_components: usize,
+        count: usize,
+        min: Option<u16>,
+        max: Option<u16>,
+    ) -> Index<Accessor> {
+        self.push_accessor(
+            name,
+            buffer_view,
+            offset_components,
+            count,
+            json::accessor::ComponentType::U16,
+            json::accessor::Type::Scalar,
+            min.map(|min| json::Value::from(vec![min])),
+            max.map(|max| json::Value::from(vec![max])),
+            false,
+        )
+    }
+
+    pub fn push_accessor_f32(
+        &mut self,
+        name: Option<String>,
+        buffer_view: Index<View>,
+        offset_components: usize,
+        count: usize,
+    ) -> Index<Accessor> {
+        self.push_accessor(
+            name,
+            buffer_view,
+            offset_components,
+            count,
+            json::accessor::ComponentType::F32,
+            json::accessor::Type::Scalar,
+            None,
+            None,
+            false,
+        )
     }
 
     pub fn push_mesh(
@@ -196,6 +475,256 @@ impl GltfBuilder {
         })
     }
 
+    /// Push a mesh whose primitives each use their own material
+    pub fn push_mesh_with_materials(
+        &mut self,
+        name: Option<String>,
+        primitives: Vec<(Primitive, Option<Index<Material>>)>,
+        weights: Option<Vec<f32>>,
+    ) -> Index<Mesh> {
+        let primitives = primitives
+            .into_iter()
+            .map(|(primitive, material)| Primitive {
+                material,
+                ..primitive
+            })
+            .collect();
+        self.push_mesh(name, primitives, weights)
+    }
+
+    /// Push a PBR metallic-roughness material. A translucent `base_color` enables alpha blending.
+    /// An unlit material registers `KHR_materials_unlit`, an emissive strength
+    /// `KHR_materials_emissive_strength`.
+    pub fn push_material(
+        &mut self,
+        name: Option<String>,
+        material: &MaterialOptions,
+    ) -> Index<Material> {
+        let alpha_mode = if material.base_color[3] < 1.0 {
+            json::material::AlphaMode::Blend
+        } else {
+            json::material::AlphaMode::Opaque
+        };
+        if material.unlit {
+            self.use_extension("KHR_materials_unlit", false);
+        }
+        if material.emissive_strength.is_some() {
+            self.use_extension("KHR_materials_emissive_strength", false);
+        }
+        let extensions = (material.unlit || material.emissive_strength.is_some()).then(|| {
+            json::extensions::material::Material {
+                unlit: material
+                    .unlit
+                    .then_some(json::extensions::material::Unlit {}),
+                emissive_strength: material.emissive_strength.map(|strength| {
+                    json::extensions::material::EmissiveStrength {
+                        emissive_strength: json::extensions::material::EmissiveStrengthFactor(
+                            strength,
+                        ),
+                    }
+                }),
+                ..Default::default()
+            }
+        });
+        self.push(json::Material {
+            alpha_mode: Checked::Valid(alpha_mode),
+            name,
+            pbr_metallic_roughness: json::material::PbrMetallicRoughness {
+                base_color_factor: json::material::PbrBaseColorFactor(material.base_color),
+                metallic_factor: json::material::StrengthFactor(material.metallic),
+                roughness_factor: json::material::StrengthFactor(material.roughness),
+                ..Default::default()
+            },
+            double_sided: material.double_sided,
+            emissive_factor: json::material::EmissiveFactor(material.emissive),
+            extensions,
+            ..Default::default()
+        })
+    }
+
+    /// Set the base color texture of a material, using the `TEXCOORD_0` attribute
+    pub fn set_base_color_texture(
+        &mut self,
+        material: Index<Material>,
+        texture: Index<Texture>,
+    ) -> Result<(), ConvertError> {
+        if texture.value() >= self.root.textures.len() {
+            return Err(ConvertError::InvalidData(format!(
+                "Unable to find texture {}",
+                texture.value()
+            )));
+        }
+        let material = self
+            .root
+            .materials
+            .get_mut(material.value())
+            .ok_or_else(|| ConvertError::InvalidData("Unable to find material".to_string()))?;
+        material.pbr_metallic_roughness.base_color_texture = Some(texture::Info {
+            index: texture,
+            tex_coord: 0,
+            extensions: Default::default(),
+            extras: Default::default(),
+        });
+        Ok(())
+    }
+
+    /// Declare a material variant (`KHR_materials_variants`), e.g. a color option of a product,
+    /// and return its index. The meshes give their material in each variant with
+    /// [`GltfBuilder::set_mesh_variants`].
+    pub fn push_material_variant(&mut self, name: &str) -> u32 {
+        self.use_extension(MATERIALS_VARIANTS, false);
+        let variants = &mut self
+            .root
+            .extensions
+            .get_or_insert_with(Default::default)
+            .khr_materials_variants
+            .get_or_insert_with(Default::default)
+            .variants;
+        variants.push(json::extensions::scene::khr_materials_variants::Variant {
+            name: name.to_string(),
+        });
+        variants.len() as u32 - 1
+    }
+
+    /// Set the material of all the primitives of a mesh in each variant, given as
+    /// `(variant, material)` pairs. Without a selected variant, the primitives keep their material.
+    pub fn set_mesh_variants(
+        &mut self,
+        mesh: Index<Mesh>,
+        variants: &[(u32, Index<Material>)],
+    ) -> Result<(), ConvertError> {
+        let variant_count = self
+            .root
+            .extensions
+            .as_ref()
+            .and_then(|it| it.khr_materials_variants.as_ref())
+            .map_or(0, |it| it.variants.len());
+        if let Some((variant, _)) = variants
+            .iter()
+            .find(|(variant, _)| *variant as usize >= variant_count)
+        {
+            return Err(ConvertError::InvalidData(format!(
+                "Unable to find material variant {}",
+                variant
+            )));
+        }
+        if let Some((_, material)) = variants
+            .iter()
+            .find(|(_, material)| material.value() >= self.root.materials.len())
+        {
+            return Err(ConvertError::InvalidData(format!(
+                "Unable to find material {}",
+                material.value()
+            )));
+        }
+        let mesh = self
+            .root
+            .meshes
+            .get_mut(mesh.value())
+            .ok_or_else(|| ConvertError::InvalidData("Unable to find mesh".to_string()))?;
+        let mappings = variants
+            .iter()
+            .map(|(variant, material)| json::extensions::mesh::Mapping {
+                material: material.value() as u32,
+                variants: vec![*variant],
+            })
+            .collect::<Vec<_>>();
+        for primitive in &mut mesh.primitives {
+            primitive
+                .extensions
+                .get_or_insert_with(Default::default)
+                .khr_materials_variants = Some(json::extensions::mesh::KhrMaterialsVariants {
+                mappings: mappings.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Push a PNG or JPEG image. With `embed`, the file is stored in a buffer of the glTF.
+    /// Otherwise it is referenced by `path`, which must then be relative to the output file.
+    pub fn push_image_from_file(
+        &mut self,
+        path: &Path,
+        embed: bool,
+    ) -> Result<Index<Image>, ConvertError> {
+        let extension = path
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_lowercase();
+        let mime_type = match extension.as_str() {
+            "png" => "image/png",
+            "jpg" | "jpeg" => "image/jpeg",
+            _ => {
+                return Err(ConvertError::UnsupportedFormat(format!(
+                    "{}: images must be PNG or JPEG",
+                    path.display()
+                )))
+            }
+        };
+        let name = path.file_stem().map(|it| it.to_string_lossy().into_owned());
+
+        let (buffer_view, uri) = if embed {
+            let mut bytes = std::fs::read(path)?;
+            let byte_length = bytes.len();
+            // Keep the data of the following buffers aligned once merged
+            bytes.resize(align_to_multiple_of_four(byte_length)?, 0);
+            let buffer = self.push_buffer(None, bytes, None);
+            let view = self.push_view(View {
+                buffer,
+                byte_length: USize64::from(byte_length),
+                byte_offset: None,
+                byte_stride: None,
+                extensions: Default::default(),
+                extras: Default::default(),
+                name: name.clone(),
+                target: None,
+            });
+            (Some(view), None)
+        } else {
+            (None, Some(path.to_string_lossy().replace('\\', "/")))
+        };
+        Ok(self.push(Image {
+            buffer_view,
+            mime_type: Some(json::image::MimeType(mime_type.to_string())),
+            name,
+            uri,
+            extensions: Default::default(),
+            extras: Default::default(),
+        }))
+    }
+
+    /// Push a sampler. `None` filters let the viewer choose.
+    pub fn push_sampler(
+        &mut self,
+        mag_filter: Option<texture::MagFilter>,
+        min_filter: Option<texture::MinFilter>,
+        wrap: texture::WrappingMode,
+    ) -> Index<texture::Sampler> {
+        self.push(texture::Sampler {
+            mag_filter: mag_filter.map(Checked::Valid),
+            min_filter: min_filter.map(Checked::Valid),
+            wrap_s: Checked::Valid(wrap),
+            wrap_t: Checked::Valid(wrap),
+            ..Default::default()
+        })
+    }
+
+    /// Push a texture sampling `image`. Without sampler, the texture repeats with auto filtering.
+    pub fn push_texture(
+        &mut self,
+        image: Index<Image>,
+        sampler: Option<Index<texture::Sampler>>,
+    ) -> Index<Texture> {
+        self.push(Texture {
+            name: None,
+            sampler,
+            source: image,
+            extensions: Default::default(),
+            extras: Default::default(),
+        })
+    }
+
     pub fn push_node(&mut self, mesh: Index<Mesh>) -> Index<Node> {
         self.push(json::Node {
             mesh: Some(mesh),
@@ -203,6 +732,248 @@ impl GltfBuilder {
         })
     }
 
+    /// Push a node placing the mesh with a translation, a rotation quaternion (x, y, z, w) and a scale.
+    /// The rotation is normalized.
+    pub fn push_node_with_transform(
+        &mut self,
+        mesh: Index<Mesh>,
+        translation: [f32; 3],
+        rotation: [f32; 4],
+        scale: [f32; 3],
+    ) -> Index<Node> {
+        let len = rotation.iter().map(|it| it * it).sum::<f32>().sqrt();
+        let rotation = if len > 0.0 {
+            rotation.map(|it| it / len)
+        } else {
+            [0.0, 0.0, 0.0, 1.0]
+        };
+        self.push(json::Node {
+            mesh: Some(mesh),
+            translation: Some(translation),
+            rotation: Some(json::scene::UnitQuaternion(rotation)),
+            scale: Some(scale),
+            ..Default::default()
+        })
+    }
+
+    /// Push a node grouping the `children` nodes, with an optional mesh of its own.
+    /// Only the root nodes of a hierarchy should be listed in the scene.
+    pub fn push_node_children(
+        &mut self,
+        mesh: Option<Index<Mesh>>,
+        children: Vec<Index<Node>>,
+    ) -> Index<Node> {
+        self.push(json::Node {
+            mesh,
+            children: (!children.is_empty()).then_some(children),
+            ..Default::default()
+        })
+    }
+
+    /// Replace the children of an existing node
+    pub fn set_node_children(
+        &mut self,
+        node: Index<Node>,
+        children: Vec<Index<Node>>,
+    ) -> Result<(), ConvertError> {
+        if let Some(child) = children
+            .iter()
+            .find(|it| it.value() >= self.root.nodes.len())
+        {
+            return Err(ConvertError::InvalidData(format!(
+                "Unable to find child node {}",
+                child.value()
+            )));
+        }
+        let node = self
+            .root
+            .nodes
+            .get_mut(node.value())
+            .ok_or_else(|| ConvertError::InvalidData("Unable to find node".to_string()))?;
+        node.children = (!children.is_empty()).then_some(children);
+        Ok(())
+    }
+
+    /// Set the name of an existing node
+    pub fn set_node_name(
+        &mut self,
+        node: Index<Node>,
+        name: Option<String>,
+    ) -> Result<(), ConvertError> {
+        let node = self
+            .root
+            .nodes
+            .get_mut(node.value())
+            .ok_or_else(|| ConvertError::InvalidData("Unable to find node".to_string()))?;
+        node.name = name;
+        Ok(())
+    }
+
+    /// Bounding sphere (center, radius) of the positions of the mesh of a node, in the space
+    /// of the node. See [`GltfBuilder::set_node_bounding_sphere`].
+    pub fn node_bounding_sphere(&self, node: Index<Node>) -> Result<([f32; 3], f32), ConvertError> {
+        let mesh = self
+            .root
+            .nodes
+            .get(node.value())
+            .ok_or_else(|| ConvertError::InvalidData("Unable to find node".to_string()))?
+            .mesh
+            .ok_or_else(|| ConvertError::InvalidData("The node has no mesh".to_string()))?;
+        let identity = [0, 1, 2, 3].map(|r| [0, 1, 2, 3].map(|c| if r == c { 1.0 } else { 0.0 }));
+        let mut positions = Vec::new();
+        self.collect_mesh_triangles(mesh, &identity, &mut positions, &mut Vec::new())?;
+        Ok(bounding_sphere(&positions))
+    }
+
+    /// Store the bounding sphere of the mesh of a node in its extras, for the engines culling
+    /// with spheres: `{"boundingSphere": {"center": [x, y, z], "radius": r}}`.
+    /// The other extras of the node are kept.
+    pub fn set_node_bounding_sphere(&mut self, node: Index<Node>) -> Result<(), ConvertError> {
+        let (center, radius) = self.node_bounding_sphere(node)?;
+        let mut sphere = json::Value::Object(Default::default());
+        sphere["center"] = json::Value::from(Vec::from(center));
+        sphere["radius"] = json::Value::from(radius);
+        self.insert_node_extra(node, "boundingSphere", sphere)
+    }
+
+    /// Draw the mesh of a node once per transform, with `EXT_mesh_gpu_instancing`.
+    /// The TRANSLATION, ROTATION and SCALE accessors have one element per instance, and each
+    /// instance transform applies before the transform of the node.
+    pub fn set_node_instances(
+        &mut self,
+        node: Index<Node>,
+        instances: &[InstanceTransform],
+    ) -> Result<(), ConvertError> {
+        if node.value() >= self.root.nodes.len() {
+            return Err(ConvertError::InvalidData("Unable to find node".to_string()));
+        }
+        if instances.is_empty() {
+            return Err(ConvertError::InvalidData(
+                "GPU instancing needs at least one instance".to_string(),
+            ));
+        }
+        let count = instances.len();
+        let translations = self.push_buffer(
+            None,
+            instances.iter().map(|it| it.translation).collect(),
+            None,
+        );
+        let rotations =
+            self.push_buffer(None, instances.iter().map(|it| it.rotation).collect(), None);
+        let scales = self.push_buffer(None, instances.iter().map(|it| it.scale).collect(), None);
+        // Instance attributes are not vertex attributes: their views have no target
+        let mut push_view = |name: &str, buffer: Index<Buffer>, byte_length: usize| {
+            self.push_view(View {
+                buffer,
+                byte_length: USize64::from(byte_length),
+                byte_offset: None,
+                byte_stride: None,
+                extensions: Default::default(),
+                extras: Default::default(),
+                name: Some(name.to_string()),
+                target: None,
+            })
+        };
+        let translation_view = push_view("instance_translations", translations, count * 12);
+        let rotation_view = push_view("instance_rotations", rotations, count * 16);
+        let scale_view = push_view("instance_scales", scales, count * 12);
+
+        let mut attributes = json::Value::Object(Default::default());
+        attributes["TRANSLATION"] = json::Value::from(
+            self.push_accessor_vec3(None, translation_view, 0, count, None, None)
+                .value(),
+        );
+        attributes["ROTATION"] = json::Value::from(
+            self.push_accessor_vec4(None, rotation_view, 0, count)
+                .value(),
+        );
+        attributes["SCALE"] = json::Value::from(
+            self.push_accessor_vec3(None, scale_view, 0, count, None, None)
+                .value(),
+        );
+        let mut instancing = json::Value::Object(Default::default());
+        instancing["attributes"] = attributes;
+        self.root.nodes[node.value()]
+            .extensions
+            .get_or_insert_with(Default::default)
+            .others
+            .insert(GPU_INSTANCING.to_string(), instancing);
+        self.use_extension(GPU_INSTANCING, false);
+        Ok(())
+    }
+
+    /// Set a key of the extras of a node, which must be an object if present
+    fn insert_node_extra(
+        &mut self,
+        node: Index<Node>,
+        key: &str,
+        value: json::Value,
+    ) -> Result<(), ConvertError> {
+        let node = self
+            .root
+            .nodes
+            .get_mut(node.value())
+            .ok_or_else(|| ConvertError::InvalidData("Unable to find node".to_string()))?;
+        let mut extras = match &node.extras {
+            Some(extras) => match json::deserialize::from_str(extras.get()) {
+                Ok(extras @ json::Value::Object(_)) => extras,
+                _ => {
+                    return Err(ConvertError::InvalidData(
+                        "The extras of the node are not an object".to_string(),
+                    ))
+                }
+            },
+            None => json::Value::Object(Default::default()),
+        };
+        extras[key] = value;
+        let extras = json::serialize::to_string(&extras).map_err(serialization_error)?;
+        node.extras =
+            Some(json::extras::RawValue::from_string(extras).map_err(serialization_error)?);
+        Ok(())
+    }
+
+    /// Set the copyright of the asset
+    pub fn set_copyright(&mut self, copyright: Option<String>) {
+        self.root.asset.copyright = copyright;
+    }
+
+    pub fn set_scene_name(
+        &mut self,
+        scene: Index<Scene>,
+        name: Option<String>,
+    ) -> Result<(), ConvertError> {
+        let scene = self
+            .root
+            .scenes
+            .get_mut(scene.value())
+            .ok_or_else(|| ConvertError::InvalidData("Unable to find scene".to_string()))?;
+        scene.name = name;
+        Ok(())
+    }
+
+    /// Round the `min` and `max` of the accessors to `decimals` decimals, outward so that they
+    /// still bound the data. The other values are kept exact: rounding them would move the geometry.
+    pub fn round_accessor_bounds(&mut self, decimals: u32) {
+        let round = |value: &mut json::Value, down: bool| {
+            let json::Value::Array(values) = value else {
+                return;
+            };
+            for value in values {
+                if let Some(it) = value.as_f64() {
+                    *value = json::Value::from(round_outward(it, decimals, down));
+                }
+            }
+        };
+        for accessor in &mut self.root.accessors {
+            if let Some(min) = &mut accessor.min {
+                round(min, true);
+            }
+            if let Some(max) = &mut accessor.max {
+                round(max, false);
+            }
+        }
+    }
+
     pub fn push_scene(&mut self, nodes: Vec<Index<Node>>) -> Index<Scene> {
         self.push(json::Scene {
             extensions: Default::default(),
@@ -212,35 +983,146 @@ impl GltfBuilder {
         })
     }
 
+    /// Push a scene of the root `nodes`, named `name`. Several scenes can be pushed: the
+    /// viewers show the default one, see [`GltfBuilder::set_default_scene`].
+    pub fn push_named_scene(&mut self, name: &str, nodes: Vec<Index<Node>>) -> Index<Scene> {
+        self.push(json::Scene {
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: Some(name.to_string()),
+            nodes,
+        })
+    }
+
+    /// Declare an extension in `extensionsUsed`, and in `extensionsRequired` if `required`
+    pub fn use_extension(&mut self, name: &str, required: bool) {
+        if !self.root.extensions_used.iter().any(|it| it == name) {
+            self.root.extensions_used.push(name.to_string());
+        }
+        if required && !self.root.extensions_required.iter().any(|it| it == name) {
+            self.root.extensions_required.push(name.to_string());
+        }
+    }
+
     pub fn set_default_scene(&mut self, scene: Option<Index<Scene>>) {
         self.root.scene = scene;
     }
 
-    fn compute_glb_len(&self, json_data_size: usize) -> usize {
-        // NOTE: glb chunks must be 4-bytes aligned (padded with 0s at the end)
-        let chunk_header_size = 8; // chunk length (u32) + chunk type (u32)
-        let bin_chunk_size = chunk_header_size
-            + align_to_multiple_of_four(self.blobs.iter().map(|it| it.len()).sum::<usize>());
-        let json_chunk_size = align_to_multiple_of_four(json_data_size) + chunk_header_size;
-        let glb_header_size = 12; // magic (u32) + version (u32) + file length (u32)
-        glb_header_size + json_chunk_size + bin_chunk_size
+    /// The scene shown by the viewers: the default scene, or else the first one
+    pub fn default_scene(&self) -> Option<Index<Scene>> {
+        default_scene(&self.root)
     }
 
-    fn combine_bin_chunk(&self) -> Vec<u8> {
-        let mut result = Vec::new();
-        for blob in &self.blobs {
-            result.extend_from_slice(blob);
+    /// Check the glTF document: indices out of range, missing data, accessors or views
+    /// exceeding their buffer, and POSITION accessors without min/max.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+        let mut error =
+            |path: String, message: String| errors.push(ValidationError { path, message });
+
+        self.root
+            .validate(&self.root, json::Path::new, &mut |path, err| {
+                let path = path().to_string();
+                // gltf-json does not know the extensions the builder writes without a model for them
+                let unknown_required = err == json::validation::Error::Unsupported
+                    && path.starts_with("extensionsRequired")
+                    && WRITTEN_EXTENSIONS
+                        .iter()
+                        .any(|ext| path.ends_with(&format!("\"{ext}\"")));
+                if !unknown_required {
+                    error(path, err.to_string())
+                }
+            });
+
+        for (i, (buffer, blob)) in self.root.buffers.iter().zip(&self.blobs).enumerate() {
+            if buffer.byte_length.0 != blob.len() as u64 {
+                error(
+                    format!("buffers[{i}].byteLength"),
+                    format!(
+                        "{} bytes, the data has {}",
+                        buffer.byte_length.0,
+                        blob.len()
+                    ),
+                );
+            }
+        }
+
+        for (i, view) in self.root.buffer_views.iter().enumerate() {
+            let Some(buffer) = self.root.buffers.get(view.buffer.value()) else {
+                continue;
+            };
+            let end = view.byte_offset.map_or(0, |it| it.0) + view.byte_length.0;
+            if end > buffer.byte_length.0 {
+                error(
+                    format!("bufferViews[{i}]"),
+                    format!("ends at byte {end}, past the end of its buffer"),
+                );
+            }
+        }
+
+        for (i, accessor) in self.root.accessors.iter().enumerate() {
+            let (Some(view), Checked::Valid(component_type), Checked::Valid(type_)) = (
+                accessor
+                    .buffer_view
+                    .and_then(|it| self.root.buffer_views.get(it.value())),
+                &accessor.component_type,
+                &accessor.type_,
+            ) else {
+                continue;
+            };
+            let element_size = (component_type.0.size() * type_.multiplicity()) as u64;
+            let stride = view.byte_stride.map_or(element_size, |it| it.0 as u64);
+            let count = accessor.count.0;
+            let end = accessor.byte_offset.map_or(0, |it| it.0)
+                + if count > 0 {
+                    stride * (count - 1) + element_size
+                } else {
+                    0
+                };
+            if end > view.byte_length.0 {
+                error(
+                    format!("accessors[{i}]"),
+                    format!("ends at byte {end}, past the end of its buffer view"),
+                );
+            }
+        }
+
+        for (i, mesh) in self.root.meshes.iter().enumerate() {
+            for (j, primitive) in mesh.primitives.iter().enumerate() {
+                let position = primitive
+                    .attributes
+                    .get(&Checked::Valid(json::mesh::Semantic::Positions))
+                    .and_then(|it| self.root.accessors.get(it.value()));
+                if let Some(position) = position {
+                    if position.min.is_none() || position.max.is_none() {
+                        error(
+                            format!("meshes[{i}].primitives[{j}].attributes.POSITION"),
+                            "missing min/max".to_string(),
+                        );
+                    }
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
         }
-        result
     }
 
-    #[allow(dead_code)]
-    pub fn to_json(&self) -> String {
-        json::serialize::to_string(&self.root).expect("Serialization error")
+    /// Fails with `TooLarge` instead of wrapping when the length does not fit in a `usize`
+    fn compute_glb_len(&self, json_data_size: usize) -> Result<usize, ConvertError> {
+        // NOTE: glb chunks must be 4-bytes aligned (padded at the end)
+        glb_len(json_data_size, self.blobs.iter().map(|it| it.len()))
+    }
+
+    /// The glTF JSON, without the buffers
+    pub fn to_json(&self) -> Result<String, ConvertError> {
+        json::serialize::to_string(&self.root).map_err(serialization_error)
     }
 
     /// Set the URI of all buffers to `"{prefix}_{index}.bin"`
-    #[allow(dead_code)]
     pub fn set_buffers_uri(&mut self, prefix: &str) {
         for (i, buffer) in self.root.buffers.iter_mut().enumerate() {
             buffer.uri = Some(format!("{}_{}.bin", prefix, i));
@@ -248,80 +1130,477 @@ impl GltfBuilder {
     }
 
     /// Set the URI of a buffer to `uri`
-    pub fn set_buffer_uri(&mut self, index: usize, uri: Option<String>) -> Result<(), String> {
+    pub fn set_buffer_uri(
+        &mut self,
+        index: usize,
+        uri: Option<String>,
+    ) -> Result<(), ConvertError> {
         let buffer = self
             .root
             .buffers
             .get_mut(index)
-            .ok_or_else(|| "Unable to find buffer".to_string())?;
+            .ok_or_else(|| ConvertError::InvalidData("Unable to find buffer".to_string()))?;
         buffer.uri = uri;
         Ok(())
     }
 
-    pub fn write_all_buffers(&self, dir: impl AsRef<Path>) -> Result<(), String> {
+    /// Write the buffers that have a URI in `dir`, in parallel since each one is a distinct file.
+    /// All the buffers are attempted: the errors of the failed files are returned together.
+    pub fn write_all_buffers(&self, dir: impl AsRef<Path>) -> Result<(), ConvertError> {
         let dir = dir.as_ref();
         if !dir.is_dir() {
-            return Err("Invalid output directory.".to_string());
+            return Err(ConvertError::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Invalid output directory.",
+            )));
+        }
+        if self.blobs.len() < self.root.buffers.len() {
+            return Err(ConvertError::InvalidData(format!(
+                "Failed to get content of buffer {}",
+                self.blobs.len()
+            )));
         }
 
-        for (i, buffer) in self.root.buffers.iter().enumerate() {
-            if let Some(uri) = &buffer.uri {
-                if i >= self.blobs.len() {
-                    return Err(format!("Failed to get content of buffer {}", i));
-                }
-                let mut path = dir.to_path_buf();
-                path.push(uri);
-                println!(
-                    "Write: {} ({}KB)",
-                    path.display(),
-                    self.blobs[i].len() / 1024
-                );
+        let files = self
+            .root
+            .buffers
+            .iter()
+            .zip(&self.blobs)
+            .filter_map(|(buffer, blob)| Some((dir.join(buffer.uri.as_ref()?), blob)))
+            .collect::<Vec<_>>();
+        let results = files
+            .par_iter()
+            .map(|(path, blob)| {
                 let f = OpenOptions::new()
                     .create(true)
                     .truncate(true)
                     .write(true)
-                    .open(path)
-                    .expect("Unable to open file");
+                    .open(path)?;
                 let mut bufw = BufWriter::new(f);
-                bufw.write_all(self.blobs[i].as_slice())
-                    .expect("Unable to write to file");
+                bufw.write_all(blob)?;
+                bufw.flush()
+            })
+            .collect::<Vec<_>>();
+        // Logged once all the files are written, in the order of the buffers
+        for ((path, blob), result) in files.iter().zip(&results) {
+            if result.is_ok() {
+                log::info!("Write: {} ({}KB)", path.display(), blob.len() / 1024);
+            }
+        }
+        merge_write_errors(files.iter().map(|it| &it.0).zip(results))
+    }
+
+    /// Write `{stem}.gltf` and its buffers in `dir`: `{stem}.bin`, or `{stem}_{index}.bin`
+    /// when there are several. All the files are written to temporary paths first, then renamed
+    /// into place, the `.gltf` last: a failed write leaves no `.gltf` referencing missing buffers.
+    /// The files whose content is unchanged are not rewritten. Returns whether any file was written.
+    pub fn write_gltf_bundle(
+        &mut self,
+        dir: impl AsRef<Path>,
+        stem: &str,
+    ) -> Result<bool, ConvertError> {
+        let dir = dir.as_ref();
+        match self.buffer_count() {
+            0 => {}
+            1 => self.set_buffer_uri(0, Some(format!("{}.bin", stem)))?,
+            _ => self.set_buffers_uri(stem),
+        }
+
+        let mut gltf_json = Vec::new();
+        self.write_to_gltf(&mut gltf_json)?;
+        // File name and content of each file, the .gltf last
+        let mut files = self
+            .root
+            .buffers
+            .iter()
+            .zip(&self.blobs)
+            .filter_map(|(buffer, blob)| Some((buffer.uri.clone()?, blob.as_slice())))
+            .collect::<Vec<_>>();
+        files.push((format!("{}.gltf", stem), gltf_json.as_slice()));
+        files.retain(|(name, data)| !has_content(&dir.join(name), data));
+        if files.is_empty() {
+            return Ok(false);
+        }
+
+        let temp_paths = files
+            .iter()
+            .map(|(name, _)| dir.join(format!(".{}.{}.tmp", name, std::process::id())))
+            .collect::<Vec<_>>();
+        // Each file is distinct: they are written in parallel
+        let results = files
+            .par_iter()
+            .zip(&temp_paths)
+            .map(|((_, data), temp_path)| {
+                let mut writer = BufWriter::new(File::create(temp_path)?);
+                writer.write_all(data)?;
+                writer
+                    .into_inner()
+                    .map_err(|err| err.into_error())?
+                    .sync_all()
+            })
+            .collect::<Vec<_>>();
+        let result = merge_write_errors(temp_paths.iter().zip(results)).and_then(|()| {
+            for ((name, _), temp_path) in files.iter().zip(&temp_paths) {
+                let path = dir.join(name);
+                let result = std::fs::rename(temp_path, &path);
+                merge_write_errors(std::iter::once((&path, result)))?;
+            }
+            Ok(())
+        });
+        if result.is_err() {
+            for temp_path in &temp_paths {
+                let _ = std::fs::remove_file(temp_path);
+            }
+        }
+        result.map(|()| true)
+    }
+
+    #[allow(dead_code)]
+    fn get_buffer_offset(&self, buffer: Index<Buffer>) -> u64 {
+        self.blobs[..buffer.value()]
+            .iter()
+            .map(|it| it.len())
+            .sum::<usize>() as u64
+    }
+
+    fn compute_buffers_offsets(&self) -> Vec<usize> {
+        let mut res = Vec::new();
+        let mut accum = 0;
+        for b in self.blobs.iter() {
+            res.push(accum);
+            accum += b.len();
+        }
+        res
+    }
+
+    fn compute_buffers_len(&self) -> usize {
+        self.blobs.iter().map(|it| it.len()).sum::<usize>()
+    }
+
+    /// Total size of the binary data, in bytes
+    pub fn total_buffer_bytes(&self) -> usize {
+        self.compute_buffers_len()
+    }
+
+    pub fn buffer_count(&self) -> usize {
+        self.blobs.len()
+    }
+
+    /// Size of each buffer, in bytes
+    pub fn buffer_lengths(&self) -> Vec<usize> {
+        self.blobs.iter().map(|it| it.len()).collect()
+    }
+
+    /// Offset of each buffer once merged into a single buffer, in bytes
+    pub fn buffer_offsets(&self) -> Vec<usize> {
+        self.compute_buffers_offsets()
+    }
+
+    pub fn accessor_count(&self) -> usize {
+        self.root.accessors.len()
+    }
+
+    pub fn mesh_count(&self) -> usize {
+        self.root.meshes.len()
+    }
+
+    /// Statistics of the triangles of the default scene, placed by their nodes.
+    /// Without any scene, the meshes are used untransformed.
+    pub fn mesh_stats(&self) -> Result<MeshStats, ConvertError> {
+        let identity = gltf::scene::Transform::Decomposed {
+            translation: [0.0; 3],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+            scale: [1.0; 3],
+        }
+        .matrix();
+        let mut positions = Vec::new();
+        let mut triangles = Vec::new();
+        let scene = self
+            .default_scene()
+            .and_then(|it| self.root.scenes.get(it.value()));
+        match scene {
+            Some(scene) => {
+                for &node in &scene.nodes {
+                    self.collect_node_triangles(node, &identity, &mut positions, &mut triangles)?;
+                }
+            }
+            None => {
+                for mesh in 0..self.root.meshes.len() {
+                    self.collect_mesh_triangles(
+                        Index::new(mesh as u32),
+                        &identity,
+                        &mut positions,
+                        &mut triangles,
+                    )?;
+                }
             }
         }
-        Ok(())
+        Ok(MeshStats::new(&positions, &triangles))
+    }
+
+    fn collect_node_triangles(
+        &self,
+        node: Index<Node>,
+        parent_transform: &[[f32; 4]; 4],
+        positions: &mut Vec<[f32; 3]>,
+        triangles: &mut Vec<[usize; 3]>,
+    ) -> Result<(), ConvertError> {
+        let node = self
+            .root
+            .nodes
+            .get(node.value())
+            .ok_or_else(|| ConvertError::InvalidData("Unable to find node".to_string()))?;
+        let transform = match node.matrix {
+            Some(m) => gltf::scene::Transform::Matrix {
+                matrix: [0, 1, 2, 3].map(|c| [0, 1, 2, 3].map(|r| m[4 * c + r])),
+            },
+            None => gltf::scene::Transform::Decomposed {
+                translation: node.translation.unwrap_or([0.0; 3]),
+                rotation: node.rotation.map_or([0.0, 0.0, 0.0, 1.0], |it| it.0),
+                scale: node.scale.unwrap_or([1.0; 3]),
+            },
+        };
+        let transform = mat4_mul(parent_transform, &transform.matrix());
+        if let Some(mesh) = node.mesh {
+            self.collect_mesh_triangles(mesh, &transform, positions, triangles)?;
+        }
+        for &child in node.children.iter().flatten() {
+            self.collect_node_triangles(child, &transform, positions, triangles)?;
+        }
+        Ok(())
+    }
+
+    fn collect_mesh_triangles(
+        &self,
+        mesh: Index<Mesh>,
+        transform: &[[f32; 4]; 4],
+        positions: &mut Vec<[f32; 3]>,
+        triangles: &mut Vec<[usize; 3]>,
+    ) -> Result<(), ConvertError> {
+        let mesh = self
+            .root
+            .meshes
+            .get(mesh.value())
+            .ok_or_else(|| ConvertError::InvalidData("Unable to find mesh".to_string()))?;
+        for primitive in &mesh.primitives {
+            if primitive.mode != Checked::Valid(json::mesh::Mode::Triangles) {
+                return Err(ConvertError::UnsupportedFormat(format!(
+                    "Unsupported primitive mode {:?}",
+                    primitive.mode
+                )));
+            }
+            let position = primitive
+                .attributes
+                .get(&Checked::Valid(json::mesh::Semantic::Positions))
+                .ok_or_else(|| {
+                    ConvertError::InvalidData("Primitive has no positions".to_string())
+                })?;
+            let first_vertex = positions.len();
+            let values = self.read_accessor(*position)?;
+            positions.extend(
+                values
+                    .chunks_exact(3)
+                    .map(|p| mat4_transform_point(transform, [0, 1, 2].map(|k| p[k] as f32))),
+            );
+            let vertex_count = positions.len() - first_vertex;
+            let indices = match primitive.indices {
+                Some(indices) => self
+                    .read_accessor(indices)?
+                    .into_iter()
+                    .map(|it| it as usize)
+                    .collect(),
+                None => (0..vertex_count).collect::<Vec<_>>(),
+            };
+            if let Some(index) = indices.iter().find(|&&it| it >= vertex_count) {
+                return Err(ConvertError::InvalidData(format!(
+                    "Index {} out of range ({} vertices)",
+                    index, vertex_count
+                )));
+            }
+            triangles.extend(
+                indices
+                    .chunks_exact(3)
+                    .map(|it| [it[0], it[1], it[2]].map(|vi| first_vertex + vi)),
+            );
+        }
+        Ok(())
+    }
+
+    /// Read the components of all the elements of an accessor, normalized integers being
+    /// converted to floats
+    fn read_accessor(&self, index: Index<Accessor>) -> Result<Vec<f64>, ConvertError> {
+        let invalid = |message: &str| {
+            ConvertError::InvalidData(format!(
+                "Unable to read accessor {}: {}",
+                index.value(),
+                message
+            ))
+        };
+        let accessor = self
+            .root
+            .accessors
+            .get(index.value())
+            .ok_or_else(|| invalid("not found"))?;
+        let (Checked::Valid(component_type), Checked::Valid(type_)) =
+            (&accessor.component_type, &accessor.type_)
+        else {
+            return Err(invalid("invalid type"));
+        };
+        let view = accessor
+            .buffer_view
+            .and_then(|it| self.root.buffer_views.get(it.value()))
+            .ok_or_else(|| invalid("no buffer view"))?;
+        let blob = self
+            .blobs
+            .get(view.buffer.value())
+            .ok_or_else(|| invalid("no buffer"))?;
+
+        use json::accessor::ComponentType;
+        let component_type = component_type.0;
+        let component_size = component_type.size();
+        let components = type_.multiplicity();
+        let stride = view
+            .byte_stride
+            .map_or(component_size * components, |it| it.0);
+        let start = view.byte_offset.map_or(0, |it| it.0) as usize
+            + accessor.byte_offset.map_or(0, |it| it.0) as usize;
+        let mut values = Vec::with_capacity(accessor.count.0 as usize * components);
+        for i in 0..accessor.count.0 as usize {
+            for k in 0..components {
+                let at = start + i * stride + k * component_size;
+                let bytes = blob
+                    .get(at..at + component_size)
+                    .ok_or_else(|| invalid("out of bounds"))?;
+                let value = match component_type {
+                    ComponentType::I8 => i8::from_le_bytes([bytes[0]]) as f64,
+                    ComponentType::U8 => bytes[0] as f64,
+                    ComponentType::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    ComponentType::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    ComponentType::U32 => {
+                        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64
+                    }
+                    ComponentType::F32 => {
+                        f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64
+                    }
+                };
+                let value = match (accessor.normalized, component_type) {
+                    (true, ComponentType::I8) => (value / 127.0).max(-1.0),
+                    (true, ComponentType::U8) => value / 255.0,
+                    (true, ComponentType::I16) => (value / 32767.0).max(-1.0),
+                    (true, ComponentType::U16) => value / 65535.0,
+                    _ => value,
+                };
+                values.push(value);
+            }
+        }
+        Ok(values)
+    }
+
+    /// Merge two builders: the elements of `other` are appended with their indices offset.
+    /// Each buffer keeps its binary data. The scenes stay separate, with their names, and the
+    /// default scene of `self` is kept, if any. See [`GltfBuilder::merge_flatten`] to show both
+    /// in one scene, and [`GltfBuilder::merge_dedup`] to store the shared data once.
+    pub fn merge(self, other: GltfBuilder) -> GltfBuilder {
+        let mut blobs = self.blobs;
+        blobs.extend(other.blobs);
+        GltfBuilder {
+            root: merge_gltf_roots(self.root, other.root),
+            blobs,
+        }
     }
 
-    #[allow(dead_code)]
-    fn get_buffer_offset(&self, buffer: Index<Buffer>) -> u64 {
-        self.blobs[..buffer.value()]
-            .iter()
-            .map(|it| it.len())
-            .sum::<usize>() as u64
+    /// Same as [`GltfBuilder::merge`], but the root nodes of the default scene of `other` are
+    /// added to the default scene of `self` instead of keeping a scene of their own
+    pub fn merge_flatten(self, other: GltfBuilder) -> GltfBuilder {
+        let target = self.default_scene();
+        let source = other
+            .default_scene()
+            .map(|it| it.value() + self.root.scenes.len());
+        let mut merged = self.merge(other);
+        if let (Some(target), Some(source)) = (target, source) {
+            // The default scene is `target`, before `source`: no index to shift
+            let nodes = merged.root.scenes.remove(source).nodes;
+            merged.root.scenes[target.value()].nodes.extend(nodes);
+            // Without a default scene in `self`, `merge` kept the one of `other`
+            merged.root.scene = Some(target);
+        }
+        merged
     }
 
-    fn compute_buffers_offsets(&self) -> Vec<usize> {
-        let mut res = Vec::new();
-        let mut accum = 0;
-        for b in self.blobs.iter() {
-            res.push(accum);
-            accum += b.len();
+    /// Same as [`GltfBuilder::merge`], followed by [`GltfBuilder::dedup_buffers`]:
+    /// the parts sharing geometry store it once.
+    pub fn merge_dedup(self, other: GltfBuilder) -> GltfBuilder {
+        let mut merged = self.merge(other);
+        merged.dedup_buffers();
+        merged
+    }
+
+    /// Collapse the byte-identical buffers into the first of them, and point their buffer
+    /// views to it. Returns the number of buffers removed.
+    pub fn dedup_buffers(&mut self) -> usize {
+        use std::collections::HashMap;
+        use std::hash::{DefaultHasher, Hash, Hasher};
+        if self.blobs.len() != self.root.buffers.len() {
+            return 0;
         }
-        res
+        // Buffers by hash of their data, compared in full in case of collision
+        let mut by_hash = HashMap::<u64, Vec<usize>>::new();
+        // New index of each buffer, and whether it is kept
+        let mut remap = Vec::with_capacity(self.blobs.len());
+        let mut kept = Vec::with_capacity(self.blobs.len());
+        let mut kept_count = 0;
+        for (index, blob) in self.blobs.iter().enumerate() {
+            let mut hasher = DefaultHasher::new();
+            blob.hash(&mut hasher);
+            let candidates = by_hash.entry(hasher.finish()).or_default();
+            match candidates.iter().find(|&&it| self.blobs[it] == *blob) {
+                Some(&first) => {
+                    remap.push(remap[first]);
+                    kept.push(false);
+                }
+                None => {
+                    candidates.push(index);
+                    remap.push(kept_count);
+                    kept.push(true);
+                    kept_count += 1;
+                }
+            }
+        }
+        let removed = self.blobs.len() - kept_count;
+        if removed == 0 {
+            return 0;
+        }
+
+        let mut is_kept = kept.iter();
+        self.blobs.retain(|_| *is_kept.next().unwrap_or(&true));
+        let mut is_kept = kept.iter();
+        self.root
+            .buffers
+            .retain(|_| *is_kept.next().unwrap_or(&true));
+        for view in &mut self.root.buffer_views {
+            if let Some(&index) = remap.get(view.buffer.value()) {
+                view.buffer = Index::new(index as u32);
+            }
+        }
+        removed
     }
 
-    fn compute_buffers_len(&self) -> usize {
-        self.blobs.iter().map(|it| it.len()).sum::<usize>()
+    pub fn merge_gltf_buffers(&self) -> Result<GltfBuilder, ConvertError> {
+        self.merge_gltf_buffers_max(usize::MAX)
     }
 
-    pub fn merge_gltf_buffers(&self) -> Result<GltfBuilder, String> {
+    /// Merge consecutive buffers as long as each merged buffer stays within `max_bytes`.
+    /// A buffer already larger than `max_bytes` stays on its own.
+    pub fn merge_gltf_buffers_max(&self, max_bytes: usize) -> Result<GltfBuilder, ConvertError> {
         let root = &self.root;
         let blobs = &self.blobs;
         debug_assert!(blobs.len() == root.buffers.len());
         if blobs.len() != root.buffers.len() {
-            return Err("Invalid input data".to_string());
+            return Err(ConvertError::InvalidData("Invalid input data".to_string()));
         }
         for (i, buffer) in root.buffers.iter().enumerate() {
             if buffer.byte_length.0 != blobs[i].len() as u64 {
-                return Err("Invalid input data".to_string());
+                return Err(ConvertError::InvalidData("Invalid input data".to_string()));
             }
         }
 
@@ -329,75 +1608,200 @@ impl GltfBuilder {
             return Ok(self.clone());
         }
 
-        let bin_len = self.compute_buffers_len();
-        let offsets = self.compute_buffers_offsets();
-        debug_assert!(blobs.len() == offsets.len());
+        // Merged buffer of each buffer, and its offset in it
+        let mut merged_blobs = Vec::<Vec<u8>>::new();
+        let mut merged_buffers = Vec::<Buffer>::new();
+        let mut placements = Vec::with_capacity(blobs.len());
+        // Each buffer starts on a multiple of 4 bytes, the alignment of the largest components
+        for (blob, buffer) in blobs.iter().zip(&root.buffers) {
+            let fits = merged_blobs.last().is_some_and(|it| {
+                align_to_multiple_of_four(it.len())
+                    .ok()
+                    .and_then(|offset| offset.checked_add(blob.len()))
+                    .is_some_and(|len| len <= max_bytes)
+            });
+            if fits {
+                let index = merged_blobs.len() - 1;
+                let offset = align_to_multiple_of_four(merged_blobs[index].len())?;
+                merged_blobs[index].resize(offset, 0);
+                placements.push((index, offset));
+                merged_blobs[index].extend_from_slice(blob);
+            } else {
+                placements.push((merged_blobs.len(), 0));
+                merged_blobs.push(blob.clone());
+                merged_buffers.push(buffer.clone());
+            }
+        }
 
         let mut root = root.clone();
-
-        root.buffers.truncate(1);
-        root.buffers[0].byte_length = USize64(bin_len as u64);
+        for (buffer, blob) in merged_buffers.iter_mut().zip(&merged_blobs) {
+            buffer.byte_length = USize64::from(blob.len());
+        }
+        root.buffers = merged_buffers;
 
         for view in &mut root.buffer_views {
-            let index = view.buffer.value();
-            view.buffer = Index::new(0);
-            view.byte_offset = Some(USize64(offsets[index] as u64));
+            let (index, offset) = placements[view.buffer.value()];
+            view.buffer = Index::new(index as u32);
+            let byte_offset = view.byte_offset.map_or(0, |it| it.0);
+            view.byte_offset = Some(USize64(offset as u64 + byte_offset));
         }
 
         Ok(GltfBuilder {
             root,
-            blobs: vec![self.combine_bin_chunk()],
+            blobs: merged_blobs,
         })
     }
 
     /// @param out_dir: only for text format. The file in which to write the binary data
-    pub fn to_glb(&self) -> Result<Glb, String> {
+    pub fn to_glb(&self) -> Result<Glb<'_>, ConvertError> {
         debug_assert_eq!(self.root.buffers.len(), self.blobs.len());
 
-        let mut builder = self.merge_gltf_buffers().unwrap();
-        let bin_chunk = builder.blobs.remove(0);
+        let mut builder = self.merge_gltf_buffers()?;
+        let json_string = json::serialize::to_string(&builder.root).map_err(serialization_error)?;
+        // Computed before taking the merged blob, which makes the BIN chunk
+        let glb_length = builder.compute_glb_len(json_string.len())?;
+        let bin_chunk = builder.blobs.pop();
 
-        let json_string = json::serialize::to_string(&builder.root).expect("Serialization error");
-        let glb_length = self.compute_glb_len(json_string.len());
+        // NOTE: the JSON chunk must be padded with spaces, and the BIN chunk with 0s
+        let mut json_bytes = json_string.into_bytes();
+        json_bytes.resize(align_to_multiple_of_four(json_bytes.len())?, b' ');
+        let bin_chunk = bin_chunk
+            .map(|mut it| -> Result<Cow<[u8]>, ConvertError> {
+                it.resize(align_to_multiple_of_four(it.len())?, 0);
+                Ok(Cow::Owned(it))
+            })
+            .transpose()?;
+        // The declared length must match the padded chunks actually written
+        debug_assert_eq!(
+            glb_length,
+            12 + 8 + json_bytes.len() + bin_chunk.as_ref().map_or(0, |it: &Cow<[u8]>| 8 + it.len())
+        );
 
         let header = Header {
             magic: *b"glTF",
             version: 2,
-            length: (glb_length)
+            length: glb_length
                 .try_into()
-                .expect("file size exceeds binary glTF limit"),
+                .map_err(|_| ConvertError::TooLarge(glb_length as u64))?,
         };
         Ok(Glb {
             header,
-            json: Cow::Owned(json_string.into_bytes()),
-            bin: Some(Cow::Owned(bin_chunk)),
+            json: Cow::Owned(json_bytes),
+            bin: bin_chunk,
         })
     }
 
-    pub fn write_to_gltf<W>(&self, writer: W) -> Result<(), String>
+    /// The whole GLB file, e.g. for an HTTP response
+    pub fn to_glb_bytes(&self) -> Result<Vec<u8>, ConvertError> {
+        Ok(self.to_glb()?.to_vec()?)
+    }
+
+    /// The glTF JSON with each buffer inlined as a base64 data URI.
+    /// The URIs of the images that are not stored in a buffer are kept.
+    pub fn to_gltf_json_with_embedded(&self) -> Result<String, ConvertError> {
+        let mut root = self.root.clone();
+        for (buffer, blob) in root.buffers.iter_mut().zip(&self.blobs) {
+            buffer.uri = Some(format!(
+                "data:application/octet-stream;base64,{}",
+                base64::encode(blob)
+            ));
+        }
+        json::serialize::to_string(&root).map_err(serialization_error)
+    }
+
+    pub fn write_to_gltf<W>(&self, writer: W) -> Result<(), ConvertError>
     where
         W: std::io::Write,
     {
-        json::serialize::to_writer(writer, &self.root).expect("Serialization error");
-        Ok(())
+        json::serialize::to_writer(writer, &self.root).map_err(|err| ConvertError::Io(err.into()))
+    }
+}
+
+/// Whether the file at `path` exists and holds exactly `data`
+fn has_content(path: &Path, data: &[u8]) -> bool {
+    std::fs::metadata(path).is_ok_and(|it| it.len() == data.len() as u64)
+        && std::fs::read(path).is_ok_and(|it| it == data)
+}
+
+/// The I/O errors of the files written in parallel, as a single error naming each failed file
+fn merge_write_errors<'a>(
+    results: impl Iterator<Item = (&'a PathBuf, std::io::Result<()>)>,
+) -> Result<(), ConvertError> {
+    let errors = results
+        .filter_map(|(path, result)| Some((path, result.err()?)))
+        .collect::<Vec<_>>();
+    let Some((_, first)) = errors.first() else {
+        return Ok(());
+    };
+    let message = errors
+        .iter()
+        .map(|(path, err)| format!("{}: {}", path.display(), err))
+        .collect::<Vec<_>>()
+        .join("; ");
+    Err(ConvertError::Io(std::io::Error::new(first.kind(), message)))
+}
+
+/// JSON errors of the serialization to a string: they can only come from the data
+fn serialization_error(err: json::Error) -> ConvertError {
+    ConvertError::InvalidData(format!("Serialization error: {}", err))
+}
+
+/// Round `value` to `decimals` decimals, towards -inf if `down` or towards +inf otherwise
+fn round_outward(value: f64, decimals: u32, down: bool) -> f64 {
+    if !value.is_finite() {
+        return value;
+    }
+    let factor = 10f64.powi(decimals as i32);
+    let steps = if down {
+        (value * factor).floor()
+    } else {
+        (value * factor).ceil()
+    };
+    // The product and the division may round the wrong way: step once more if needed
+    let rounded = steps / factor;
+    match (down, rounded) {
+        (true, it) if it > value => (steps - 1.0) / factor,
+        (false, it) if it < value => (steps + 1.0) / factor,
+        _ => rounded,
     }
 }
 
-fn align_to_multiple_of_four(n: usize) -> usize {
-    (n + 3) & !3
+/// Fails with `TooLarge` for the lengths within 3 bytes of `usize::MAX`
+fn align_to_multiple_of_four(n: usize) -> Result<usize, ConvertError> {
+    n.checked_add(3)
+        .map(|it| it & !3)
+        .ok_or(ConvertError::TooLarge(n as u64))
 }
 
-// fn to_padded_byte_vector<T>(vec: Vec<T>) -> Vec<u8> {
-// let byte_length = vec.len() * std::mem::size_of::<T>();
-// let byte_capacity = vec.capacity() * std::mem::size_of::<T>();
-// let alloc = vec.into_boxed_slice();
-// let ptr = Box::<[T]>::into_raw(alloc) as *mut u8;
-// let mut new_vec = unsafe { Vec::from_raw_parts(ptr, byte_length, byte_capacity) };
-// while new_vec.len() % 4 != 0 {
-// new_vec.push(0); // pad to multiple of four bytes
-// }
-// new_vec
-// }
+/// Length of a GLB file with a JSON chunk of `json_len` bytes and the blobs in its BIN chunk,
+/// which is omitted without blobs. Each chunk is padded to 4 bytes.
+/// Summed as `u64` with checked additions, so that a length overflowing `usize` (e.g. on
+/// 32-bit targets) or `u64` fails with `TooLarge` instead of wrapping.
+fn glb_len(json_len: usize, blob_lens: impl Iterator<Item = usize>) -> Result<usize, ConvertError> {
+    let chunk_header_size = 8; // chunk length (u32) + chunk type (u32)
+    let glb_header_size = 12; // magic (u32) + version (u32) + file length (u32)
+    let too_large = || ConvertError::TooLarge(u64::MAX);
+    let chunk_size = |n: u64| {
+        n.checked_add(3)
+            .map(|it| it & !3)
+            .and_then(|it| it.checked_add(chunk_header_size))
+            .ok_or_else(too_large)
+    };
+    let mut blob_lens = blob_lens.peekable();
+    let bin_chunk_size = if blob_lens.peek().is_none() {
+        0
+    } else {
+        let blobs_len = blob_lens
+            .try_fold(0u64, |total, it| total.checked_add(it as u64))
+            .ok_or_else(too_large)?;
+        chunk_size(blobs_len)?
+    };
+    let length = chunk_size(json_len as u64)?
+        .checked_add(bin_chunk_size)
+        .and_then(|it| it.checked_add(glb_header_size))
+        .ok_or_else(too_large)?;
+    usize::try_from(length).map_err(|_| ConvertError::TooLarge(length))
+}
 
 macro_rules! impl_get {
     ($ty:ty, $field:ident) => {
@@ -429,7 +1833,15 @@ impl<T> IndexMath for Index<T> {
     }
 }
 
-#[allow(dead_code)]
+/// The default scene of `root`, or else its first scene. `None` if it does not exist.
+fn default_scene(root: &Root) -> Option<Index<Scene>> {
+    root.scene
+        .or_else(|| (!root.scenes.is_empty()).then(|| Index::new(0)))
+        .filter(|it| it.value() < root.scenes.len())
+}
+
+/// Append the elements of `b` to `a`, offsetting the indices of `b`.
+/// The buffers of `b` are appended after the buffers of `a`.
 fn merge_gltf_roots(a: Root, b: Root) -> Root {
     let mut result = a;
     let mut append = b;
@@ -444,11 +1856,67 @@ fn merge_gltf_roots(a: Root, b: Root) -> Root {
     let orig_meshes_count = result.meshes.len();
     let orig_nodes_count = result.nodes.len();
     let orig_samplers_count = result.samplers.len();
-    // let orig_scenes_count = result.scenes.len();
+    let orig_scenes_count = result.scenes.len();
     let orig_skins_count = result.skins.len();
     let orig_textures_count = result.textures.len();
+    let orig_variants_count = result
+        .extensions
+        .as_ref()
+        .and_then(|it| it.khr_materials_variants.as_ref())
+        .map_or(0, |it| it.variants.len() as u32);
+    #[cfg(feature = "KHR_lights_punctual")]
+    let orig_lights_count = result
+        .extensions
+        .as_ref()
+        .and_then(|it| it.khr_lights_punctual.as_ref())
+        .map_or(0, |it| it.lights.len());
+
+    for name in append.extensions_used.drain(..) {
+        if !result.extensions_used.contains(&name) {
+            result.extensions_used.push(name);
+        }
+    }
+    for name in append.extensions_required.drain(..) {
+        if !result.extensions_required.contains(&name) {
+            result.extensions_required.push(name);
+        }
+    }
+
+    #[cfg(feature = "KHR_lights_punctual")]
+    if let Some(lights) = append
+        .extensions
+        .as_mut()
+        .and_then(|it| it.khr_lights_punctual.take())
+    {
+        result
+            .extensions
+            .get_or_insert_with(Default::default)
+            .khr_lights_punctual
+            .get_or_insert_with(Default::default)
+            .lights
+            .extend(lights.lights);
+    }
+
+    if let Some(variants) = append
+        .extensions
+        .as_mut()
+        .and_then(|it| it.khr_materials_variants.take())
+    {
+        result
+            .extensions
+            .get_or_insert_with(Default::default)
+            .khr_materials_variants
+            .get_or_insert_with(Default::default)
+            .variants
+            .extend(variants.variants);
+    }
 
     result.samplers.append(&mut append.samplers);
+    for image in &mut append.images {
+        if let Some(view) = &mut image.buffer_view {
+            view.add(orig_buffer_views_count);
+        }
+    }
     result.images.append(&mut append.images);
     result.buffers.append(&mut append.buffers);
     result.cameras.append(&mut append.cameras);
@@ -489,6 +1957,7 @@ fn merge_gltf_roots(a: Root, b: Root) -> Root {
             }
         }
     }
+    result.materials.append(&mut append.materials);
 
     for buffer_view in &mut append.buffer_views {
         buffer_view.buffer.add(orig_buffers_count);
@@ -499,6 +1968,10 @@ fn merge_gltf_roots(a: Root, b: Root) -> Root {
         if let Some(view) = &mut accessor.buffer_view {
             view.add(orig_buffer_views_count);
         }
+        if let Some(sparse) = &mut accessor.sparse {
+            sparse.indices.buffer_view.add(orig_buffer_views_count);
+            sparse.values.buffer_view.add(orig_buffer_views_count);
+        }
     }
     result.accessors.append(&mut append.accessors);
 
@@ -513,6 +1986,18 @@ fn merge_gltf_roots(a: Root, b: Root) -> Root {
             if let Some(material) = &mut primitive.material {
                 material.add(orig_materials_count);
             }
+            if let Some(variants) = primitive
+                .extensions
+                .as_mut()
+                .and_then(|it| it.khr_materials_variants.as_mut())
+            {
+                for mapping in &mut variants.mappings {
+                    mapping.material += orig_materials_count as u32;
+                    for variant in &mut mapping.variants {
+                        *variant += orig_variants_count;
+                    }
+                }
+            }
             if let Some(morph_targets) = &mut primitive.targets {
                 for morph_target in morph_targets {
                     if let Some(positions) = &mut morph_target.positions {
@@ -548,10 +2033,22 @@ fn merge_gltf_roots(a: Root, b: Root) -> Root {
         #[cfg(feature = "KHR_lights_punctual")]
         if let Some(extensions) = &mut node.extensions {
             if let Some(khr_lights_punctual) = &mut extensions.khr_lights_punctual {
-                // TODO: NOT WORKING: where is the list list located ?
                 khr_lights_punctual.light.add(orig_lights_count);
             }
         }
+        if let Some(attributes) = node
+            .extensions
+            .as_mut()
+            .and_then(|it| it.others.get_mut(GPU_INSTANCING))
+            .and_then(|it| it.get_mut("attributes"))
+            .and_then(|it| it.as_object_mut())
+        {
+            for accessor in attributes.values_mut() {
+                if let Some(index) = accessor.as_u64() {
+                    *accessor = json::Value::from(index + orig_accessors_count as u64);
+                }
+            }
+        }
     }
     result.nodes.append(&mut append.nodes);
 
@@ -563,6 +2060,9 @@ fn merge_gltf_roots(a: Root, b: Root) -> Root {
         // NOTE: Do not increment animation.channels[*].sampler.
         // Because it is the index of the sampler inside the animation.
         // So the index doesn't change.
+        for channel in &mut animation.channels {
+            channel.target.node.add(orig_nodes_count);
+        }
     }
     result.animations.append(&mut append.animations);
 
@@ -588,6 +2088,12 @@ fn merge_gltf_roots(a: Root, b: Root) -> Root {
         }
     }
     result.scenes.append(&mut append.scenes);
+    if result.scene.is_none() {
+        result.scene = append.scene.map(|mut scene| {
+            scene.add(orig_scenes_count);
+            scene
+        });
+    }
 
     debug_assert!(append.accessors.is_empty());
     debug_assert!(append.animations.is_empty());
@@ -620,20 +2126,862 @@ impl_get!(Scene, scenes);
 impl_get!(Skin, skins);
 impl_get!(Texture, textures);
 
-fn vec_to_u8_vec<T: Sized>(vec: Vec<T>) -> Vec<u8> {
-    unsafe {
-        let t_size = core::mem::size_of::<T>();
-        let result = Vec::from_raw_parts(
-            vec.as_ptr() as *mut u8,
-            vec.len() * t_size,
-            vec.capacity() * t_size,
-        );
-        std::mem::forget(vec);
-        result
+/// Serialize a value as little-endian bytes, as mandated by glTF for all binary data
+pub trait ToLeBytes {
+    fn write_le_bytes(&self, out: &mut Vec<u8>);
+}
+
+impl ToLeBytes for f32 {
+    fn write_le_bytes(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl ToLeBytes for u8 {
+    fn write_le_bytes(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+}
+
+impl ToLeBytes for i8 {
+    fn write_le_bytes(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
     }
 }
 
-#[allow(dead_code)]
-unsafe fn vec_as_u8_slice<T: Sized>(data: &[T]) -> &[u8] {
-    core::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data))
+impl ToLeBytes for i16 {
+    fn write_le_bytes(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl ToLeBytes for u16 {
+    fn write_le_bytes(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl ToLeBytes for u32 {
+    fn write_le_bytes(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl<T: ToLeBytes, const N: usize> ToLeBytes for [T; N] {
+    fn write_le_bytes(&self, out: &mut Vec<u8>) {
+        for it in self {
+            it.write_le_bytes(out);
+        }
+    }
+}
+
+fn to_le_byte_vec<T: ToLeBytes>(data: &[T]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(std::mem::size_of_val(data));
+    for it in data {
+        it.write_le_bytes(&mut result);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builder with one named scene of one node, without a default scene
+    fn named_scene_builder(name: &str) -> GltfBuilder {
+        let mut gltf = GltfBuilder::new();
+        let node = gltf.push(Node::default());
+        gltf.push_named_scene(name, vec![node]);
+        gltf
+    }
+
+    /// The root, as read back from its serialized JSON
+    fn serialized_root(gltf: &GltfBuilder) -> Root {
+        json::deserialize::from_str(&gltf.to_json().unwrap()).unwrap()
+    }
+
+    /// Empty directory of the temporary files of a test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("conv3d-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Builder with a material textured by an embedded image holding `data`
+    fn textured_builder(dir: &Path, name: &str, data: &[u8]) -> GltfBuilder {
+        let path = dir.join(format!("{}.png", name));
+        std::fs::write(&path, data).unwrap();
+        let mut gltf = GltfBuilder::new();
+        let image = gltf.push_image_from_file(&path, true).unwrap();
+        let sampler = gltf.push_sampler(None, None, texture::WrappingMode::Repeat);
+        let texture = gltf.push_texture(image, Some(sampler));
+        let material = gltf.push_material(None, &MaterialOptions::default());
+        gltf.set_base_color_texture(material, texture).unwrap();
+        gltf
+    }
+
+    /// Bytes of the embedded image of each material, following material → texture → image →
+    /// view → buffer
+    fn material_images(glb: &[u8]) -> Vec<Vec<u8>> {
+        let gltf = gltf::Gltf::from_slice(glb).unwrap();
+        let blob = gltf.blob.as_ref().unwrap();
+        gltf.document
+            .materials()
+            .map(|material| {
+                let info = material
+                    .pbr_metallic_roughness()
+                    .base_color_texture()
+                    .unwrap();
+                let texture = info.texture();
+                assert!(texture.sampler().index().is_some());
+                match texture.source().source() {
+                    gltf::image::Source::View { view, mime_type } => {
+                        assert_eq!(mime_type, "image/png");
+                        assert_eq!(view.buffer().index(), 0);
+                        blob[view.offset()..view.offset() + view.length()].to_vec()
+                    }
+                    gltf::image::Source::Uri { .. } => panic!("the image is not embedded"),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn texture_chain() {
+        let dir = temp_dir("texture-chain");
+        let gltf = textured_builder(&dir, "a", b"first image");
+        let root = serialized_root(&gltf);
+        let info = root.materials[0]
+            .pbr_metallic_roughness
+            .base_color_texture
+            .as_ref();
+        let texture = root.get(info.unwrap().index).unwrap();
+        assert!(root.get(texture.sampler.unwrap()).is_some());
+        let image = root.get(texture.source).unwrap();
+        let view = root.get(image.buffer_view.unwrap()).unwrap();
+        assert_eq!(view.byte_length, USize64(11));
+        assert!(root.get(view.buffer).is_some());
+        assert_eq!(
+            material_images(&gltf.to_glb_bytes().unwrap()),
+            [b"first image"]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn merge_textured_builders() {
+        let dir = temp_dir("merge-textured");
+        let a = textured_builder(&dir, "a", b"first image");
+        let b = textured_builder(&dir, "b", b"second");
+        for merged in [
+            a.clone().merge(b.clone()),
+            a.clone().merge_flatten(b.clone()),
+            a.clone().merge_dedup(b.clone()),
+        ] {
+            let images = material_images(&merged.to_glb_bytes().unwrap());
+            assert_eq!(images, [&b"first image"[..], &b"second"[..]]);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Builder of the unit cube, scaled by `scale` so that the builders differ
+    fn cube_builder(scale: f32) -> GltfBuilder {
+        let options = crate::ConvertOptions::builder()
+            .scale(scale)
+            .build()
+            .unwrap();
+        crate::stl_to_gltf(crate::geometry::tests::indexed_cube(), "cube", options).unwrap()
+    }
+
+    #[test]
+    fn merge_single_mesh_builders() {
+        let (a, b) = (cube_builder(1.0), cube_builder(2.0));
+        let merged = a.clone().merge(b.clone());
+        assert_eq!(merged.mesh_count(), 2);
+        assert_eq!(merged.root.accessors.len(), a.root.accessors.len() * 2);
+        assert_eq!(merged.root.nodes.len(), 2);
+        assert_eq!(merged.root.nodes[1].mesh, Some(Index::new(1)));
+
+        let (views, buffers) = (a.root.buffer_views.len(), a.root.buffers.len());
+        for (merged, original) in merged.root.accessors[a.root.accessors.len()..]
+            .iter()
+            .zip(&b.root.accessors)
+        {
+            let view = original.buffer_view.unwrap().value();
+            assert_eq!(merged.buffer_view.unwrap().value(), view + views);
+            assert_eq!(merged.count, original.count);
+        }
+        for (merged, original) in merged.root.buffer_views[views..]
+            .iter()
+            .zip(&b.root.buffer_views)
+        {
+            assert_eq!(merged.buffer.value(), original.buffer.value() + buffers);
+        }
+        let attributes = |mesh: &Mesh| mesh.primitives[0].attributes.clone();
+        for (semantic, accessor) in attributes(&merged.root.meshes[1]) {
+            let original = attributes(&b.root.meshes[0])[&semantic];
+            assert_eq!(accessor.value(), original.value() + a.root.accessors.len());
+        }
+        assert_eq!(merged.blobs[..buffers], a.blobs[..]);
+        assert_eq!(merged.blobs[buffers..], b.blobs[..]);
+    }
+
+    #[test]
+    fn merge_keeps_named_scenes() {
+        let mut a = named_scene_builder("a");
+        a.set_default_scene(Some(Index::new(0)));
+        let merged = a.merge(named_scene_builder("b"));
+        let root = serialized_root(&merged);
+        let names = root
+            .scenes
+            .iter()
+            .map(|it| it.name.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(names, [Some("a"), Some("b")]);
+        assert_eq!(root.scenes[1].nodes, [Index::new(1)]);
+        assert_eq!(root.scene, Some(Index::new(0)));
+    }
+
+    #[test]
+    fn merge_flatten_without_default_scene() {
+        let merged = named_scene_builder("a").merge_flatten(named_scene_builder("b"));
+        let root = serialized_root(&merged);
+        assert_eq!(root.scenes.len(), 1);
+        assert_eq!(root.scenes[0].name.as_deref(), Some("a"));
+        assert_eq!(root.scenes[0].nodes, [Index::new(0), Index::new(1)]);
+        assert_eq!(root.scene, Some(Index::new(0)));
+    }
+
+    /// Builder with a mesh switching between a red and a blue material variant
+    fn variants_builder() -> GltfBuilder {
+        let mut gltf = GltfBuilder::new();
+        let mut variants = Vec::new();
+        for (name, base_color) in [
+            ("red", [1.0, 0.0, 0.0, 1.0]),
+            ("blue", [0.0, 0.0, 1.0, 1.0]),
+        ] {
+            let material = MaterialOptions {
+                base_color,
+                ..Default::default()
+            };
+            let material = gltf.push_material(Some(name.to_string()), &material);
+            variants.push((gltf.push_material_variant(name), material));
+        }
+        let primitive = Primitive {
+            attributes: Default::default(),
+            extensions: None,
+            extras: Default::default(),
+            indices: None,
+            material: None,
+            mode: Checked::Valid(json::mesh::Mode::Triangles),
+            targets: None,
+        };
+        let mesh = gltf.push_mesh(None, vec![primitive], None);
+        gltf.set_mesh_variants(mesh, &variants).unwrap();
+        gltf
+    }
+
+    /// `(material, variants)` mappings of a primitive
+    type Mappings = Vec<(u32, Vec<u32>)>;
+
+    /// Names of the root variants, and the mappings of the first primitive of each mesh
+    fn variants_of(root: &Root) -> (Vec<String>, Vec<Mappings>) {
+        let names = root
+            .extensions
+            .as_ref()
+            .and_then(|it| it.khr_materials_variants.as_ref())
+            .map(|it| it.variants.iter().map(|it| it.name.clone()).collect())
+            .unwrap_or_default();
+        let mappings = root
+            .meshes
+            .iter()
+            .map(|mesh| {
+                let extensions = mesh.primitives[0].extensions.as_ref().unwrap();
+                let variants = extensions.khr_materials_variants.as_ref().unwrap();
+                variants
+                    .mappings
+                    .iter()
+                    .map(|it| (it.material, it.variants.clone()))
+                    .collect()
+            })
+            .collect();
+        (names, mappings)
+    }
+
+    #[test]
+    fn variants_serialize() {
+        let gltf = variants_builder();
+        let root = serialized_root(&gltf);
+        assert_eq!(root.extensions_used, [MATERIALS_VARIANTS]);
+        let (names, mappings) = variants_of(&root);
+        assert_eq!(names, ["red", "blue"]);
+        assert_eq!(mappings, [vec![(0, vec![0]), (1, vec![1])]]);
+    }
+
+    #[test]
+    fn variants_survive_merge() {
+        let merged = variants_builder().merge(variants_builder());
+        let root = serialized_root(&merged);
+        assert_eq!(root.extensions_used, [MATERIALS_VARIANTS]);
+        assert_eq!(root.materials.len(), 4);
+        let (names, mappings) = variants_of(&root);
+        assert_eq!(names, ["red", "blue", "red", "blue"]);
+        assert_eq!(
+            mappings,
+            [
+                vec![(0, vec![0]), (1, vec![1])],
+                vec![(2, vec![2]), (3, vec![3])]
+            ]
+        );
+    }
+
+    #[test]
+    fn generic_accessor_matches_vec3() {
+        let mut gltf = GltfBuilder::new();
+        let view = gltf
+            .push_buffer_with_view(None, vec![[0.0f32; 3]; 8], Some(2), None)
+            .unwrap();
+        let (min, max) = ([-1.0, -2.0, -3.0], [1.0, 2.0, 3.0]);
+        let vec3 = gltf.push_accessor_vec3(Some("a".to_string()), view, 3, 4, Some(min), Some(max));
+        let generic = gltf.push_accessor(
+            Some("a".to_string()),
+            view,
+            3,
+            4,
+            json::accessor::ComponentType::F32,
+            json::accessor::Type::Vec3,
+            Some(json::Value::from(Vec::from(min))),
+            Some(json::Value::from(Vec::from(max))),
+            false,
+        );
+        let to_value = |index: Index<Accessor>| {
+            json::serialize::to_value(gltf.root.get(index).unwrap()).unwrap()
+        };
+        assert_eq!(to_value(vec3), to_value(generic));
+        assert_eq!(gltf.root.get(vec3).unwrap().byte_offset, Some(USize64(12)));
+    }
+
+    #[test]
+    fn glb_round_trip() {
+        let mut gltf = GltfBuilder::new();
+        // 5 bytes: the BIN chunk needs padding
+        gltf.push_buffer(None, vec![1u8, 2, 3, 4, 5], None);
+        let bytes = gltf.to_glb_bytes().unwrap();
+        assert_eq!(bytes.len() % 4, 0);
+
+        let glb = Glb::from_slice(&bytes).unwrap();
+        assert_eq!(glb.header.length as usize, bytes.len());
+        assert_eq!(glb.json.len() % 4, 0);
+        let json = std::str::from_utf8(&glb.json).unwrap();
+        assert!(json.trim_end().ends_with('}'));
+        assert!(json[json.trim_end().len()..].bytes().all(|it| it == b' '));
+        let root: Root = json::deserialize::from_str(json.trim_end()).unwrap();
+        assert_eq!(root.buffers.len(), 1);
+        assert_eq!(root.buffers[0].byte_length, USize64(5));
+        let bin = glb.bin.unwrap();
+        assert_eq!(bin[..], [1, 2, 3, 4, 5, 0, 0, 0]);
+        assert_eq!(12 + 8 + glb.json.len() + 8 + bin.len(), bytes.len());
+    }
+
+    #[test]
+    fn buffer_bytes_match_to_le_bytes() {
+        let values = vec![[1.5f32, -2.0, 3.25], [0.0, f32::MAX, -0.0]];
+        let expected = values
+            .iter()
+            .flatten()
+            .flat_map(|it| it.to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut gltf = GltfBuilder::new();
+        let buffer = gltf.push_buffer(None, values, None);
+        assert_eq!(gltf.blobs[buffer.value()], expected);
+        assert_eq!(
+            gltf.root.buffers[buffer.value()].byte_length,
+            USize64::from(expected.len())
+        );
+    }
+
+    #[test]
+    fn le_bytes_layout() {
+        assert_eq!(
+            to_le_byte_vec(&[1.0f32, -2.0]),
+            [0, 0, 0x80, 0x3f, 0, 0, 0, 0xc0]
+        );
+        assert_eq!(to_le_byte_vec(&[0x0102u16, 0x0304]), [2, 1, 4, 3]);
+        assert_eq!(to_le_byte_vec(&[0x0102_0304u32]), [4, 3, 2, 1]);
+        assert_eq!(to_le_byte_vec(&[-2i16]), [0xfe, 0xff]);
+        assert_eq!(to_le_byte_vec(&[-2i8, 3]), [0xfe, 3]);
+        assert_eq!(to_le_byte_vec(&[7u8, 8]), [7, 8]);
+        // Arrays and vectors are written component by component, without padding
+        assert_eq!(
+            to_le_byte_vec(&[[1u16, 2], [3, 4]]),
+            [1, 0, 2, 0, 3, 0, 4, 0]
+        );
+        let v = crate::geometry::V3 {
+            v: [1.0, 0.0, -2.0],
+        };
+        assert_eq!(
+            to_le_byte_vec(&[v]),
+            [0, 0, 0x80, 0x3f, 0, 0, 0, 0, 0, 0, 0, 0xc0]
+        );
+    }
+
+    #[test]
+    fn node_transform() {
+        let mut gltf = GltfBuilder::new();
+        let mesh = gltf.push_mesh(None, vec![], None);
+        let node =
+            gltf.push_node_with_transform(mesh, [1.0, 2.0, 3.0], [0.0, 0.0, 2.0, 2.0], [4.0; 3]);
+        // A zero rotation is the identity
+        let identity = gltf.push_node_with_transform(mesh, [0.0; 3], [0.0; 4], [1.0; 3]);
+
+        let root = serialized_root(&gltf);
+        let node = &root.nodes[node.value()];
+        assert_eq!(node.mesh, Some(mesh));
+        assert_eq!(node.translation, Some([1.0, 2.0, 3.0]));
+        assert_eq!(node.scale, Some([4.0; 3]));
+        let rotation = node.rotation.unwrap().0;
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert!(rotation
+            .iter()
+            .zip([0.0, 0.0, half, half])
+            .all(|(a, b)| (a - b).abs() < 1e-6));
+        let identity = &root.nodes[identity.value()];
+        assert_eq!(identity.rotation.unwrap().0, [0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn node_hierarchy() {
+        let mut gltf = GltfBuilder::new();
+        let mesh = gltf.push_mesh(None, vec![], None);
+        let leaves = vec![gltf.push_node(mesh), gltf.push_node(mesh)];
+        let group = gltf.push_node_children(None, leaves.clone());
+        let top = gltf.push_node_children(Some(mesh), vec![]);
+        gltf.set_node_children(top, vec![group]).unwrap();
+        gltf.push_scene(vec![top]);
+        assert!(gltf.set_node_children(top, vec![Index::new(10)]).is_err());
+
+        let root = serialized_root(&gltf);
+        let scene = &root.scenes[0];
+        assert_eq!(scene.nodes, [top]);
+        let top = root.get(scene.nodes[0]).unwrap();
+        assert_eq!(top.mesh, Some(mesh));
+        let children = top.children.as_ref().unwrap();
+        assert_eq!(children, &[group]);
+        let group = root.get(children[0]).unwrap();
+        assert_eq!(group.mesh, None);
+        let children = group.children.as_ref().unwrap();
+        assert_eq!(children, &leaves);
+        for &leaf in children {
+            let leaf = root.get(leaf).unwrap();
+            assert_eq!(leaf.mesh, Some(mesh));
+            assert!(leaf.children.is_none());
+        }
+    }
+
+    #[test]
+    fn validate_out_of_bounds_accessors() {
+        let mut gltf = GltfBuilder::new();
+        let points = vec![[0.0f32, 1.0, 2.0]; 4];
+        let view = gltf
+            .push_buffer_with_view(None, points, None, None)
+            .unwrap();
+        gltf.push_accessor_vec3(None, view, 0, 4, None, None);
+        assert_eq!(gltf.validate(), Ok(()));
+
+        // One element too many, and the last element shifted past the end
+        gltf.push_accessor_vec3(None, view, 0, 5, None, None);
+        gltf.push_accessor_vec3(None, view, 3, 4, None, None);
+        let errors = gltf.validate().unwrap_err();
+        let paths = errors.iter().map(|it| it.path.as_str());
+        assert!(paths.eq(["accessors[1]", "accessors[2]"]));
+        assert!(errors[0].message.contains("ends at byte 60"));
+
+        gltf.root.accessors.truncate(1);
+        gltf.root.buffer_views[0].byte_length = USize64(52);
+        let errors = gltf.validate().unwrap_err();
+        assert_eq!(errors[0].path, "bufferViews[0]");
+    }
+
+    /// Bytes of a buffer view
+    fn view_bytes(gltf: &GltfBuilder, view: usize) -> &[u8] {
+        let view = &gltf.root.buffer_views[view];
+        let offset = view.byte_offset.map_or(0, |it| it.0) as usize;
+        &gltf.blobs[view.buffer.value()][offset..offset + view.byte_length.0 as usize]
+    }
+
+    #[test]
+    fn merge_buffers_up_to_a_size() {
+        let gltf = cube_builder(1.0);
+        // Positions and normals of 96 bytes, indices of 72 bytes
+        let sizes = gltf.blobs.iter().map(Vec::len).collect::<Vec<_>>();
+        assert_eq!(sizes, [96, 96, 72]);
+        for (max_bytes, buffer_count) in [(usize::MAX, 1), (264, 1), (263, 2), (200, 2), (50, 3)] {
+            let merged = gltf.merge_gltf_buffers_max(max_bytes).unwrap();
+            assert_eq!(merged.root.buffers.len(), buffer_count, "{}", max_bytes);
+            assert_eq!(merged.validate(), Ok(()));
+            for (buffer, blob) in merged.root.buffers.iter().zip(&merged.blobs) {
+                assert!(blob.len() <= max_bytes.max(96));
+                assert_eq!(buffer.byte_length.0, blob.len() as u64);
+            }
+            // The views, and so the attributes, are moved whole
+            for view in 0..gltf.root.buffer_views.len() {
+                assert_eq!(view_bytes(&merged, view), view_bytes(&gltf, view));
+            }
+        }
+    }
+
+    #[test]
+    fn load_multi_buffer_gltf() {
+        let dir = temp_dir("load-multi-buffer");
+        let mut original = cube_builder(1.0);
+        original.write_gltf_bundle(&dir, "cube").unwrap();
+        for i in 0..3 {
+            assert!(dir.join(format!("cube_{}.bin", i)).is_file());
+        }
+
+        let loaded = GltfBuilder::from_gltf(dir.join("cube.gltf")).unwrap();
+        assert_eq!(loaded.buffer_count(), 3);
+        assert_eq!(loaded.blobs, original.blobs);
+        assert!(loaded.root.buffers.iter().all(|it| it.uri.is_none()));
+        assert_eq!(loaded.validate(), Ok(()));
+
+        let glb = loaded.to_glb_bytes().unwrap();
+        let (document, buffers, _) = gltf::import_slice(&glb).unwrap();
+        assert_eq!(document.buffers().count(), 1);
+        assert!(matches!(
+            document.buffers().next().unwrap().source(),
+            gltf::buffer::Source::Bin
+        ));
+        // The attributes still read the same values from the merged buffer
+        let primitive = document
+            .meshes()
+            .next()
+            .unwrap()
+            .primitives()
+            .next()
+            .unwrap();
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let positions = reader.read_positions().unwrap().collect::<Vec<_>>();
+        assert_eq!(positions.len(), 8);
+        assert_eq!(reader.read_indices().unwrap().into_u32().count(), 36);
+
+        // And the GLB loads back as is
+        let path = dir.join("cube.glb");
+        std::fs::write(&path, &glb).unwrap();
+        let reloaded = GltfBuilder::from_gltf(&path).unwrap();
+        assert_eq!(reloaded.buffer_count(), 1);
+        assert_eq!(reloaded.to_glb_bytes().unwrap(), glb);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn round_outward_never_tightens() {
+        assert_eq!(round_outward(1.234, 2, true), 1.23);
+        assert_eq!(round_outward(1.234, 2, false), 1.24);
+        assert_eq!(round_outward(-1.234, 2, true), -1.24);
+        assert_eq!(round_outward(-1.234, 2, false), -1.23);
+        assert_eq!(round_outward(-0.5, 0, false), 0.0);
+        assert_eq!(round_outward(2.0, 3, true), 2.0);
+        assert!(round_outward(f64::INFINITY, 2, true).is_infinite());
+        for value in [
+            0.1 + 0.2,
+            1.005,
+            -1.005,
+            -0.7,
+            1e-7,
+            -123.456789,
+            0.1f32 as f64,
+        ] {
+            for decimals in 0..=9 {
+                let step = 10f64.powi(-(decimals as i32));
+                let down = round_outward(value, decimals, true);
+                let up = round_outward(value, decimals, false);
+                assert!(
+                    down <= value && value - down < step,
+                    "{} {}",
+                    value,
+                    decimals
+                );
+                assert!(up >= value && up - value < step, "{} {}", value, decimals);
+            }
+        }
+    }
+
+    #[test]
+    fn rounded_bounds_contain_the_exact_ones() {
+        let mut gltf = GltfBuilder::new();
+        let points = vec![[-1.23456f32, 0.1, 7.0], [2.98765, -0.3333, 7.5]];
+        let view = gltf
+            .push_buffer_with_view(None, points, None, None)
+            .unwrap();
+        let exact = ([-1.23456f32, -0.3333, 7.0], [2.98765f32, 0.1, 7.5]);
+        gltf.push_accessor_vec3(None, view, 0, 2, Some(exact.0), Some(exact.1));
+        gltf.round_accessor_bounds(2);
+
+        let accessor = &gltf.root.accessors[0];
+        let values = |value: &Option<json::Value>| -> Vec<f64> {
+            let values = value.as_ref().unwrap().as_array().unwrap();
+            values.iter().map(|it| it.as_f64().unwrap()).collect()
+        };
+        let (min, max) = (values(&accessor.min), values(&accessor.max));
+        assert_eq!(min, [-1.24, -0.34, 7.0]);
+        // 0.1 as a f32 is slightly above 0.1
+        assert_eq!(max, [2.99, 0.11, 7.5]);
+        for k in 0..3 {
+            assert!(min[k] <= exact.0[k] as f64);
+            assert!(max[k] >= exact.1[k] as f64);
+        }
+    }
+
+    #[test]
+    fn merge_is_deterministic() {
+        let merged = || {
+            cube_builder(1.0)
+                .merge_dedup(cube_builder(2.0))
+                .merge_dedup(cube_builder(1.0))
+                .to_glb_bytes()
+                .unwrap()
+        };
+        let first = merged();
+        for _ in 0..4 {
+            assert!(merged() == first);
+        }
+        let merged = || cube_builder(1.0).merge(cube_builder(2.0)).to_glb_bytes();
+        assert!(merged().unwrap() == merged().unwrap());
+    }
+
+    #[test]
+    fn glb_bytes_import() {
+        let gltf = cube_builder(1.0).merge(cube_builder(2.0));
+        let glb = gltf.to_glb_bytes().unwrap();
+        let (document, buffers, images) = gltf::import_slice(&glb).unwrap();
+        assert_eq!(document.meshes().count(), 2);
+        assert_eq!(buffers.len(), 1);
+        assert!(images.is_empty());
+        for (mesh, scale) in document.meshes().zip([1.0, 2.0]) {
+            let primitive = mesh.primitives().next().unwrap();
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let positions = reader.read_positions().unwrap().collect::<Vec<_>>();
+            assert_eq!(positions.len(), 8);
+            assert!(positions.iter().flatten().all(|it| it.abs() <= scale));
+            assert_eq!(reader.read_normals().unwrap().count(), 8);
+            let indices = reader.read_indices().unwrap().into_u32();
+            assert!(indices.map(|it| it as usize).all(|it| it < positions.len()));
+        }
+    }
+
+    /// Names of the files of a directory, sorted
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|it| it.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn failed_bundle_leaves_no_files() {
+        let dir = temp_dir("failed-bundle");
+        // The temporary file of the second buffer cannot be created
+        let blocker = format!(".cube_1.bin.{}.tmp", std::process::id());
+        std::fs::create_dir(dir.join(&blocker)).unwrap();
+        let mut gltf = cube_builder(1.0);
+        assert!(gltf.write_gltf_bundle(&dir, "cube").is_err());
+        assert_eq!(file_names(&dir), [blocker.as_str()]);
+        std::fs::remove_dir(dir.join(&blocker)).unwrap();
+
+        // The .gltf cannot be renamed into place: it is the last file, no temporary file remains
+        std::fs::create_dir(dir.join("cube.gltf")).unwrap();
+        assert!(gltf.write_gltf_bundle(&dir, "cube").is_err());
+        assert!(dir.join("cube.gltf").is_dir());
+        assert!(file_names(&dir).iter().all(|it| !it.ends_with(".tmp")));
+        std::fs::remove_dir(dir.join("cube.gltf")).unwrap();
+
+        assert!(gltf.write_gltf_bundle(&dir, "cube").unwrap());
+        let names = ["cube.gltf", "cube_0.bin", "cube_1.bin", "cube_2.bin"];
+        assert_eq!(file_names(&dir), names);
+        // Unchanged
+        assert!(!gltf.write_gltf_bundle(&dir, "cube").unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn serialization_errors() {
+        let mut gltf = cube_builder(1.0);
+        // Invalid values cannot be serialized
+        gltf.root.accessors[0].component_type = Checked::Invalid;
+        let is_serialization_error = |result: Result<_, ConvertError>| {
+            matches!(result, Err(ConvertError::InvalidData(message))
+                if message.starts_with("Serialization error"))
+        };
+        assert!(is_serialization_error(gltf.to_json().map(|_| ())));
+        assert!(is_serialization_error(gltf.to_glb_bytes().map(|_| ())));
+        assert!(is_serialization_error(
+            gltf.to_gltf_json_with_embedded().map(|_| ())
+        ));
+    }
+
+    #[test]
+    fn write_buffers_concurrently() {
+        let dir = temp_dir("write-buffers");
+        let mut gltf = cube_builder(1.0);
+        gltf.set_buffers_uri("cube");
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(3)
+            .build()
+            .unwrap()
+            .install(|| gltf.write_all_buffers(&dir))
+            .unwrap();
+        for (i, blob) in gltf.blobs.iter().enumerate() {
+            let data = std::fs::read(dir.join(format!("cube_{}.bin", i))).unwrap();
+            assert_eq!(&data, blob);
+        }
+
+        // The other buffers are still written when one fails
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::create_dir_all(dir.join("cube_1.bin")).unwrap();
+        let err = gltf.write_all_buffers(&dir).unwrap_err();
+        assert!(err.to_string().contains("cube_1.bin"), "{}", err);
+        for i in [0, 2] {
+            let data = std::fs::read(dir.join(format!("cube_{}.bin", i))).unwrap();
+            assert_eq!(data, gltf.blobs[i]);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn glb_length_overflow() {
+        assert_eq!(align_to_multiple_of_four(0).unwrap(), 0);
+        assert_eq!(align_to_multiple_of_four(5).unwrap(), 8);
+        assert_eq!(
+            align_to_multiple_of_four(usize::MAX - 3).unwrap(),
+            usize::MAX - 3
+        );
+        for n in [usize::MAX - 2, usize::MAX] {
+            assert!(matches!(
+                align_to_multiple_of_four(n),
+                Err(ConvertError::TooLarge(_))
+            ));
+        }
+
+        // 12-byte header, 8-byte chunk headers, padded chunks
+        assert_eq!(glb_len(10, std::iter::empty()).unwrap(), 12 + 8 + 12);
+        assert_eq!(
+            glb_len(10, [5, 6].into_iter()).unwrap(),
+            12 + 8 + 12 + 8 + 12
+        );
+        for blob_lens in [
+            vec![usize::MAX],
+            vec![usize::MAX, usize::MAX],
+            vec![usize::MAX / 2, usize::MAX / 2 + 1],
+        ] {
+            assert!(matches!(
+                glb_len(10, blob_lens.into_iter()),
+                Err(ConvertError::TooLarge(_))
+            ));
+        }
+        assert!(matches!(
+            glb_len(usize::MAX, std::iter::empty()),
+            Err(ConvertError::TooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn default_generator() {
+        let generator = format!("conv3d {}", env!("CARGO_PKG_VERSION"));
+        let gltf = GltfBuilder::default();
+        let serialized = serialized_root(&gltf).asset.generator.unwrap();
+        assert!(serialized.contains("conv3d"));
+        assert!(serialized.contains(env!("CARGO_PKG_VERSION")));
+        assert_eq!(serialized, generator);
+        assert_eq!(
+            GltfBuilder::new().root.asset.generator,
+            Some(generator.clone())
+        );
+
+        let glb = cube_builder(1.0).to_glb_bytes().unwrap();
+        let document = gltf::Gltf::from_slice(&glb).unwrap().document;
+        assert_eq!(document.as_json().asset.generator, Some(generator));
+    }
+
+    #[test]
+    fn dedup_identical_cubes() {
+        let cube = cube_builder(1.0);
+        let merged = cube.clone().merge_dedup(cube_builder(1.0));
+        assert_eq!(merged.mesh_count(), 2);
+        assert_eq!(merged.blobs, cube.blobs);
+        assert_eq!(merged.root.buffers.len(), cube.root.buffers.len());
+        // The views of the second cube point to the buffers of the first one
+        let views = cube.root.buffer_views.len();
+        for (first, second) in merged.root.buffer_views[..views]
+            .iter()
+            .zip(&merged.root.buffer_views[views..])
+        {
+            assert_eq!(first.buffer, second.buffer);
+        }
+
+        let bytes = |gltf: &GltfBuilder| gltf.blobs.iter().map(Vec::len).sum::<usize>();
+        let merged_bytes = merged.clone().merge_gltf_buffers().unwrap();
+        assert_eq!(bytes(&merged_bytes), bytes(&cube));
+        let glb = merged.to_glb_bytes().unwrap();
+        let (document, buffers, _) = gltf::import_slice(&glb).unwrap();
+        let positions = document
+            .meshes()
+            .map(|mesh| {
+                let primitive = mesh.primitives().next().unwrap();
+                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+                reader.read_positions().unwrap().collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(positions.len(), 2);
+        assert_eq!(positions[0], positions[1]);
+    }
+
+    #[test]
+    fn node_instances() {
+        let mut gltf = cube_builder(1.0);
+        let node = Index::new(0);
+        let instances = (0..4)
+            .map(|i| InstanceTransform {
+                translation: [i as f32, 0.0, 0.0],
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        gltf.set_node_instances(node, &instances).unwrap();
+        assert!(matches!(
+            gltf.set_node_instances(node, &[]),
+            Err(ConvertError::InvalidData(_))
+        ));
+
+        let root = serialized_root(&gltf);
+        assert!(root.extensions_used.contains(&GPU_INSTANCING.to_string()));
+        assert!(!root
+            .extensions_required
+            .contains(&GPU_INSTANCING.to_string()));
+        let extensions = root.nodes[0].extensions.as_ref().unwrap();
+        let attributes = &extensions.others[GPU_INSTANCING]["attributes"];
+        for (semantic, dimensions) in [
+            ("TRANSLATION", json::accessor::Type::Vec3),
+            ("ROTATION", json::accessor::Type::Vec4),
+            ("SCALE", json::accessor::Type::Vec3),
+        ] {
+            let accessor = &root.accessors[attributes[semantic].as_u64().unwrap() as usize];
+            assert_eq!(accessor.count.0, 4);
+            assert_eq!(accessor.type_, Checked::Valid(dimensions));
+        }
+
+        let glb = gltf.to_glb_bytes().unwrap();
+        let (document, buffers, _) = gltf::import_slice(&glb).unwrap();
+        let accessor = document
+            .accessors()
+            .nth(attributes["TRANSLATION"].as_u64().unwrap() as usize)
+            .unwrap();
+        let translations = gltf::accessor::Iter::<[f32; 3]>::new(accessor, |buffer| {
+            Some(&buffers[buffer.index()][..])
+        })
+        .unwrap();
+        assert!(translations.eq(instances.iter().map(|it| it.translation)));
+    }
 }
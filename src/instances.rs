@@ -0,0 +1,103 @@
+use std::path::Path;
+
+use crate::{ConvertError, InstanceTransform};
+
+/// Parse one transform per line: `tx ty tz`, optionally followed by a rotation quaternion
+/// `qx qy qz qw`, and then a scale `sx sy sz`. The numbers are separated by commas and/or
+/// spaces. Empty lines and lines starting with `#` are skipped.
+fn parse_instances(text: &str) -> Result<Vec<InstanceTransform>, ConvertError> {
+    let mut instances = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |message: &str| ConvertError::Parse(format!("Line {}: {}", i + 1, message));
+        let values = line
+            .split(|c: char| c == ',' || c.is_ascii_whitespace())
+            .filter(|it| !it.is_empty())
+            .map(|it| it.parse::<f32>().ok().filter(|it| it.is_finite()))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| invalid("invalid number"))?;
+        let mut instance = InstanceTransform::default();
+        match values.len() {
+            3 | 7 | 10 => instance.translation.copy_from_slice(&values[..3]),
+            _ => return Err(invalid("expected 3, 7 or 10 numbers")),
+        }
+        if values.len() >= 7 {
+            let rotation = &values[3..7];
+            let len = rotation.iter().map(|it| it * it).sum::<f32>().sqrt();
+            if len == 0.0 {
+                return Err(invalid("the rotation quaternion is zero"));
+            }
+            for (q, value) in instance.rotation.iter_mut().zip(rotation) {
+                *q = value / len;
+            }
+        }
+        if values.len() == 10 {
+            instance.scale.copy_from_slice(&values[7..]);
+        }
+        instances.push(instance);
+    }
+    Ok(instances)
+}
+
+/// Read the transforms of the instances of a mesh, for [`crate::ConvertOptions::instances`].
+/// Each line holds a translation `tx ty tz`, optionally followed by a rotation quaternion
+/// `qx qy qz qw` and a scale `sx sy sz`, separated by commas or spaces.
+pub fn read_instances_file(path: impl AsRef<Path>) -> Result<Vec<InstanceTransform>, ConvertError> {
+    let text = std::fs::read_to_string(path)?;
+    let instances = parse_instances(&text)?;
+    if instances.is_empty() {
+        return Err(ConvertError::Parse(
+            "The instances file is empty".to_string(),
+        ));
+    }
+    Ok(instances)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_3_7_10_values() {
+        let text = "# Instances\n1 2 3\n\n4, 5, 6, 0, 0, 0, 2\n7 8 9 0 0 1 1 2 2 2\n";
+        let instances = parse_instances(text).unwrap();
+        let half = 0.5f32.sqrt();
+        assert_eq!(
+            instances,
+            [
+                InstanceTransform {
+                    translation: [1.0, 2.0, 3.0],
+                    ..Default::default()
+                },
+                InstanceTransform {
+                    translation: [4.0, 5.0, 6.0],
+                    ..Default::default()
+                },
+                InstanceTransform {
+                    translation: [7.0, 8.0, 9.0],
+                    rotation: [0.0, 0.0, half, half],
+                    scale: [2.0; 3],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_lines() {
+        let message = |text| match parse_instances(text) {
+            Err(ConvertError::Parse(message)) => message,
+            result => panic!("unexpected result {result:?}"),
+        };
+        assert_eq!(
+            message("1 2 3\n0 0 0 0 0 0 0"),
+            "Line 2: the rotation quaternion is zero"
+        );
+        assert_eq!(message("1 2"), "Line 1: expected 3, 7 or 10 numbers");
+        assert_eq!(message("1 2 3 4 5"), "Line 1: expected 3, 7 or 10 numbers");
+        assert_eq!(message("1 x 3"), "Line 1: invalid number");
+        assert_eq!(message("1 inf 3"), "Line 1: invalid number");
+    }
+}
@@ -0,0 +1,416 @@
+//! Conversion of STL meshes to glTF/GLB, and back.
+
+use std::path::PathBuf;
+
+#[cfg(feature = "ao")]
+mod ao;
+mod bvh;
+mod convert;
+mod error;
+mod geometry;
+mod gltf_builder;
+mod instances;
+mod ply;
+mod simplify;
+
+pub use convert::{
+    gltf_to_stl, obj_to_gltf, ply_to_gltf, read_stl_bytes, read_stl_colors, read_stl_colors_bytes,
+    read_stl_file, read_stl_file_mmap, read_stl_solid_name, read_stl_triangle_count, stl_to_gltf,
+    stl_to_gltf_with_colors,
+};
+pub use error::ConvertError;
+pub use geometry::{
+    connected_components, fix_winding, is_watertight, point_inside, reindex_vertices,
+    weld_vertices, weld_vertices_within_components, InsideTest, ManifoldReport, MeshStats,
+};
+pub use gltf_builder::{GltfBuilder, ToLeBytes, ValidationError};
+pub use instances::read_instances_file;
+pub use simplify::simplify_mesh;
+
+/// Up axis of the STL geometry
+#[derive(Debug, Clone, Copy, clap::ValueEnum, PartialEq, Eq)]
+pub enum UpAxis {
+    Z,
+    Y,
+}
+
+/// How the face normals are weighted when averaging the vertex normals
+#[derive(Debug, Clone, Copy, clap::ValueEnum, PartialEq, Eq)]
+pub enum NormalMode {
+    /// Every incident face has the same weight. Uses the face normals of the input, or the
+    /// winding of the faces whose normal is zero.
+    Simple,
+    /// Faces are weighted by their area
+    Area,
+    /// Faces are weighted by their angle at the vertex
+    Angle,
+}
+
+/// Shading of the converted meshes
+#[derive(Debug, Clone, Copy, clap::ValueEnum, PartialEq, Eq)]
+pub enum ShadingMode {
+    /// Vertex normals averaged from the incident faces
+    Smooth,
+    /// Each face corner uses the normal of its face (implies a non-indexed output)
+    Flat,
+}
+
+/// Component type of the index accessor
+#[derive(Debug, Clone, Copy, clap::ValueEnum, PartialEq, Eq)]
+pub enum IndexType {
+    /// u16 when the mesh has at most 65535 vertices, u32 otherwise
+    Auto,
+    U16,
+    U32,
+}
+
+/// Face normals of the STL output
+#[derive(Debug, Clone, Copy, clap::ValueEnum, PartialEq, Eq)]
+pub enum StlNormalMode {
+    /// Normal of the triangle, from its edges in winding order
+    Winding,
+    /// Average of the vertex normals of the input, or the winding normal without them
+    Average,
+}
+
+/// Buffer views of the vertex positions and normals
+#[derive(Debug, Clone, Copy, clap::ValueEnum, PartialEq, Eq)]
+pub enum VertexLayout {
+    /// One tightly packed view for the positions, and one for the normals
+    Separate,
+    /// A single view, with the normal of each vertex after its position
+    Interleaved,
+}
+
+/// How the model is moved relative to the origin
+#[derive(Debug, Clone, Copy, clap::ValueEnum, PartialEq, Eq)]
+pub enum CenterMode {
+    /// Keep the original coordinates
+    None,
+    /// Move the center of the bounding box to the origin
+    Bbox,
+    /// Move the lowest point along the up axis to zero
+    Base,
+}
+
+/// Projection used to generate texture coordinates
+#[derive(Debug, Clone, Copy, clap::ValueEnum, PartialEq, Eq)]
+pub enum UvMode {
+    /// Project on the plane of the two largest dimensions of the bounding box
+    Planar,
+    /// Project each vertex on the plane facing the dominant axis of its normal
+    Box,
+}
+
+/// Integer type of the quantized vertex attributes (`KHR_mesh_quantization`)
+#[derive(Debug, Clone, Copy, clap::ValueEnum, PartialEq, Eq)]
+pub enum Quantization {
+    #[value(name = "8")]
+    I8,
+    #[value(name = "16")]
+    I16,
+}
+
+/// Where the baked ambient occlusion is stored
+#[derive(Debug, Clone, Copy, clap::ValueEnum, PartialEq, Eq)]
+pub enum AoTarget {
+    /// Gray `COLOR_0`, multiplied with the colors of the faces if any
+    Color,
+    /// Custom `_AMBIENT_OCCLUSION` scalar attribute
+    Attribute,
+}
+
+/// Per-vertex ambient occlusion baked by ray sampling. Requires the `ao` feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AmbientOcclusion {
+    /// Rays cast from each vertex
+    pub samples: u32,
+    pub target: AoTarget,
+}
+
+/// PBR material applied to the converted meshes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaterialOptions {
+    /// Linear RGBA base color
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Ignore the lighting (`KHR_materials_unlit`)
+    pub unlit: bool,
+    /// Render the back faces too (no backface culling)
+    pub double_sided: bool,
+    /// Linear RGB emitted color, in [0, 1]
+    pub emissive: [f32; 3],
+    /// Multiplier of the emitted color (`KHR_materials_emissive_strength`)
+    pub emissive_strength: Option<f32>,
+}
+
+impl Default for MaterialOptions {
+    fn default() -> Self {
+        // Same as the glTF defaults
+        Self {
+            base_color: [1.0, 1.0, 1.0, 1.0],
+            metallic: 1.0,
+            roughness: 1.0,
+            unlit: false,
+            double_sided: false,
+            emissive: [0.0, 0.0, 0.0],
+            emissive_strength: None,
+        }
+    }
+}
+
+/// Material variant (`KHR_materials_variants`), e.g. a color option of a product
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaterialVariant {
+    pub name: String,
+    /// Linear RGBA base color. The other parameters come from [`ConvertOptions::material`].
+    pub base_color: [f32; 4],
+}
+
+/// Transform of one instance of a mesh (`EXT_mesh_gpu_instancing`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InstanceTransform {
+    pub translation: [f32; 3],
+    /// Unit quaternion (x, y, z, w)
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+}
+
+impl Default for InstanceTransform {
+    fn default() -> Self {
+        Self {
+            translation: [0.0, 0.0, 0.0],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+            scale: [1.0, 1.0, 1.0],
+        }
+    }
+}
+
+/// Options of the conversion. Built with [`ConvertOptions::builder`], or by changing the fields
+/// of [`ConvertOptions::default`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ConvertOptions {
+    /// Up axis of the STL geometry. Z-up STL is rotated to/from the Y-up glTF convention.
+    pub up_axis: UpAxis,
+    /// Output an indexed mesh, or a triangle soup
+    pub indexed: bool,
+    /// Component type of the indices of an indexed mesh
+    pub index_type: IndexType,
+    /// Merge vertices with the same position, quantized to this precision (0 for exact positions)
+    pub reindex: Option<f32>,
+    /// Merge vertices closer than this distance
+    pub weld_tolerance: Option<f32>,
+    /// Only weld the vertices of the same connected component, so that separate shells
+    /// stay distinct
+    pub weld_within_components: bool,
+    /// Give each connected component of an STL mesh its own mesh and node, named
+    /// `<name>_<index>`
+    pub split_components: bool,
+    /// Reduce the number of triangles to about this fraction, in (0, 1]
+    pub simplify: Option<f32>,
+    /// Weighting of the face normals in the vertex normals
+    pub normals: NormalMode,
+    /// Uniform scale applied to the vertex positions of the input. Must be positive.
+    pub scale: f32,
+    /// Translation applied to the vertex positions of the input, after the scale
+    pub translate: [f32; 3],
+    pub shading: ShadingMode,
+    /// Use the face normals written in the STL file as is, without normalizing them, for the
+    /// three corners of each face (implies a non-indexed output)
+    pub stl_normals: bool,
+    /// Remove the triangles with a zero area. They are always ignored by the normals.
+    pub drop_degenerate: bool,
+    /// Reorder the triangles and vertices of indexed meshes for faster GPU rendering
+    pub optimize: bool,
+    /// Do not compute the vertex normals nor emit a NORMAL attribute
+    pub no_normals: bool,
+    /// Negate the vertex normals
+    pub flip_normals: bool,
+    /// Reverse the winding order of the triangles
+    pub flip_winding: bool,
+    /// Make the winding of the triangles consistent and outward-facing (see [`fix_winding`]),
+    /// before [`ConvertOptions::flip_winding`]
+    pub fix_winding: bool,
+    /// Hard edges between faces whose normals differ by more than this angle, in degrees.
+    /// 180 is fully smooth, 0 is flat.
+    pub crease_angle: Option<f32>,
+    /// Move the model relative to the origin, after the scale and translation
+    pub center: CenterMode,
+    /// Generate texture coordinates with this projection
+    pub uv: Option<UvMode>,
+    /// Store the positions and normals as normalized integers. The node of the mesh scales
+    /// them back.
+    pub quantize: Option<Quantization>,
+    /// Emit a TANGENT attribute. Requires texture coordinates.
+    pub tangents: bool,
+    /// Buffer views of the positions and normals. Quantized vertices are always separate.
+    pub layout: VertexLayout,
+    /// Also write the min/max of the normal and index accessors, not only of the positions
+    pub full_bounds: bool,
+    /// Experimental: when most normals are equal, write the NORMAL accessor as a sparse
+    /// accessor of the other ones, over a view of the modal normal. Only the float normals of
+    /// the separate layout.
+    pub sparse_normals: bool,
+    /// Store the bounding sphere of the positions in the extras of the mesh nodes:
+    /// `{"boundingSphere": {"center": [x, y, z], "radius": r}}`
+    pub bounding_sphere: bool,
+    /// Draw the meshes once per transform with `EXT_mesh_gpu_instancing`. The transforms are
+    /// in the frame of the input like the geometry, and their translations are scaled by `scale`.
+    pub instances: Option<Vec<InstanceTransform>>,
+    /// Face normals of the STL output
+    pub stl_face_normals: StlNormalMode,
+    /// Bake the ambient occlusion of the vertices. Requires the `ao` feature.
+    pub ambient_occlusion: Option<AmbientOcclusion>,
+    /// Write an empty scene instead of failing with `ConvertError::EmptyMesh`
+    pub allow_empty: bool,
+    /// Material of the meshes. No material is emitted when `None`.
+    pub material: Option<MaterialOptions>,
+    /// PNG or JPEG image embedded as the base color texture of the material
+    pub texture: Option<PathBuf>,
+    /// Give each object/group of an OBJ file its own material, with a base color taken in turn
+    /// from a palette. The other parameters come from `material`.
+    pub group_materials: bool,
+    /// Material variants the viewers can switch between, each with its own material
+    pub variants: Vec<MaterialVariant>,
+}
+
+impl ConvertOptions {
+    pub fn builder() -> ConvertOptionsBuilder {
+        ConvertOptionsBuilder::default()
+    }
+
+    /// Apply the scale and translation of the options to a point
+    pub(crate) fn transform_point(&self, p: [f32; 3]) -> [f32; 3] {
+        [0, 1, 2].map(|k| p[k] * self.scale + self.translate[k])
+    }
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        Self {
+            up_axis: UpAxis::Z,
+            indexed: true,
+            index_type: IndexType::Auto,
+            reindex: None,
+            weld_tolerance: None,
+            weld_within_components: false,
+            split_components: false,
+            simplify: None,
+            normals: NormalMode::Simple,
+            scale: 1.0,
+            translate: [0.0, 0.0, 0.0],
+            shading: ShadingMode::Smooth,
+            stl_normals: false,
+            crease_angle: None,
+            drop_degenerate: false,
+            optimize: false,
+            no_normals: false,
+            flip_normals: false,
+            flip_winding: false,
+            fix_winding: false,
+            center: CenterMode::None,
+            uv: None,
+            quantize: None,
+            tangents: false,
+            layout: VertexLayout::Separate,
+            full_bounds: false,
+            sparse_normals: false,
+            bounding_sphere: false,
+            instances: None,
+            stl_face_normals: StlNormalMode::Winding,
+            ambient_occlusion: None,
+            allow_empty: false,
+            material: None,
+            texture: None,
+            group_materials: false,
+            variants: Vec::new(),
+        }
+    }
+}
+
+/// Builder of [`ConvertOptions`], starting from the defaults
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptionsBuilder {
+    options: ConvertOptions,
+}
+
+impl ConvertOptionsBuilder {
+    /// Check the values of the options
+    pub fn build(self) -> Result<ConvertOptions, ConvertError> {
+        let options = self.options;
+        let invalid = |message: &str| Err(ConvertError::InvalidData(message.to_string()));
+        if !(options.scale > 0.0 && options.scale.is_finite()) {
+            return invalid("The scale must be positive");
+        }
+        if options.simplify.is_some_and(|it| !(it > 0.0 && it <= 1.0)) {
+            return invalid("The simplification ratio must be in (0, 1]");
+        }
+        let names = options.variants.iter().map(|it| &it.name);
+        if names.clone().any(|it| it.is_empty()) {
+            return invalid("The material variants must have a name");
+        }
+        if names.collect::<std::collections::HashSet<_>>().len() != options.variants.len() {
+            return invalid("The names of the material variants must be distinct");
+        }
+        if options.instances.as_ref().is_some_and(|it| it.is_empty()) {
+            return invalid("The list of instances must not be empty");
+        }
+        Ok(options)
+    }
+}
+
+/// A setter of the builder for each field of the options
+macro_rules! builder_setters {
+    ($($field:ident: $ty:ty,)*) => {
+        impl ConvertOptionsBuilder {
+            $(
+                #[doc = concat!("See [`ConvertOptions::", stringify!($field), "`]")]
+                pub fn $field(mut self, $field: $ty) -> Self {
+                    self.options.$field = $field;
+                    self
+                }
+            )*
+        }
+    };
+}
+
+builder_setters! {
+    up_axis: UpAxis,
+    indexed: bool,
+    index_type: IndexType,
+    reindex: Option<f32>,
+    weld_tolerance: Option<f32>,
+    weld_within_components: bool,
+    split_components: bool,
+    simplify: Option<f32>,
+    normals: NormalMode,
+    scale: f32,
+    translate: [f32; 3],
+    shading: ShadingMode,
+    stl_normals: bool,
+    drop_degenerate: bool,
+    optimize: bool,
+    no_normals: bool,
+    flip_normals: bool,
+    flip_winding: bool,
+    fix_winding: bool,
+    crease_angle: Option<f32>,
+    center: CenterMode,
+    uv: Option<UvMode>,
+    quantize: Option<Quantization>,
+    tangents: bool,
+    layout: VertexLayout,
+    full_bounds: bool,
+    sparse_normals: bool,
+    bounding_sphere: bool,
+    instances: Option<Vec<InstanceTransform>>,
+    stl_face_normals: StlNormalMode,
+    ambient_occlusion: Option<AmbientOcclusion>,
+    allow_empty: bool,
+    material: Option<MaterialOptions>,
+    texture: Option<PathBuf>,
+    group_materials: bool,
+    variants: Vec<MaterialVariant>,
+}
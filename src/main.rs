@@ -1,17 +1,28 @@
-mod gltf_builder;
-
-use clap::{clap_derive::ValueEnum, Parser};
+use clap::{clap_derive::ValueEnum, error::ErrorKind, CommandFactory, Parser};
+use conv3d::{
+    gltf_to_stl, obj_to_gltf, ply_to_gltf, read_instances_file, read_stl_bytes, read_stl_colors,
+    read_stl_colors_bytes, read_stl_file, read_stl_file_mmap, read_stl_solid_name,
+    read_stl_triangle_count, stl_to_gltf_with_colors, CenterMode, ConvertError, ConvertOptions,
+    GltfBuilder, IndexType, MaterialOptions, MaterialVariant, MeshStats, NormalMode, Quantization,
+    ShadingMode, StlNormalMode, UpAxis, UvMode, VertexLayout,
+};
+#[cfg(feature = "ao")]
+use conv3d::{AmbientOcclusion, AoTarget};
 use glob::glob;
-use gltf::json;
-use gltf_builder::GltfBuilder;
-use json::validation::Checked::Valid;
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use std::{
-    fs::{File, OpenOptions},
-    io::BufWriter,
-    path::Path,
+    fs::File,
+    io::{BufWriter, IsTerminal, Read, Write},
+    num::NonZeroUsize,
+    panic::AssertUnwindSafe,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
 };
-use stl_io::IndexedMesh;
+use walkdir::WalkDir;
 
 #[derive(Debug, Clone, ValueEnum, PartialEq, Eq, PartialOrd, Ord)]
 enum FileFormat {
@@ -28,208 +39,2354 @@ fn get_extension(format: FileFormat) -> &'static str {
     }
 }
 
+impl FileFormat {
+    /// Inverse of `get_extension`, ignoring the case
+    fn from_extension(extension: &str) -> Result<Self, String> {
+        match extension.to_lowercase().as_str() {
+            "stl" => Ok(FileFormat::Stl),
+            "gltf" => Ok(FileFormat::Gltf),
+            "glb" => Ok(FileFormat::Glb),
+            _ => Err(format!(
+                "unknown output extension \"{}\", expected stl, gltf or glb",
+                extension
+            )),
+        }
+    }
+}
+
+/// What to do when an output file already exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Overwrite {
+    Refuse,
+    Force,
+    Skip,
+}
+
+/// Result of a successful call to `convert_file`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Converted,
+    Overwritten,
+    /// The existing output files already had the converted content: they were not rewritten
+    Unchanged,
+    Skipped,
+    /// The input exceeds --max-triangles or --max-file-bytes
+    OverLimit,
+}
+
+/// Step at which the conversion of a file failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FailureKind {
+    /// Reading, parsing or converting the input
+    Read,
+    /// Validating the converted glTF
+    Validation,
+    /// Writing the output files
+    Write,
+    /// The conversion panicked
+    Crash,
+}
+
+impl FailureKind {
+    fn label(self) -> &'static str {
+        match self {
+            FailureKind::Read => "read",
+            FailureKind::Validation => "validation",
+            FailureKind::Write => "write",
+            FailureKind::Crash => "crash",
+        }
+    }
+}
+
+/// A file to convert. Files found in an input directory keep their path relative to it
+/// inside --output-dir.
+struct InputFile {
+    path: PathBuf,
+    base_dir: Option<PathBuf>,
+    /// STL file to convert inside the ZIP archive at `path`
+    member: Option<ZipMember>,
+}
+
+impl InputFile {
+    /// Path shown in the logs: `<archive>.zip/<member>` for the members of an archive
+    fn label(&self) -> PathBuf {
+        match &self.member {
+            Some(member) => self.path.join(&member.path),
+            None => self.path.clone(),
+        }
+    }
+}
+
+/// A file of a ZIP archive
+struct ZipMember {
+    index: usize,
+    /// Path inside the archive, which is mirrored inside the output directory
+    path: PathBuf,
+    /// Uncompressed size
+    size: u64,
+}
+
+/// Limits above which the input files are skipped, from --max-triangles and --max-file-bytes
+#[derive(Debug, Clone, Copy, Default)]
+struct InputLimits {
+    max_triangles: Option<u64>,
+    max_file_bytes: Option<u64>,
+}
+
+impl InputLimits {
+    fn is_set(&self) -> bool {
+        self.max_triangles.is_some() || self.max_file_bytes.is_some()
+    }
+
+    /// Reason to skip the file, checked before loading it: from its size, and for the STL
+    /// files from their triangle count. The members of a ZIP archive are checked on their
+    /// uncompressed size.
+    fn check_file(&self, input: &InputFile) -> Result<Option<String>, ConvertError> {
+        let path = &input.path;
+        if let Some(max) = self.max_file_bytes {
+            let len = match &input.member {
+                Some(member) => member.size,
+                None => std::fs::metadata(path)?.len(),
+            };
+            if len > max {
+                return Ok(Some(format!(
+                    "{} bytes, over --max-file-bytes {}",
+                    len, max
+                )));
+            }
+        }
+        if self.max_triangles.is_some() && input.member.is_none() && is_stl_input(path) {
+            return Ok(self.check_triangles(read_stl_triangle_count(path)?));
+        }
+        Ok(None)
+    }
+
+    /// Reason to skip a mesh of `triangles` triangles
+    fn check_triangles(&self, triangles: u64) -> Option<String> {
+        let max = self.max_triangles?;
+        (triangles > max).then(|| format!("{} triangles, over --max-triangles {}", triangles, max))
+    }
+}
+
+/// Input files larger than this get their own progress bar, showing the current stage
+const LARGE_FILE_BYTES: u64 = 16 << 20;
+
+/// Progress of the conversions: progress bars on a terminal, or lines of log
+struct Progress {
+    bars: Option<(MultiProgress, ProgressBar)>,
+}
+
+impl Progress {
+    fn new(enabled: bool, file_count: usize) -> Self {
+        let bars = enabled.then(|| {
+            let multi = MultiProgress::new();
+            let overall = multi.add(ProgressBar::new(file_count as u64));
+            overall.set_style(
+                ProgressStyle::with_template("[{elapsed_precise}] {wide_bar} {pos}/{len} files")
+                    .expect("Invalid progress template"),
+            );
+            (multi, overall)
+        });
+        Self { bars }
+    }
+
+    /// Log a message at the info level, above the progress bars
+    fn info(&self, message: String) {
+        match &self.bars {
+            Some((multi, _)) => multi.suspend(|| log::info!("{}", message)),
+            None => log::info!("{}", message),
+        }
+    }
+
+    /// Log a message at the warning level, above the progress bars
+    fn warn(&self, message: String) {
+        match &self.bars {
+            Some((multi, _)) => multi.suspend(|| log::warn!("{}", message)),
+            None => log::warn!("{}", message),
+        }
+    }
+
+    /// Start the conversion of a file. Large files get a bar showing their stage.
+    fn start_file(&self, path: &Path) -> FileProgress {
+        let large = std::fs::metadata(path).is_ok_and(|it| it.len() >= LARGE_FILE_BYTES);
+        let bar = match &self.bars {
+            Some((multi, _)) if large => {
+                let bar = multi.add(ProgressBar::new_spinner());
+                bar.set_style(
+                    ProgressStyle::with_template("{spinner} {prefix}: {msg}")
+                        .expect("Invalid progress template"),
+                );
+                bar.set_prefix(path.display().to_string());
+                bar.enable_steady_tick(Duration::from_millis(100));
+                Some(bar)
+            }
+            _ => None,
+        };
+        FileProgress { bar }
+    }
+
+    fn finish_file(&self) {
+        if let Some((_, overall)) = &self.bars {
+            overall.inc(1);
+        }
+    }
+
+    fn finish(&self) {
+        if let Some((_, overall)) = &self.bars {
+            overall.finish_and_clear();
+        }
+    }
+}
+
+/// Stage of the conversion of a large file. The bar is removed when dropped.
+struct FileProgress {
+    bar: Option<ProgressBar>,
+}
+
+impl FileProgress {
+    fn stage(&self, label: &'static str) {
+        if let Some(bar) = &self.bar {
+            bar.set_message(label);
+        }
+    }
+}
+
+impl Drop for FileProgress {
+    fn drop(&mut self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
+
 #[derive(Parser)]
 struct App {
     input_files: Vec<String>,
 
+    /// Formats of the converted files, e.g. `-o glb,gltf`: each input is parsed once for all of
+    /// them. Inferred from the extension of --output if omitted.
+    #[arg(short, long, value_delimiter = ',', required_unless_present = "output")]
+    output_format: Vec<FileFormat>,
+
+    /// Path of the converted file. Requires a single input.
+    /// The files of the gltf format are named after its stem: `<stem>.gltf` and `<stem>.bin`.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["output_dir", "base_dir", "stdout"])]
+    output: Option<PathBuf>,
+
+    /// Write the converted files into this directory instead of next to their input
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+
+    /// Preserve the input paths relative to this directory inside --output-dir
+    #[arg(long, requires = "output_dir")]
+    base_dir: Option<PathBuf>,
+
+    /// Also search the subdirectories of the input directories. The paths matching the
+    /// gitignore-style patterns of a `.conv3dignore` file in an input directory are skipped.
     #[arg(short, long)]
-    output_format: FileFormat,
+    recursive: bool,
+
+    /// Split the binary data of the glTF output into files of at most N bytes:
+    /// `<name>_0.bin`, `<name>_1.bin`... The data of a single attribute is never split.
+    #[arg(long, value_name = "N")]
+    max_buffer_bytes: Option<NonZeroUsize>,
+
+    /// Round the min/max bounds of the accessors to this number of decimals, outward
+    #[arg(long, value_name = "DECIMALS", value_parser = clap::value_parser!(u32).range(0..=9))]
+    float_precision: Option<u32>,
+
+    /// Copyright notice of the glTF output, stored in its `asset`
+    #[arg(long, value_name = "TEXT")]
+    copyright: Option<String>,
+
+    /// Name of the mesh, node and scene of the converted files. `{stem}` is replaced by the name
+    /// of the output file, `{parent}` by the directory of the input and `{index}` by its position
+    /// in the batch. By default, the STL solid name or the name of the output file.
+    #[arg(long, value_name = "TEMPLATE", value_parser = parse_name_template)]
+    name: Option<String>,
+
+    /// Write the GLB to stdout. Requires a single input and the glb format.
+    #[arg(long, conflicts_with_all = ["output_dir", "base_dir"])]
+    stdout: bool,
+
+    /// Print the planned conversions and the existing outputs, without writing anything
+    #[arg(long, conflicts_with = "stdout")]
+    dry_run: bool,
+
+    /// Validate the glTF before writing it. Invalid files are not written unless --force.
+    #[arg(long)]
+    validate: bool,
+
+    /// Overwrite the existing output files
+    #[arg(long, conflicts_with = "skip_existing")]
+    force: bool,
+
+    /// Leave the inputs whose output files already exist
+    #[arg(long)]
+    skip_existing: bool,
+
+    /// Up axis of the STL geometry. Z-up STL is rotated to/from the Y-up glTF convention.
+    #[arg(long, default_value = "z")]
+    up_axis: UpAxis,
+
+    /// Output an indexed mesh. Use `false` to output a triangle soup instead.
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    indexed: bool,
+
+    /// Component type of the indices. `auto` uses u16 when the mesh has at most 65535 vertices.
+    #[arg(long, default_value = "auto")]
+    index_type: IndexType,
+
+    /// Merge the vertices with identical positions, optionally quantized to a PRECISION.
+    /// Faster than --weld-tolerance.
+    #[arg(long, value_name = "PRECISION", num_args = 0..=1, default_missing_value = "0")]
+    reindex: Option<f32>,
+
+    /// Merge vertices closer than this distance
+    #[arg(long)]
+    weld_tolerance: Option<f32>,
+
+    /// Only weld the vertices of the same connected component (faces sharing vertices), so that
+    /// separate parts closer than --weld-tolerance stay distinct
+    #[arg(long, requires = "weld_tolerance")]
+    weld_within_components: bool,
+
+    /// Convert each connected component of the STL inputs (faces sharing vertices) to its own
+    /// mesh and node, named `<name>_<index>`
+    #[arg(long)]
+    split_components: bool,
+
+    /// Reduce the number of triangles to about this fraction (e.g. 0.5), with edge collapses
+    /// that preserve the shape. The vertex normals are recomputed.
+    #[arg(long, value_name = "RATIO", value_parser = parse_ratio)]
+    simplify: Option<f32>,
+
+    /// Weighting of the face normals when computing the vertex normals
+    #[arg(long, default_value = "simple")]
+    normals: NormalMode,
+
+    /// Scale the vertex positions (e.g. 0.001 to convert millimeters to meters)
+    #[arg(long, default_value_t = 1.0, value_parser = parse_scale)]
+    scale: f32,
+
+    /// Translate the vertex positions by X,Y,Z, after the scale
+    #[arg(long, value_parser = parse_vec3, default_value = "0,0,0")]
+    translate: [f32; 3],
+
+    /// Move the model to the origin
+    #[arg(long, default_value = "none")]
+    center: CenterMode,
+
+    /// Generate texture coordinates by projecting the vertices
+    #[arg(long)]
+    uv: Option<UvMode>,
+
+    /// Store the positions and normals as 8 or 16 bit integers (KHR_mesh_quantization)
+    #[arg(long, value_name = "BITS")]
+    quantize: Option<Quantization>,
+
+    /// Buffer views of the positions and normals: one each, or a single interleaved view.
+    /// Quantized vertices are always separate.
+    #[arg(long, default_value = "separate")]
+    layout: VertexLayout,
+
+    /// Also write the min/max of the normal and index accessors (an extra pass over them),
+    /// for the importers that expect them
+    #[arg(long)]
+    full_bounds: bool,
+
+    /// Experimental: when most normals are equal, store the others apart (glTF sparse
+    /// accessor). Ignored for quantized and interleaved vertices.
+    #[arg(long)]
+    sparse_normals: bool,
+
+    /// Store the bounding sphere of each mesh in the extras of its node, for the engines
+    /// culling with spheres
+    #[arg(long)]
+    bounding_sphere: bool,
+
+    /// Draw each mesh once per transform of FILE (EXT_mesh_gpu_instancing). One transform per
+    /// line: tx,ty,tz[,qx,qy,qz,qw[,sx,sy,sz]], in the frame and units of the input.
+    #[arg(long, value_name = "FILE")]
+    instances: Option<PathBuf>,
+
+    /// Face normals of the STL output: from the winding of the triangles, or the average of
+    /// the vertex normals of the glTF input
+    #[arg(long, default_value = "winding")]
+    stl_normal: StlNormalMode,
+
+    /// Compute vertex tangents (requires texture coordinates)
+    #[arg(long)]
+    tangents: bool,
+
+    /// Bake per-vertex ambient occlusion into the vertex colors or a custom attribute
+    #[cfg(feature = "ao")]
+    #[arg(long, value_name = "TARGET", num_args = 0..=1, default_missing_value = "color")]
+    bake_ao: Option<AoTarget>,
+
+    /// Rays cast from each vertex by --bake-ao
+    #[cfg(feature = "ao")]
+    #[arg(long, value_name = "N", default_value_t = 32, requires = "bake_ao")]
+    ao_samples: u32,
+
+    /// Smooth or flat (faceted) shading
+    #[arg(long, default_value = "smooth")]
+    shading: ShadingMode,
+
+    /// Use the face normals of the STL file verbatim instead of computing them
+    /// (implies a non-indexed output)
+    #[arg(long, conflicts_with_all = ["shading", "crease_angle"])]
+    use_stl_normals: bool,
+
+    /// Split the smooth shading on edges sharper than this angle, in degrees
+    #[arg(long)]
+    crease_angle: Option<f32>,
+
+    /// Remove the triangles with a zero area (collinear vertices)
+    #[arg(long)]
+    drop_degenerate: bool,
+
+    /// Write an empty scene for the inputs without any valid triangle, instead of failing
+    #[arg(long)]
+    allow_empty: bool,
+
+    /// Optimize the order of the triangles and vertices for the GPU caches (indexed output only)
+    #[arg(long)]
+    optimize: bool,
+
+    /// Do not compute the vertex normals nor write them (e.g. with --unlit)
+    #[arg(long)]
+    no_normals: bool,
+
+    /// Negate the normals (for models whose normals point inward)
+    #[arg(long)]
+    flip_normals: bool,
+
+    /// Reverse the winding order of the triangles
+    #[arg(long)]
+    flip_winding: bool,
+
+    /// Make the winding of the triangles consistent across the shared edges and outward-facing
+    #[arg(long)]
+    fix_winding: bool,
+
+    /// Base color of the material, as linear R,G,B[,A] in [0, 1]
+    #[arg(long, value_parser = parse_color)]
+    color: Option<[f32; 4]>,
+
+    /// Metallic factor of the material
+    #[arg(long)]
+    metallic: Option<f32>,
+
+    /// Roughness factor of the material
+    #[arg(long)]
+    roughness: Option<f32>,
+
+    /// Apply a PNG or JPEG image as base color texture, using the generated texture coordinates
+    #[arg(long, requires = "uv")]
+    texture: Option<PathBuf>,
+
+    /// Give each OBJ object/group its own material, colored from a palette (overrides --color)
+    #[arg(long)]
+    group_materials: bool,
+
+    /// Material variants the viewers can switch between (KHR_materials_variants),
+    /// as NAME=R,G,B[,A] base colors, e.g. `--variants red=1,0,0 blue=0,0,1`
+    #[arg(long, num_args = 1.., value_parser = parse_variant)]
+    variants: Vec<MaterialVariant>,
+
+    /// Render the surfaces with their base color only, without lighting (KHR_materials_unlit)
+    #[arg(long)]
+    unlit: bool,
+
+    /// Render both sides of the faces (for thin parts with inconsistent winding)
+    #[arg(long)]
+    double_sided: bool,
+
+    /// Emitted color of the material, as linear R,G,B in [0, 1]
+    #[arg(long, value_parser = parse_emissive)]
+    emissive: Option<[f32; 3]>,
+
+    /// Multiplier of the emitted color, for colors brighter than 1 (KHR_materials_emissive_strength)
+    #[arg(long, requires = "emissive", value_parser = parse_emissive_strength)]
+    emissive_strength: Option<f32>,
+
+    /// Memory-map the STL input files instead of reading them through a buffer
+    #[arg(long)]
+    mmap: bool,
+
+    /// Number of files converted in parallel (defaults to the number of logical cores).
+    /// Each file in flight holds its whole mesh in memory: lower it for very large inputs.
+    /// The vertex normals of large meshes are also computed with these threads.
+    #[arg(short, long)]
+    jobs: Option<NonZeroUsize>,
+
+    /// Split the --jobs threads between files and within files: each file is converted by its
+    /// own pool of at least N threads, with --jobs / N files in parallel. The threads left when
+    /// there are fewer files go within the files, e.g. a single file gets all of them.
+    /// N is capped to --jobs: larger values convert one file at a time with --jobs threads.
+    /// By default, all the threads are shared by the files and the work within them.
+    #[arg(long, value_name = "N")]
+    threads_per_file: Option<NonZeroUsize>,
+
+    /// Only convert the first N input files
+    #[arg(long)]
+    limit: Option<usize>,
+
+    /// Skip the input files with more triangles. The count is read from the header of binary STL
+    /// files, and by scanning ASCII STL files, before their mesh is loaded.
+    #[arg(long, value_name = "N", conflicts_with = "stdout")]
+    max_triangles: Option<u64>,
+
+    /// Skip the input files larger than N bytes (before decompression)
+    #[arg(long, value_name = "N", conflicts_with = "stdout")]
+    max_file_bytes: Option<u64>,
+
+    /// Shuffle the input files with the given seed (before applying --limit).
+    /// Otherwise the files are converted in path order.
+    #[arg(long)]
+    shuffle_seed: Option<u64>,
+
+    /// Print the triangle count, vertex count, size and watertightness of each output file,
+    /// and the totals of the batch
+    #[arg(long, conflicts_with = "stdout")]
+    stats: bool,
+
+    /// Write the statistics of the output files as JSON to FILE
+    #[arg(long, value_name = "FILE", conflicts_with = "stdout")]
+    stats_json: Option<PathBuf>,
+
+    /// Write a JSON manifest listing the converted files, with their path relative to FILE,
+    /// their bounding box and their triangle count
+    #[arg(long, value_name = "FILE", conflicts_with = "stdout")]
+    manifest: Option<PathBuf>,
+
+    /// Log one line per file instead of showing progress bars (always the case when stderr
+    /// is not a terminal)
+    #[arg(long)]
+    no_progress: bool,
+
+    /// Log more details: `-v` for debug, `-vv` for trace. `RUST_LOG` replaces these levels when
+    /// set, e.g. `RUST_LOG=warn` to only log the warnings and errors.
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+}
+
+/// Parse a `R,G,B[,A]` color
+fn parse_color(s: &str) -> Result<[f32; 4], String> {
+    let values = s
+        .split(',')
+        .map(|it| it.trim().parse::<f32>().map_err(|err| err.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    match values[..] {
+        [r, g, b] => Ok([r, g, b, 1.0]),
+        [r, g, b, a] => Ok([r, g, b, a]),
+        _ => Err("expected R,G,B or R,G,B,A".to_string()),
+    }
+}
+
+/// Parse a `NAME=R,G,B[,A]` material variant
+fn parse_variant(s: &str) -> Result<MaterialVariant, String> {
+    let (name, color) = s
+        .split_once('=')
+        .ok_or_else(|| "expected NAME=R,G,B[,A]".to_string())?;
+    Ok(MaterialVariant {
+        name: name.trim().to_string(),
+        base_color: parse_color(color)?,
+    })
+}
+
+/// Parse a `R,G,B` emitted color in [0, 1]
+fn parse_emissive(s: &str) -> Result<[f32; 3], String> {
+    let color = parse_vec3(s).map_err(|_| "expected R,G,B".to_string())?;
+    if color.iter().any(|it| !(0.0..=1.0).contains(it)) {
+        return Err("the components must be in [0, 1]".to_string());
+    }
+    Ok(color)
+}
+
+/// Parse a non-negative emissive strength
+fn parse_emissive_strength(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(strength) if strength >= 0.0 && strength.is_finite() => Ok(strength),
+        Ok(_) => Err("the strength must be positive".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Parse a `X,Y,Z` vector
+fn parse_vec3(s: &str) -> Result<[f32; 3], String> {
+    let values = s
+        .split(',')
+        .map(|it| it.trim().parse::<f32>().map_err(|err| err.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    values.try_into().map_err(|_| "expected X,Y,Z".to_string())
+}
+
+/// Parse a fraction in (0, 1]
+fn parse_ratio(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(ratio) if ratio > 0.0 && ratio <= 1.0 => Ok(ratio),
+        Ok(_) => Err("the ratio must be in (0, 1]".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Check that the `{...}` tokens of a --name template are known
+fn parse_name_template(s: &str) -> Result<String, String> {
+    expand_name_template(s, "", "", 0).map(|_| s.to_string())
+}
+
+/// Replace the `{stem}`, `{parent}` and `{index}` tokens of a --name template
+fn expand_name_template(
+    template: &str,
+    stem: &str,
+    parent: &str,
+    index: usize,
+) -> Result<String, String> {
+    let mut name = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        name.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed `{{` in {}", template))?;
+        match &rest[start + 1..start + end] {
+            "stem" => name.push_str(stem),
+            "parent" => name.push_str(parent),
+            "index" => name.push_str(&index.to_string()),
+            token => return Err(format!("unknown token {{{}}}", token)),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    name.push_str(rest);
+    Ok(name)
+}
+
+/// Name of the converted mesh of the `index`th input, from the --name template
+fn mesh_name(template: Option<&str>, path: &Path, outpath: &Path, index: usize) -> Option<String> {
+    let stem = outpath.file_stem().unwrap_or_default().to_string_lossy();
+    let parent = path
+        .parent()
+        .and_then(|it| it.file_name())
+        .unwrap_or_default()
+        .to_string_lossy();
+    // The template was checked when parsing the arguments
+    template.and_then(|it| expand_name_template(it, &stem, &parent, index).ok())
+}
+
+/// Log the messages of conv3d to stderr, one line each. The info and warning messages are
+/// written as is, the others prefixed by their level.
+fn init_logger(verbose: u8) {
+    logger_builder(verbose, std::env::var("RUST_LOG").ok().as_deref()).init();
+}
+
+/// Logger of the messages of conv3d at the --verbose level, unless RUST_LOG `filters` are given
+fn logger_builder(verbose: u8, filters: Option<&str>) -> env_logger::Builder {
+    let level = match verbose {
+        0 => log::LevelFilter::Info,
+        1 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    };
+    let mut builder = env_logger::Builder::new();
+    match filters {
+        Some(filters) => builder.parse_filters(filters),
+        None => builder
+            .filter_level(log::LevelFilter::Warn)
+            .filter_module("conv3d", level),
+    };
+    builder.format(|buf, record| match record.level() {
+        log::Level::Info | log::Level::Warn => writeln!(buf, "{}", record.args()),
+        level => writeln!(buf, "[{}] {}", level, record.args()),
+    });
+    builder
+}
+
+/// Parse a strictly positive scale
+fn parse_scale(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(scale) if scale > 0.0 && scale.is_finite() => Ok(scale),
+        Ok(_) => Err("the scale must be positive".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Material of the material flags, if any is given. The others keep the glTF defaults.
+fn material_options(app: &App) -> Option<MaterialOptions> {
+    (app.color.is_some()
+        || app.metallic.is_some()
+        || app.roughness.is_some()
+        || app.unlit
+        || app.double_sided
+        || app.emissive.is_some())
+    .then(|| {
+        let default = MaterialOptions::default();
+        MaterialOptions {
+            base_color: app.color.unwrap_or(default.base_color),
+            metallic: app.metallic.unwrap_or(default.metallic),
+            roughness: app.roughness.unwrap_or(default.roughness),
+            unlit: app.unlit,
+            double_sided: app.double_sided,
+            emissive: app.emissive.unwrap_or(default.emissive),
+            emissive_strength: app.emissive_strength,
+        }
+    })
 }
 
 fn main() {
     let app = App::parse();
+    init_logger(app.verbose);
+    // The explicit formats override the extension of --output
+    let mut output_formats = app.output_format.clone();
+    output_formats.sort();
+    output_formats.dedup();
+    if output_formats.is_empty() {
+        let output = app
+            .output
+            .as_ref()
+            .expect("--output-format is required without --output");
+        let extension = output.extension().unwrap_or_default().to_string_lossy();
+        output_formats.push(
+            FileFormat::from_extension(&extension).unwrap_or_else(|err| {
+                App::command()
+                    .error(ErrorKind::InvalidValue, format!("--output: {}", err))
+                    .exit()
+            }),
+        );
+    }
+    if output_formats.len() > 1 {
+        // The glTF formats are written from the same loaded scene, STL is converted from glTF
+        if output_formats.contains(&FileFormat::Stl) {
+            App::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "the stl output format cannot be combined with the glTF formats",
+                )
+                .exit();
+        }
+        if app.output.is_some() {
+            App::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--output requires a single output format",
+                )
+                .exit();
+        }
+    }
+    // The formats accept the same inputs, see the check above
+    let input_format = &output_formats[0];
 
-    println!("{:?} {:?}", app.output_format, app.input_files);
+    // With --stdout, only the GLB is written to stdout
+    if !app.stdout {
+        log::info!("{:?} {:?}", output_formats, app.input_files);
+    }
 
+    // Bad patterns and unreadable matches are reported with the failed conversions
+    let mut failures = Vec::new();
     let mut input_files = Vec::new();
-    for pattern in app.input_files {
-        for entry in glob(&pattern)
-            .unwrap_or_else(|_| panic!("Unable to read pattern: {}", &pattern))
-            .flatten()
-        {
-            input_files.push(entry);
-        }
-    }
-
-    input_files.par_iter().for_each(|path| {
-        let mut file = OpenOptions::new()
-            .read(true)
-            .open(path)
-            .unwrap_or_else(|_| panic!("Unable to open {}", path.display()));
-        let stl = stl_io::read_stl(&mut file)
-            .unwrap_or_else(|_| panic!("Unable to parse {}", path.display()));
-        println!("Parsed {}", path.display());
-
-        let mut outpath = path.clone();
-        outpath.set_extension(get_extension(app.output_format.to_owned()));
-        if outpath != *path {
-            let gltf =
-                if app.output_format == FileFormat::Glb || app.output_format == FileFormat::Gltf {
-                    convert_stl_to_gltf(stl, path).unwrap()
-                } else {
-                    unimplemented!()
-                };
-            let file = File::create(outpath.clone()).unwrap();
-            let writer = BufWriter::new(file);
-            if app.output_format == FileFormat::Glb {
-                let glb = gltf.to_glb().unwrap();
-                glb.to_writer(writer).unwrap();
-            } else if app.output_format == FileFormat::Gltf {
-                let file = File::create(outpath.clone()).unwrap();
-                let writer = BufWriter::new(file);
-                let mut gltf = gltf.merge_gltf_buffers().unwrap();
-                gltf.set_buffer_uri(
-                    0,
-                    Some(format!(
-                        "{}.bin",
-                        outpath
-                            .file_stem()
-                            .unwrap_or_default()
-                            .to_str()
-                            .unwrap_or_default()
-                    )),
-                )
-                .unwrap();
-                gltf.write_to_gltf(writer).unwrap();
-                gltf.write_all_buffers(outpath.parent().unwrap_or(Path::new(".")))
-                    .unwrap();
+    for pattern in &app.input_files {
+        let entries = match glob(pattern) {
+            Ok(entries) => entries,
+            Err(err) => {
+                log::warn!("Invalid pattern {}: {}", pattern, err);
+                failures.push((
+                    PathBuf::from(pattern),
+                    FailureKind::Read,
+                    ConvertError::InvalidData(format!("Invalid pattern: {}", err)),
+                ));
+                continue;
+            }
+        };
+        let mut matched = false;
+        for entry in entries {
+            matched = true;
+            match entry {
+                Ok(path) if path.is_dir() => collect_directory(
+                    &path,
+                    app.recursive,
+                    input_format,
+                    &mut input_files,
+                    &mut failures,
+                ),
+                Ok(path) if is_zip(&path) => {
+                    collect_zip(&path, input_format, &mut input_files, &mut failures)
+                }
+                Ok(path) => input_files.push(InputFile {
+                    path,
+                    base_dir: None,
+                    member: None,
+                }),
+                Err(err) => {
+                    log::warn!("Unable to read {}: {}", err.path().display(), err.error());
+                    let path = err.path().to_path_buf();
+                    failures.push((path, FailureKind::Read, std::io::Error::from(err).into()));
+                }
             }
-
-            println!("Output: {}", outpath.display());
         }
+        if !matched {
+            log::warn!("Warning: no file matches {}", pattern);
+        }
+    }
+
+    // Sorted so that the batch does not depend on the order of the patterns. A file matched
+    // twice is converted once, with the base directory of its first match.
+    input_files.sort_by_key(InputFile::label);
+    input_files.dedup_by(|a, b| a.label() == b.label());
+
+    if let Some(seed) = app.shuffle_seed {
+        log::info!("Shuffle seed: {}", seed);
+        input_files.shuffle(&mut StdRng::seed_from_u64(seed));
+    }
+    if let Some(limit) = app.limit {
+        input_files.truncate(limit);
+    }
+
+    let material = material_options(&app);
+    let instances = app.instances.as_ref().map(|path| {
+        read_instances_file(path).unwrap_or_else(|err| {
+            App::command()
+                .error(
+                    ErrorKind::InvalidValue,
+                    format!("{}: {}", path.display(), err),
+                )
+                .exit()
+        })
+    });
+    let options = ConvertOptions::builder()
+        .up_axis(app.up_axis)
+        .indexed(app.indexed)
+        .index_type(app.index_type)
+        .reindex(app.reindex)
+        .weld_tolerance(app.weld_tolerance)
+        .weld_within_components(app.weld_within_components)
+        .split_components(app.split_components)
+        .simplify(app.simplify)
+        .normals(app.normals)
+        .shading(app.shading)
+        .stl_normals(app.use_stl_normals)
+        .crease_angle(app.crease_angle)
+        .drop_degenerate(app.drop_degenerate)
+        .optimize(app.optimize)
+        .no_normals(app.no_normals)
+        .flip_normals(app.flip_normals)
+        .flip_winding(app.flip_winding)
+        .fix_winding(app.fix_winding)
+        .scale(app.scale)
+        .translate(app.translate)
+        .center(app.center)
+        .uv(app.uv)
+        .quantize(app.quantize)
+        .tangents(app.tangents)
+        .layout(app.layout)
+        .full_bounds(app.full_bounds)
+        .sparse_normals(app.sparse_normals)
+        .bounding_sphere(app.bounding_sphere)
+        .instances(instances)
+        .stl_face_normals(app.stl_normal)
+        .material(material)
+        .texture(app.texture.clone())
+        .group_materials(app.group_materials)
+        .variants(app.variants.clone())
+        .allow_empty(app.allow_empty);
+    #[cfg(feature = "ao")]
+    let options = options.ambient_occlusion(app.bake_ao.map(|target| AmbientOcclusion {
+        samples: app.ao_samples,
+        target,
+    }));
+    let options = options.build().unwrap_or_else(|err| {
+        App::command()
+            .error(ErrorKind::InvalidValue, err.to_string())
+            .exit()
     });
+
+    if app.output.is_some() && (input_files.len() != 1 || !failures.is_empty()) {
+        App::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                format!(
+                    "--output requires a single input file, got {}",
+                    input_files.len() + failures.len()
+                ),
+            )
+            .exit();
+    }
+
+    if app.stdout {
+        if output_formats != [FileFormat::Glb] {
+            App::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--stdout requires the glb output format",
+                )
+                .exit();
+        }
+        if input_files.iter().any(|it| it.member.is_some()) {
+            App::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--stdout does not support ZIP archives",
+                )
+                .exit();
+        }
+        if input_files.len() != 1 || !failures.is_empty() {
+            App::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!(
+                        "--stdout requires a single input file, got {}",
+                        input_files.len() + failures.len()
+                    ),
+                )
+                .exit();
+        }
+        if let Err(err) = convert_to_stdout(
+            &input_files[0].path,
+            app.name.as_deref(),
+            app.mmap,
+            app.validate,
+            app.force,
+            app.float_precision,
+            app.copyright.as_deref(),
+            &options,
+        ) {
+            log::error!("{}: {}", input_files[0].path.display(), err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let overwrite = if app.force {
+        Overwrite::Force
+    } else if app.skip_existing {
+        Overwrite::Skip
+    } else {
+        Overwrite::Refuse
+    };
+
+    if app.dry_run {
+        dry_run(
+            &app,
+            &output_formats,
+            &input_files,
+            overwrite,
+            &mut failures,
+        );
+        if !failures.is_empty() {
+            print_failures(&failures);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let limits = InputLimits {
+        max_triangles: app.max_triangles,
+        max_file_bytes: app.max_file_bytes,
+    };
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(app.jobs.map_or(0, NonZeroUsize::get))
+        .build()
+        .expect("Unable to create the thread pool");
+    let progress = Progress::new(
+        !app.no_progress && std::io::stderr().is_terminal(),
+        input_files.len(),
+    );
+    let convert_input = |index: usize, input: &InputFile| {
+        let path = &input.label();
+        let outputs = output_formats
+            .iter()
+            .map(|format| (format.clone(), input_output_path(&app, format, input)))
+            .collect::<Vec<_>>();
+        let name = mesh_name(app.name.as_deref(), path, &outputs[0].1, index);
+        // A panic only fails the file being converted
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            convert_file(
+                input,
+                &outputs,
+                name.as_deref(),
+                app.mmap,
+                app.validate,
+                app.max_buffer_bytes.map(NonZeroUsize::get),
+                app.float_precision,
+                app.copyright.as_deref(),
+                overwrite,
+                app.stats || app.stats_json.is_some() || app.manifest.is_some(),
+                limits,
+                &options,
+                &progress,
+            )
+        }))
+        .unwrap_or_else(|panic| {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|it| it.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Err((
+                FailureKind::Crash,
+                ConvertError::InvalidData(format!("panicked: {}", message)),
+            ))
+        });
+        progress.finish_file();
+        (path.clone(), outputs, result)
+    };
+    let results = match app.threads_per_file {
+        Some(threads) => convert_split(
+            &input_files,
+            pool.current_num_threads(),
+            threads.get(),
+            &convert_input,
+        ),
+        None => pool.install(|| {
+            input_files
+                .par_iter()
+                .enumerate()
+                .map(|(index, input)| convert_input(index, input))
+                .collect::<Vec<_>>()
+        }),
+    };
+    progress.finish();
+
+    let mut outcomes = Vec::new();
+    let mut stats = Vec::new();
+    let mut sources = Vec::new();
+    for (path, outputs, result) in results {
+        match result {
+            Ok((outcome, file_stats)) => {
+                outcomes.push(outcome);
+                if let Some(file_stats) = file_stats {
+                    for (_, outpath) in outputs {
+                        stats.push((outpath, file_stats));
+                        sources.push(path.clone());
+                    }
+                }
+            }
+            Err((kind, err)) => failures.push((path, kind, err)),
+        }
+    }
+    let count = |outcome| outcomes.iter().filter(|&&it| it == outcome).count();
+    let over_limit = if limits.is_set() {
+        format!(", {} over the limits", count(Outcome::OverLimit))
+    } else {
+        String::new()
+    };
+    println!(
+        "{} converted, {} overwritten, {} unchanged, {} skipped{}",
+        count(Outcome::Converted),
+        count(Outcome::Overwritten),
+        count(Outcome::Unchanged),
+        count(Outcome::Skipped),
+        over_limit
+    );
+    if app.stats {
+        print_stats(&stats);
+    }
+    if let Some(stats_path) = &app.stats_json {
+        if let Err(err) = write_stats_json(&stats, stats_path) {
+            log::error!("Unable to write {}: {}", stats_path.display(), err);
+            failures.push((stats_path.clone(), FailureKind::Write, err));
+        }
+    }
+    if let Some(manifest_path) = &app.manifest {
+        if let Err(err) = write_manifest(&sources, &stats, manifest_path) {
+            log::error!("Unable to write {}: {}", manifest_path.display(), err);
+            failures.push((manifest_path.clone(), FailureKind::Write, err));
+        }
+    }
+
+    if !failures.is_empty() {
+        print_failures(&failures);
+        std::process::exit(1);
+    }
 }
 
-#[derive(Copy, Clone, Debug)]
-#[repr(C)]
-struct V3 {
-    v: [f32; 3],
+/// Convert the inputs with `total` threads: each worker converts one file at a time with its own
+/// pool of at least `threads_per_file` threads, capped to `total`.
+/// Returns the results in the order of the inputs.
+fn convert_split<I: Sync, R: Send>(
+    inputs: &[I],
+    total: usize,
+    threads_per_file: usize,
+    convert: &(impl Fn(usize, &I) -> R + Sync),
+) -> Vec<R> {
+    let threads_per_file = threads_per_file.clamp(1, total.max(1));
+    let workers = (total / threads_per_file).clamp(1, inputs.len().max(1));
+    let threads = (total / workers).max(1);
+    log::info!(
+        "{} file(s) in parallel, with {} thread(s) each",
+        workers,
+        threads
+    );
+    let next = AtomicUsize::new(0);
+    let mut results = std::thread::scope(|scope| {
+        let workers = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let pool = rayon::ThreadPoolBuilder::new()
+                        .num_threads(threads)
+                        .build()
+                        .expect("Unable to create the thread pool");
+                    let mut results = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(input) = inputs.get(index) else {
+                            break;
+                        };
+                        results.push((index, pool.install(|| convert(index, input))));
+                    }
+                    results
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .flat_map(|it| {
+                it.join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect::<Vec<_>>()
+    });
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
 }
 
-/// Calculate bounding coordinates of a list of vertices, used for the clipping distance of the model
-fn bounding_coords(points: &[V3]) -> ([f32; 3], [f32; 3]) {
-    let mut min = [f32::MAX, f32::MAX, f32::MAX];
-    let mut max = [f32::MIN, f32::MIN, f32::MIN];
+/// Print the failed files, grouped by the step at which they failed
+fn print_failures(failures: &[(PathBuf, FailureKind, ConvertError)]) {
+    eprintln!("{} file(s) failed:", failures.len());
+    for kind in [
+        FailureKind::Read,
+        FailureKind::Validation,
+        FailureKind::Write,
+        FailureKind::Crash,
+    ] {
+        let mut failed = failures
+            .iter()
+            .filter(|it| it.1 == kind)
+            .collect::<Vec<_>>();
+        failed.sort_by(|a, b| a.0.cmp(&b.0));
+        if failed.is_empty() {
+            continue;
+        }
+        eprintln!("  {} {} error(s):", failed.len(), kind.label());
+        for (path, _, err) in failed {
+            eprintln!("    {}: {}", path.display(), err);
+        }
+    }
+}
 
-    for point in points {
-        let p = point.v;
-        for i in 0..3 {
-            min[i] = f32::min(min[i], p[i]);
-            max[i] = f32::max(max[i], p[i]);
+/// Print the planned `input -> output` conversions without writing anything.
+/// The inputs that cannot be read and the outputs that would be refused are added to `failures`.
+fn dry_run(
+    app: &App,
+    output_formats: &[FileFormat],
+    input_files: &[InputFile],
+    overwrite: Overwrite,
+    failures: &mut Vec<(PathBuf, FailureKind, ConvertError)>,
+) {
+    let mut outcomes = Vec::new();
+    for input in input_files {
+        let path = &input.label();
+        let outpaths = output_formats
+            .iter()
+            .map(|format| (format, input_output_path(app, format, input)))
+            .collect::<Vec<_>>();
+        let mapping = format!(
+            "{} -> {}",
+            path.display(),
+            outpaths
+                .iter()
+                .map(|(_, outpath)| outpath.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        if outpaths.iter().any(|(_, outpath)| outpath == path) {
+            println!("{} (skipped: same file)", mapping);
+            outcomes.push(Outcome::Skipped);
+            continue;
+        }
+        if let Err(err) = check_readable(&input.path) {
+            println!("{}: {}", path.display(), err);
+            failures.push((path.clone(), FailureKind::Read, err));
+            continue;
+        }
+        let outputs = outpaths
+            .iter()
+            .flat_map(|(format, outpath)| {
+                output_files(outpath, format, app.max_buffer_bytes.map(NonZeroUsize::get))
+            })
+            .collect::<Vec<_>>();
+        match (outputs.iter().find(|it| it.exists()), overwrite) {
+            (None, _) => {
+                println!("{}", mapping);
+                outcomes.push(Outcome::Converted);
+            }
+            (Some(existing), Overwrite::Force) => {
+                println!("{} (overwrites {})", mapping, existing.display());
+                outcomes.push(Outcome::Overwritten);
+            }
+            (Some(existing), Overwrite::Skip) => {
+                println!("{} (skipped: {} exists)", mapping, existing.display());
+                outcomes.push(Outcome::Skipped);
+            }
+            (Some(existing), Overwrite::Refuse) => {
+                let message = format!(
+                    "{} already exists, use --force to overwrite it",
+                    existing.display()
+                );
+                println!("{} (error: {})", mapping, message);
+                let err = std::io::Error::new(std::io::ErrorKind::AlreadyExists, message);
+                failures.push((path.clone(), FailureKind::Write, err.into()));
+            }
         }
     }
-    (min, max)
+    let count = |outcome| outcomes.iter().filter(|&&it| it == outcome).count();
+    println!(
+        "Dry run: {} to convert, {} to overwrite, {} to skip",
+        count(Outcome::Converted),
+        count(Outcome::Overwritten),
+        count(Outcome::Skipped)
+    );
+}
+
+/// Check that the input can be opened and is not empty, without parsing it
+fn check_readable(path: &Path) -> Result<(), ConvertError> {
+    let mut file = File::open(path)?;
+    let mut first = [0u8; 1];
+    if file.read(&mut first)? == 0 {
+        return Err(ConvertError::InvalidData("Empty file".to_string()));
+    }
+    Ok(())
+}
+
+/// Whether the file can be converted to `output_format`, from its extension
+fn is_input_file(path: &Path, output_format: &FileFormat) -> bool {
+    let extension = |path: &Path| {
+        path.extension()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_lowercase()
+    };
+    match (output_format, extension(path).as_str()) {
+        (FileFormat::Stl, extension) => extension == "gltf" || extension == "glb",
+        (_, "stl" | "obj" | "ply" | "gltf" | "glb") => true,
+        (_, "gz") => extension(&path.with_extension("")) == "stl",
+        _ => false,
+    }
 }
 
-fn convert_stl_to_gltf(
-    stl: IndexedMesh,
-    input_filename: impl AsRef<Path>,
-) -> Result<GltfBuilder, String> {
-    let mesh_name = input_filename
-        .as_ref()
-        .file_stem()
-        .unwrap()
+/// Whether the file is read as an STL file by `load_gltf`
+fn is_stl_input(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .unwrap_or_default()
         .to_string_lossy()
-        .to_string();
+        .to_lowercase();
+    !matches!(extension.as_str(), "gltf" | "glb" | "obj" | "ply")
+}
 
-    let mut gltf = GltfBuilder::new();
-    let with_indices = true;
+/// File of gitignore-style patterns, in an input directory, of the paths not to convert
+const IGNORE_FILE: &str = ".conv3dignore";
 
-    let (positions, mut normals) = stl
-        .vertices
-        .iter()
-        .map(|it| {
-            (
-                V3 {
-                    v: [it[0], it[1], it[2]],
-                },
-                V3 { v: [0.0, 0.0, 0.0] },
-            )
-        })
-        .collect::<(Vec<V3>, Vec<V3>)>();
+/// Read the `.conv3dignore` file of `dir`, if any
+fn read_ignore_file(dir: &Path) -> Result<Option<Gitignore>, ConvertError> {
+    let path = dir.join(IGNORE_FILE);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let mut builder = GitignoreBuilder::new(dir);
+    if let Some(err) = builder.add(&path) {
+        return Err(ConvertError::Parse(err.to_string()));
+    }
+    let ignore = builder
+        .build()
+        .map_err(|err| ConvertError::Parse(err.to_string()))?;
+    Ok(Some(ignore))
+}
 
-    let mut normals_count = vec![0; normals.len()];
-    for face in &stl.faces {
-        for vi in face.vertices {
-            normals[vi].v[0] += face.normal[0];
-            normals[vi].v[1] += face.normal[1];
-            normals[vi].v[2] += face.normal[2];
-            normals_count[vi] += 1;
+/// Collect the files of `dir` that can be converted to `output_format`, sorted by name.
+/// Symbolic links are followed: a link to one of its parent directories is reported as a failure.
+/// The paths matching the `.conv3dignore` file of `dir` are skipped.
+fn collect_directory(
+    dir: &Path,
+    recursive: bool,
+    output_format: &FileFormat,
+    input_files: &mut Vec<InputFile>,
+    failures: &mut Vec<(PathBuf, FailureKind, ConvertError)>,
+) {
+    let ignore = match read_ignore_file(dir) {
+        Ok(ignore) => ignore,
+        Err(err) => {
+            let path = dir.join(IGNORE_FILE);
+            log::warn!("Unable to read {}: {}", path.display(), err);
+            failures.push((path, FailureKind::Read, err));
+            return;
+        }
+    };
+    // Ignored directories are not entered
+    let walk = WalkDir::new(dir)
+        .follow_links(true)
+        .max_depth(if recursive { usize::MAX } else { 1 })
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            ignore.as_ref().is_none_or(|ignore| {
+                !ignore
+                    .matched(entry.path(), entry.file_type().is_dir())
+                    .is_ignore()
+            })
+        });
+    for entry in walk {
+        match entry {
+            Ok(entry) => {
+                if entry.file_type().is_file() && is_input_file(entry.path(), output_format) {
+                    input_files.push(InputFile {
+                        path: entry.into_path(),
+                        base_dir: Some(dir.to_path_buf()),
+                        member: None,
+                    });
+                }
+            }
+            Err(err) => {
+                let path = err.path().unwrap_or(dir).to_path_buf();
+                log::warn!("Unable to read {}: {}", path.display(), err);
+                failures.push((path, FailureKind::Read, std::io::Error::from(err).into()));
+            }
         }
     }
+}
+
+fn is_zip(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|it| it.eq_ignore_ascii_case("zip"))
+}
 
-    // normalize
-    for i in 0..normals.len() {
-        let n = normals[i].v;
-        let count = normals_count[i] as f32;
-        normals[i].v = [n[0] / count, n[1] / count, n[2] / count];
+/// Collect the STL files of a ZIP archive, in the order of the archive.
+/// The members with a path leaving the archive directory (`..`, absolute paths) are ignored.
+fn collect_zip(
+    path: &Path,
+    output_format: &FileFormat,
+    input_files: &mut Vec<InputFile>,
+    failures: &mut Vec<(PathBuf, FailureKind, ConvertError)>,
+) {
+    let mut fail = |err: ConvertError| {
+        log::warn!("Unable to read {}: {}", path.display(), err);
+        failures.push((path.to_path_buf(), FailureKind::Read, err));
+    };
+    if *output_format == FileFormat::Stl {
+        return fail(ConvertError::InvalidData(
+            "ZIP archives can only be converted to glTF".to_string(),
+        ));
+    }
+    let mut archive = match File::open(path)
+        .map_err(ConvertError::from)
+        .and_then(|file| zip::ZipArchive::new(file).map_err(zip_error))
+    {
+        Ok(archive) => archive,
+        Err(err) => return fail(err),
+    };
+    for index in 0..archive.len() {
+        let entry = match archive.by_index_raw(index) {
+            Ok(entry) => entry,
+            Err(err) => return fail(zip_error(err)),
+        };
+        let Some(member_path) = entry.enclosed_name() else {
+            continue;
+        };
+        let is_stl = member_path
+            .extension()
+            .is_some_and(|it| it.eq_ignore_ascii_case("stl"));
+        if entry.is_file() && is_stl {
+            input_files.push(InputFile {
+                path: path.to_path_buf(),
+                base_dir: None,
+                member: Some(ZipMember {
+                    index,
+                    path: member_path,
+                    size: entry.size(),
+                }),
+            });
+        }
     }
+}
 
-    let (min, max) = bounding_coords(&positions);
-    println!("min: {min:?} max: {max:?}");
-    let vcount = positions.len();
+/// Decompress a member of a ZIP archive
+fn read_zip_member(path: &Path, member: &ZipMember) -> Result<Vec<u8>, ConvertError> {
+    let mut archive = zip::ZipArchive::new(File::open(path)?).map_err(zip_error)?;
+    let mut entry = archive.by_index(member.index).map_err(zip_error)?;
+    let mut data = Vec::with_capacity(member.size.min(1 << 30) as usize);
+    entry.read_to_end(&mut data)?;
+    Ok(data)
+}
 
-    let positions_view =
-        gltf.push_buffer_with_view(Some("positions".to_string()), positions, None, None);
+fn zip_error(err: zip::result::ZipError) -> ConvertError {
+    ConvertError::Parse(format!("Invalid ZIP archive: {}", err))
+}
 
-    let normals_view = gltf.push_buffer_with_view(Some("normals".to_string()), normals, None, None);
+/// Convert an STL, OBJ or PLY file to glTF, or load a glTF/GLB file as is.
+/// The converted mesh is named `name`, or after the ASCII STL solid, or `outpath`.
+fn load_gltf(
+    path: &Path,
+    member: Option<&ZipMember>,
+    outpath: &Path,
+    name: Option<&str>,
+    mmap: bool,
+    options: &ConvertOptions,
+    file_progress: &FileProgress,
+) -> Result<GltfBuilder, ConvertError> {
+    let mesh_name = match name {
+        Some(name) => name.into(),
+        None => outpath.file_stem().unwrap_or_default().to_string_lossy(),
+    };
+    let extension = path
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    if let Some(member) = member {
+        file_progress.stage("parsing");
+        let data = read_zip_member(path, member)?;
+        let stl = read_stl_bytes(&data)?;
+        let colors = read_stl_colors_bytes(&data)?;
+        file_progress.stage("computing normals");
+        return stl_to_gltf_with_colors(stl, colors, &mesh_name, options.clone());
+    }
+    match extension.as_str() {
+        // Re-exported as is: only the buffers are laid out again
+        "gltf" | "glb" => {
+            file_progress.stage("parsing");
+            GltfBuilder::from_gltf(path)
+        }
+        "obj" => {
+            file_progress.stage("converting");
+            obj_to_gltf(path, &mesh_name, options.clone())
+        }
+        "ply" => {
+            file_progress.stage("converting");
+            ply_to_gltf(path, &mesh_name, options.clone())
+        }
+        _ => {
+            file_progress.stage("parsing");
+            let stl = if mmap {
+                read_stl_file_mmap(path)?
+            } else {
+                read_stl_file(path)?
+            };
+            let colors = read_stl_colors(path)?;
+            let name = match name {
+                Some(name) => name.to_string(),
+                None => read_stl_solid_name(path)?.unwrap_or_else(|| mesh_name.to_string()),
+            };
+            file_progress.stage("computing normals");
+            stl_to_gltf_with_colors(stl, colors, &name, options.clone())
+        }
+    }
+}
 
-    let positions = gltf.push_accessor_vec3(
-        Some("positions".to_string()),
-        positions_view,
-        0,
-        vcount,
-        Some(min),
-        Some(max),
-    );
-    let normals = gltf.push_accessor_vec3(
-        Some("normals".to_string()),
-        normals_view,
-        3,
-        vcount,
-        None,
+/// Report the validation errors of the glTF. Fails on errors unless `force`.
+fn check_gltf(gltf: &GltfBuilder, force: bool, progress: &Progress) -> Result<(), ConvertError> {
+    let Err(errors) = gltf.validate() else {
+        return Ok(());
+    };
+    for error in &errors {
+        progress.warn(format!("Validation error: {}", error));
+    }
+    if force {
+        return Ok(());
+    }
+    Err(ConvertError::InvalidData(format!(
+        "{} validation error(s), use --force to write anyway",
+        errors.len()
+    )))
+}
+
+/// Convert a single file and write the GLB to stdout
+#[allow(clippy::too_many_arguments)]
+fn convert_to_stdout(
+    path: &Path,
+    name: Option<&str>,
+    mmap: bool,
+    validate: bool,
+    force: bool,
+    float_precision: Option<u32>,
+    copyright: Option<&str>,
+    options: &ConvertOptions,
+) -> Result<(), ConvertError> {
+    let outpath = output_path(path, FileFormat::Glb, None, None);
+    let name = mesh_name(name, path, &outpath, 0);
+    let progress = Progress::new(false, 1);
+    let file_progress = progress.start_file(path);
+    let mut gltf = load_gltf(
+        path,
         None,
-    );
+        &outpath,
+        name.as_deref(),
+        mmap,
+        options,
+        &file_progress,
+    )?;
+    if let Some(decimals) = float_precision {
+        gltf.round_accessor_bounds(decimals);
+    }
+    if let Some(copyright) = copyright {
+        gltf.set_copyright(Some(copyright.to_string()));
+    }
+    if validate {
+        check_gltf(&gltf, force, &progress)?;
+    }
+    let glb = gltf.to_glb()?;
+    let stdout = std::io::stdout().lock();
+    glb.to_writer(BufWriter::new(stdout))?;
+    Ok(())
+}
+
+/// Path of the converted file: --output, or the path given by `output_path`
+fn input_output_path(app: &App, output_format: &FileFormat, input: &InputFile) -> PathBuf {
+    match (&app.output, &input.member) {
+        // The .gltf file is always named `<stem>.gltf` by `write_gltf`
+        (Some(output), _) if *output_format == FileFormat::Gltf => output.with_extension("gltf"),
+        (Some(output), _) => output.clone(),
+        // The paths inside the archive are kept, in --output-dir or in a directory named after
+        // the archive
+        (None, Some(member)) => {
+            let dir = match &app.output_dir {
+                Some(output_dir) => output_dir.clone(),
+                None => input.path.with_extension(""),
+            };
+            output_path(&dir.join(&member.path), output_format.clone(), None, None)
+        }
+        (None, None) => output_path(
+            &input.path,
+            output_format.clone(),
+            app.output_dir.as_deref(),
+            app.base_dir.as_deref().or(input.base_dir.as_deref()),
+        ),
+    }
+}
+
+/// Path of the converted file: next to the input, or inside `output_dir`.
+/// With `base_dir`, the path of the input relative to it is kept inside `output_dir`.
+fn output_path(
+    path: &Path,
+    output_format: FileFormat,
+    output_dir: Option<&Path>,
+    base_dir: Option<&Path>,
+) -> PathBuf {
+    // `model.stl.gz` is converted to `model.<format>`
+    let is_gzip = path
+        .extension()
+        .is_some_and(|it| it.eq_ignore_ascii_case("gz"));
+    let path = if is_gzip {
+        path.with_extension("")
+    } else {
+        path.to_path_buf()
+    };
+    let path = path.as_path();
+    let mut outpath = match output_dir {
+        None => path.to_path_buf(),
+        Some(output_dir) => {
+            let relative = base_dir
+                .and_then(|base_dir| path.strip_prefix(base_dir).ok())
+                .unwrap_or_else(|| Path::new(path.file_name().unwrap_or_default()));
+            output_dir.join(relative)
+        }
+    };
+    outpath.set_extension(get_extension(output_format));
+    outpath
+}
+
+/// Files written for `outpath`: the .gltf output also writes its buffers next to it
+fn output_files(
+    outpath: &Path,
+    output_format: &FileFormat,
+    max_buffer_bytes: Option<usize>,
+) -> Vec<PathBuf> {
+    let mut outputs = vec![outpath.to_path_buf()];
+    if *output_format == FileFormat::Gltf {
+        let out_dir = outpath.parent().unwrap_or(Path::new(""));
+        let stem = outpath.file_stem().unwrap_or_default().to_string_lossy();
+        outputs.push(out_dir.join(format!("{}.bin", stem)));
+        // With --max-buffer-bytes, the buffers are named `{stem}_{index}.bin` when there are several
+        if max_buffer_bytes.is_some() {
+            outputs.push(out_dir.join(format!("{}_0.bin", stem)));
+        }
+    }
+    outputs
+}
+
+#[allow(clippy::too_many_arguments)]
+fn convert_file(
+    input: &InputFile,
+    outputs: &[(FileFormat, PathBuf)],
+    name: Option<&str>,
+    mmap: bool,
+    validate: bool,
+    max_buffer_bytes: Option<usize>,
+    float_precision: Option<u32>,
+    copyright: Option<&str>,
+    overwrite: Overwrite,
+    stats: bool,
+    limits: InputLimits,
+    options: &ConvertOptions,
+    progress: &Progress,
+) -> Result<(Outcome, Option<MeshStats>), (FailureKind, ConvertError)> {
+    let path = &input.path;
+    if outputs.iter().any(|(_, outpath)| outpath == path) {
+        return Ok((Outcome::Skipped, None));
+    }
+    let out_dir = |outpath: &Path| match outpath.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let files = outputs
+        .iter()
+        .flat_map(|(format, outpath)| output_files(outpath, format, max_buffer_bytes))
+        .collect::<Vec<_>>();
+    let outcome = match files.iter().find(|it| it.exists()) {
+        None => Outcome::Converted,
+        Some(existing) => match overwrite {
+            Overwrite::Force => Outcome::Overwritten,
+            Overwrite::Skip => return Ok((Outcome::Skipped, None)),
+            Overwrite::Refuse => {
+                let err = std::io::Error::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    format!(
+                        "{} already exists, use --force to overwrite it",
+                        existing.display()
+                    ),
+                );
+                return Err((FailureKind::Write, err.into()));
+            }
+        },
+    };
+
+    let skip = |reason: String| {
+        progress.warn(format!("Skipping {}: {}", input.label().display(), reason));
+        Ok((Outcome::OverLimit, None))
+    };
+    if let Some(reason) = limits
+        .check_file(input)
+        .map_err(|err| (FailureKind::Read, err))?
+    {
+        return skip(reason);
+    }
+
+    let file_progress = progress.start_file(path);
+    // STL is never combined with the other formats
+    let (output_format, outpath) = &outputs[0];
+    if *output_format == FileFormat::Stl {
+        file_progress.stage("parsing");
+        let stl = gltf_to_stl(path, options.clone()).map_err(|err| (FailureKind::Read, err))?;
+        if let Some(reason) = limits.check_triangles(stl.faces.len() as u64) {
+            return skip(reason);
+        }
+        progress.info(format!("Parsed {}", path.display()));
+        let stats = stats.then(|| MeshStats::from_indexed_mesh(&stl));
+        file_progress.stage("writing");
+        let written =
+            write_stl(stl, outpath, &out_dir(outpath)).map_err(|err| (FailureKind::Write, err))?;
+        if !written {
+            progress.info(format!("Unchanged: {}", outpath.display()));
+            return Ok((Outcome::Unchanged, stats));
+        }
+        progress.info(format!("Output: {}", outpath.display()));
+        return Ok((outcome, stats));
+    }
 
-    let indices = stl
-        .faces
+    let mut gltf = load_gltf(
+        path,
+        input.member.as_ref(),
+        outpath,
+        name,
+        mmap,
+        options,
+        &file_progress,
+    )
+    .map_err(|err| (FailureKind::Read, err))?;
+    // The triangles of the other formats are only known once loaded
+    if limits.max_triangles.is_some() && (input.member.is_some() || !is_stl_input(path)) {
+        let triangles = gltf
+            .mesh_stats()
+            .map_err(|err| (FailureKind::Read, err))?
+            .triangles;
+        if let Some(reason) = limits.check_triangles(triangles as u64) {
+            return skip(reason);
+        }
+    }
+    if let Some(decimals) = float_precision {
+        gltf.round_accessor_bounds(decimals);
+    }
+    if let Some(copyright) = copyright {
+        gltf.set_copyright(Some(copyright.to_string()));
+    }
+    progress.info(format!("Parsed {}", input.label().display()));
+    if validate {
+        check_gltf(&gltf, overwrite == Overwrite::Force, progress)
+            .map_err(|err| (FailureKind::Validation, err))?;
+    }
+    let stats = stats
+        .then(|| gltf.mesh_stats())
+        .transpose()
+        .map_err(|err| (FailureKind::Read, err))?;
+    file_progress.stage("writing");
+    let mut written = false;
+    for (output_format, outpath) in outputs {
+        if write_gltf(
+            &gltf,
+            output_format,
+            outpath,
+            &out_dir(outpath),
+            max_buffer_bytes,
+        )
+        .map_err(|err| (FailureKind::Write, err))?
+        {
+            written = true;
+            progress.info(format!("Output: {}", outpath.display()));
+        } else {
+            progress.info(format!("Unchanged: {}", outpath.display()));
+        }
+    }
+    Ok((if written { outcome } else { Outcome::Unchanged }, stats))
+}
+
+/// Print the statistics of the output files as an aligned table, followed by the totals
+fn print_stats(stats: &[(PathBuf, MeshStats)]) {
+    let rows = stats
         .iter()
-        .flat_map(|it| {
+        .map(|(path, stats)| {
+            let [x, y, z] = stats.size();
             [
-                it.vertices[0] as u32,
-                it.vertices[1] as u32,
-                it.vertices[2] as u32,
+                path.display().to_string(),
+                stats.triangles.to_string(),
+                stats.vertices.to_string(),
+                format!("{} x {} x {}", x, y, z),
+                format!("{:.3}", stats.volume),
+                format!("{:.3}", stats.area),
+                if stats.watertight { "yes" } else { "no" }.to_string(),
             ]
         })
+        .chain(std::iter::once([
+            "total".to_string(),
+            stats
+                .iter()
+                .map(|it| it.1.triangles)
+                .sum::<usize>()
+                .to_string(),
+            stats
+                .iter()
+                .map(|it| it.1.vertices)
+                .sum::<usize>()
+                .to_string(),
+            String::new(),
+            format!("{:.3}", stats.iter().map(|it| it.1.volume).sum::<f64>()),
+            format!("{:.3}", stats.iter().map(|it| it.1.area).sum::<f64>()),
+            format!(
+                "{}/{}",
+                stats.iter().filter(|it| it.1.watertight).count(),
+                stats.len()
+            ),
+        ]));
+    let header = [
+        "file",
+        "triangles",
+        "vertices",
+        "size",
+        "volume",
+        "area",
+        "watertight",
+    ]
+    .map(String::from);
+    let rows = std::iter::once(header).chain(rows).collect::<Vec<_>>();
+    let widths = [0, 1, 2, 3, 4, 5].map(|k| rows.iter().map(|it| it[k].len()).max().unwrap_or(0));
+    for row in &rows {
+        println!(
+            "{:<w0$}  {:>w1$}  {:>w2$}  {:<w3$}  {:>w4$}  {:>w5$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            row[4],
+            row[5],
+            row[6],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+            w4 = widths[4],
+            w5 = widths[5],
+        );
+    }
+}
+
+/// Write the manifest of the converted files, for the viewers loading them lazily:
+/// `{"version": 1, "files": [{"path", "source", "triangles", "min", "max"}, ...]}`.
+/// `path` is relative to the directory of the manifest when possible.
+fn write_manifest(
+    sources: &[PathBuf],
+    stats: &[(PathBuf, MeshStats)],
+    path: &Path,
+) -> Result<(), ConvertError> {
+    use gltf::json::Value;
+    let manifest_dir = std::path::absolute(path)?
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let files = sources
+        .iter()
+        .zip(stats)
+        .map(
+            |(source, (outpath, stats))| -> Result<Value, ConvertError> {
+                let outpath = std::path::absolute(outpath)?;
+                let relative = outpath.strip_prefix(&manifest_dir).unwrap_or(&outpath);
+                // Forward slashes, for the URLs of the viewers
+                let relative = relative
+                    .components()
+                    .map(|it| it.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                Ok([
+                    ("path", Value::from(relative)),
+                    ("source", Value::from(source.display().to_string())),
+                    ("triangles", Value::from(stats.triangles)),
+                    ("min", Value::from(Vec::from(stats.min))),
+                    ("max", Value::from(Vec::from(stats.max))),
+                ]
+                .into_iter()
+                .collect::<Value>())
+            },
+        )
+        .collect::<Result<Vec<_>, _>>()?;
+    let value = [("version", Value::from(1)), ("files", Value::from(files))]
+        .into_iter()
+        .collect::<Value>();
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "{:#}", value)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write the statistics of the output files and their totals as JSON
+fn write_stats_json(stats: &[(PathBuf, MeshStats)], path: &Path) -> Result<(), ConvertError> {
+    use gltf::json::Value;
+    let files = stats
+        .iter()
+        .map(|(path, stats)| {
+            [
+                ("path", Value::from(path.display().to_string())),
+                ("triangles", Value::from(stats.triangles)),
+                ("vertices", Value::from(stats.vertices)),
+                ("min", Value::from(Vec::from(stats.min))),
+                ("max", Value::from(Vec::from(stats.max))),
+                ("size", Value::from(Vec::from(stats.size()))),
+                ("volume", Value::from(stats.volume)),
+                ("area", Value::from(stats.area)),
+                ("watertight", Value::from(stats.watertight)),
+            ]
+            .into_iter()
+            .collect::<Value>()
+        })
         .collect::<Vec<_>>();
-    let nb_indices = indices.len();
-    let indices_view =
-        gltf.push_buffer_with_view(Some("indices".to_string()), indices, Some(1), None);
-    let indices = if with_indices {
-        Some(gltf.push_accessor_u32(Some("indices".to_string()), indices_view, 0, nb_indices))
+    let total = [
+        ("files", Value::from(stats.len())),
+        (
+            "triangles",
+            Value::from(stats.iter().map(|it| it.1.triangles).sum::<usize>()),
+        ),
+        (
+            "vertices",
+            Value::from(stats.iter().map(|it| it.1.vertices).sum::<usize>()),
+        ),
+        (
+            "volume",
+            Value::from(stats.iter().map(|it| it.1.volume).sum::<f64>()),
+        ),
+        (
+            "area",
+            Value::from(stats.iter().map(|it| it.1.area).sum::<f64>()),
+        ),
+        (
+            "watertight",
+            Value::from(stats.iter().filter(|it| it.1.watertight).count()),
+        ),
+    ]
+    .into_iter()
+    .collect::<Value>();
+    let value = [("files", Value::from(files)), ("total", total)]
+        .into_iter()
+        .collect::<Value>();
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "{:#}", value)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write `data` to `path`, unless the file already holds exactly these bytes: its modification
+/// time is then left untouched. Returns whether the file was written.
+fn write_if_changed(path: &Path, data: &[u8]) -> Result<bool, ConvertError> {
+    let same_len = std::fs::metadata(path).is_ok_and(|it| it.len() == data.len() as u64);
+    if same_len && std::fs::read(path)? == data {
+        return Ok(false);
+    }
+    std::fs::write(path, data)?;
+    Ok(true)
+}
+
+/// Write an STL file. Returns whether it was written, see `write_if_changed`.
+fn write_stl(
+    stl: stl_io::IndexedMesh,
+    outpath: &Path,
+    out_dir: &Path,
+) -> Result<bool, ConvertError> {
+    std::fs::create_dir_all(out_dir)?;
+    let mut data = Vec::new();
+    stl_io::write_stl(&mut data, stl.into_triangle_vec().iter())?;
+    write_if_changed(outpath, &data)
+}
+
+/// Write a .glb, or a .gltf with its buffers in `out_dir`.
+/// Returns whether any file was written: the files with the same content are left as is.
+fn write_gltf(
+    gltf: &GltfBuilder,
+    output_format: &FileFormat,
+    outpath: &Path,
+    out_dir: &Path,
+    max_buffer_bytes: Option<usize>,
+) -> Result<bool, ConvertError> {
+    std::fs::create_dir_all(out_dir)?;
+    if *output_format == FileFormat::Glb {
+        write_if_changed(outpath, &gltf.to_glb_bytes()?)
     } else {
-        None
-    };
+        let mut gltf = gltf.merge_gltf_buffers_max(max_buffer_bytes.unwrap_or(usize::MAX))?;
+        let stem = outpath.file_stem().unwrap_or_default().to_string_lossy();
+        gltf.write_gltf_bundle(out_dir, &stem)
+    }
+}
 
-    let primitive = json::mesh::Primitive {
-        attributes: {
-            let mut map = std::collections::BTreeMap::new();
-            map.insert(Valid(json::mesh::Semantic::Positions), positions);
-            map.insert(Valid(json::mesh::Semantic::Normals), normals);
-            map
-        },
-        extensions: Default::default(),
-        extras: Default::default(),
-        indices,
-        material: None,
-        mode: Valid(json::mesh::Mode::Triangles),
-        targets: None,
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Empty directory of the temporary files of a test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("conv3d-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn ignored_files_are_skipped() {
+        let dir = temp_dir("ignore-file");
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        for name in ["a.stl", "b.stl", "sub/c.stl", "notes.txt"] {
+            std::fs::write(dir.join(name), "solid").unwrap();
+        }
+        std::fs::write(dir.join(IGNORE_FILE), "# Drafts\nb.stl\n").unwrap();
+        assert!(read_ignore_file(&dir).unwrap().is_some());
+
+        let (mut input_files, mut failures) = (Vec::new(), Vec::new());
+        collect_directory(
+            &dir,
+            true,
+            &FileFormat::Glb,
+            &mut input_files,
+            &mut failures,
+        );
+        assert!(failures.is_empty());
+        let paths = input_files.iter().map(|it| it.path.clone());
+        assert!(paths.eq([dir.join("a.stl"), dir.join("sub/c.stl")]));
+        for input in &input_files {
+            assert_eq!(input.base_dir.as_deref(), Some(dir.as_path()));
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn manifest_lists_every_input() {
+        let dir = temp_dir("manifest");
+        let stats = |triangles, max| MeshStats {
+            triangles,
+            vertices: 3 * triangles,
+            min: [-1.0, 0.0, 0.5],
+            max,
+            volume: 0.0,
+            area: 1.0,
+            watertight: false,
+        };
+        let sources = [dir.join("a.stl"), dir.join("parts/b.stl")];
+        let outputs = [
+            (dir.join("out/a.glb"), stats(12, [1.0, 2.0, 3.0])),
+            (dir.join("out/parts/b.glb"), stats(4, [4.0, 5.0, 6.0])),
+        ];
+        let path = dir.join("out/manifest.json");
+        std::fs::create_dir(dir.join("out")).unwrap();
+        write_manifest(&sources, &outputs, &path).unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let manifest: gltf::json::Value = gltf::json::deserialize::from_str(&text).unwrap();
+        assert_eq!(manifest["version"], 1);
+        let files = manifest["files"].as_array().unwrap();
+        assert_eq!(files.len(), 2);
+        for ((file, source), (_, stats)) in files.iter().zip(&sources).zip(&outputs) {
+            assert_eq!(file["source"], source.display().to_string());
+            assert_eq!(file["triangles"], stats.triangles);
+            let coords = |key: &str| {
+                file[key]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|it| it.as_f64().unwrap() as f32)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(coords("min"), stats.min);
+            assert_eq!(coords("max"), stats.max);
+        }
+        assert_eq!(files[0]["path"], "a.glb");
+        assert_eq!(files[1]["path"], "parts/b.glb");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn verbose_levels() {
+        use log::Level::{Debug, Info, Trace, Warn};
+        let enabled = |verbose, filters, target, level| {
+            let logger = logger_builder(verbose, filters).build();
+            let metadata = log::Metadata::builder().target(target).level(level).build();
+            log::Log::enabled(&logger, &metadata)
+        };
+        assert!(enabled(0, None, "conv3d::convert", Info));
+        assert!(!enabled(0, None, "conv3d::convert", Debug));
+        assert!(enabled(1, None, "conv3d", Debug));
+        assert!(!enabled(1, None, "conv3d", Trace));
+        assert!(enabled(2, None, "conv3d::gltf_builder", Trace));
+        // The other crates only report their warnings
+        assert!(!enabled(2, None, "gltf", Info));
+        assert!(enabled(2, None, "gltf", Warn));
+        // RUST_LOG replaces the levels of --verbose
+        assert!(enabled(0, Some("gltf=debug"), "gltf", Debug));
+        assert!(!enabled(2, Some("gltf=debug"), "conv3d", Info));
+    }
 
-    let mesh = gltf.push_mesh(Some(mesh_name), vec![primitive], None);
-    let node = gltf.push_node(mesh);
-    let scene = gltf.push_scene(vec![node]);
-    gltf.set_default_scene(Some(scene));
+    /// Shared buffer receiving the output of the logger
+    #[derive(Clone, Default)]
+    struct LogBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
 
-    Ok(gltf)
+    impl Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn log_macros_fire() {
+        let buffer = LogBuffer::default();
+        let logger = logger_builder(1, None)
+            .target(env_logger::Target::Pipe(Box::new(buffer.clone())))
+            .build();
+        log::set_max_level(logger.filter());
+        log::set_boxed_logger(Box::new(logger)).unwrap();
+        log::info!("Converted {} files", 2);
+        log::debug!("Read {} triangles", 12);
+        log::trace!("Hidden");
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("Converted 2 files\n"));
+        assert!(output.contains("[DEBUG] Read 12 triangles\n"));
+        assert!(!output.contains("Hidden"));
+    }
+
+    /// Arguments parsed for the conversion of `a.stl` to GLB
+    fn parse_args(args: &[&str]) -> App {
+        let args = ["conv3d", "a.stl", "-o", "glb"].iter().chain(args);
+        App::try_parse_from(args).unwrap()
+    }
+
+    #[test]
+    fn double_sided_material() {
+        assert_eq!(material_options(&parse_args(&[])), None);
+        let material = material_options(&parse_args(&["--double-sided"])).unwrap();
+        assert_eq!(
+            material,
+            MaterialOptions {
+                double_sided: true,
+                ..Default::default()
+            }
+        );
+
+        let options = ConvertOptions::builder()
+            .material(Some(material))
+            .build()
+            .unwrap();
+        let triangle = stl_io::IndexedMesh {
+            vertices: [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+                .map(stl_io::Vertex::new)
+                .to_vec(),
+            faces: vec![stl_io::IndexedTriangle {
+                normal: stl_io::Normal::new([0.0, 0.0, 1.0]),
+                vertices: [0, 1, 2],
+            }],
+        };
+        let gltf = conv3d::stl_to_gltf(triangle, "triangle", options).unwrap();
+        let root: gltf::json::Root =
+            gltf::json::deserialize::from_str(&gltf.to_json().unwrap()).unwrap();
+        assert_eq!(root.materials.len(), 1);
+        assert!(root.materials[0].double_sided);
+    }
+
+    #[test]
+    fn name_templates() {
+        let name = |template| {
+            mesh_name(
+                Some(template),
+                Path::new("parts/left/cube.stl"),
+                Path::new("out/cube.glb"),
+                3,
+            )
+        };
+        assert_eq!(name("part_{stem}").as_deref(), Some("part_cube"));
+        assert_eq!(
+            name("{parent}-{stem}-{index}").as_deref(),
+            Some("left-cube-3")
+        );
+        assert_eq!(name("fixed").as_deref(), Some("fixed"));
+        assert_eq!(
+            mesh_name(None, Path::new("cube.stl"), Path::new("cube.glb"), 0),
+            None
+        );
+
+        assert_eq!(
+            expand_name_template("{size}", "cube", "", 0),
+            Err("unknown token {size}".to_string())
+        );
+        assert_eq!(
+            expand_name_template("part_{stem", "cube", "", 0),
+            Err("unclosed `{` in part_{stem".to_string())
+        );
+        // Checked when parsing the arguments
+        assert!(App::try_parse_from(["conv3d", "a.stl", "-o", "glb", "--name", "{oops}"]).is_err());
+    }
+
+    #[test]
+    fn zip_members() {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let members = [
+            ("parts/a.stl", "solid a"),
+            ("parts/b/c.STL", "solid c"),
+            ("parts/readme.txt", "text"),
+            ("../evil.stl", "solid evil"),
+            ("/absolute.stl", "solid absolute"),
+        ];
+        for (name, content) in members {
+            zip.start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        let data = zip.finish().unwrap().into_inner();
+        let dir = temp_dir("zip-members");
+        let path = dir.join("parts.zip");
+        std::fs::write(&path, data).unwrap();
+
+        let (mut input_files, mut failures) = (Vec::new(), Vec::new());
+        collect_zip(&path, &FileFormat::Glb, &mut input_files, &mut failures);
+        assert!(failures.is_empty());
+        let members = input_files
+            .iter()
+            .map(|it| it.member.as_ref().unwrap())
+            .collect::<Vec<_>>();
+        let paths = members.iter().map(|it| it.path.as_path());
+        assert!(paths.eq([Path::new("parts/a.stl"), Path::new("parts/b/c.STL")]));
+        for input in &input_files {
+            assert_eq!(input.path, path);
+        }
+        assert_eq!(input_files[1].label(), path.join("parts/b/c.STL"));
+        assert_eq!(read_zip_member(&path, members[1]).unwrap(), b"solid c");
+
+        // Only glTF outputs
+        collect_zip(&path, &FileFormat::Stl, &mut input_files, &mut failures);
+        assert_eq!(failures.len(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn format_from_extension() {
+        for format in [FileFormat::Stl, FileFormat::Gltf, FileFormat::Glb] {
+            let extension = get_extension(format.clone());
+            assert_eq!(FileFormat::from_extension(extension), Ok(format.clone()));
+            let upper = extension.to_uppercase();
+            assert_eq!(FileFormat::from_extension(&upper), Ok(format));
+        }
+        assert_eq!(FileFormat::from_extension("Glb"), Ok(FileFormat::Glb));
+        assert!(FileFormat::from_extension("obj").is_err());
+        assert!(FileFormat::from_extension("").is_err());
+    }
+
+    #[test]
+    fn input_files_by_extension() {
+        let is_input = |path: &str, format| is_input_file(Path::new(path), &format);
+        assert!(is_input("a.stl", FileFormat::Glb));
+        assert!(is_input("a.STL", FileFormat::Glb));
+        assert!(is_input("a.Obj", FileFormat::Gltf));
+        assert!(is_input("a.ply", FileFormat::Glb));
+        assert!(is_input("a.stl.gz", FileFormat::Glb));
+        assert!(!is_input("a.txt.gz", FileFormat::Glb));
+        assert!(!is_input("a.png", FileFormat::Glb));
+        assert!(!is_input("a", FileFormat::Glb));
+        // Only the glTF files convert to STL
+        assert!(is_input("a.GLB", FileFormat::Stl));
+        assert!(!is_input("a.stl", FileFormat::Stl));
+        assert!(is_stl_input(Path::new("a.stl.gz")));
+        assert!(!is_stl_input(Path::new("a.PLY")));
+    }
+
+    /// Number of threads of the pool converting each input
+    fn split_threads(inputs: usize, total: usize, threads_per_file: usize) -> Vec<usize> {
+        convert_split(&vec![(); inputs], total, threads_per_file, &|_, _| {
+            rayon::current_num_threads()
+        })
+    }
+
+    #[test]
+    fn single_file_uses_all_threads() {
+        assert_eq!(split_threads(1, 4, 1), [4]);
+    }
+
+    #[test]
+    fn many_files_split_the_threads() {
+        assert_eq!(split_threads(8, 4, 1), [1; 8]);
+        assert_eq!(split_threads(8, 4, 2), [2; 8]);
+        // 3 files for 8 threads: the leftover threads go within the files
+        assert_eq!(split_threads(3, 8, 1), [2; 3]);
+    }
+
+    #[test]
+    fn threads_per_file_is_capped_to_total() {
+        assert_eq!(split_threads(2, 2, 8), [2; 2]);
+    }
+
+    #[test]
+    fn results_keep_the_order_of_the_inputs() {
+        let inputs = (0..50).collect::<Vec<_>>();
+        let results = convert_split(&inputs, 4, 1, &|index, input| (index, *input));
+        assert_eq!(results, (0..50).map(|it| (it, it)).collect::<Vec<_>>());
+    }
+
+    /// ASCII STL of a single triangle
+    const TRIANGLE_STL: &str = "solid triangle
+facet normal 0 0 1
+outer loop
+vertex 0 0 0
+vertex 1 0 0
+vertex 0 1 0
+endloop
+endfacet
+endsolid triangle
+";
+
+    /// Convert `input` to `outputs` with the default settings
+    fn convert_default(
+        input: &Path,
+        outputs: &[(FileFormat, PathBuf)],
+    ) -> Result<Outcome, ConvertError> {
+        let input = InputFile {
+            path: input.to_path_buf(),
+            base_dir: None,
+            member: None,
+        };
+        let (outcome, _) = convert_file(
+            &input,
+            outputs,
+            None,
+            false,
+            false,
+            None,
+            None,
+            None,
+            Overwrite::Force,
+            false,
+            InputLimits::default(),
+            &ConvertOptions::default(),
+            &Progress::new(false, 1),
+        )
+        .map_err(|(_, err)| err)?;
+        Ok(outcome)
+    }
+
+    #[test]
+    fn glb_and_gltf_outputs() {
+        let dir = temp_dir("glb-and-gltf");
+        let input = dir.join("triangle.stl");
+        std::fs::write(&input, TRIANGLE_STL).unwrap();
+        let outputs = [
+            (FileFormat::Glb, dir.join("triangle.glb")),
+            (FileFormat::Gltf, dir.join("triangle.gltf")),
+        ];
+        let outcome = convert_default(&input, &outputs).unwrap();
+        assert_eq!(outcome, Outcome::Converted);
+
+        // Both outputs are written from the same loaded scene
+        let glb = gltf::Gltf::open(dir.join("triangle.glb")).unwrap();
+        let gltf = gltf::Gltf::open(dir.join("triangle.gltf")).unwrap();
+        let bin = std::fs::read(dir.join("triangle.bin")).unwrap();
+        // The GLB chunk is padded to a multiple of 4 bytes
+        let blob = glb.blob.as_deref().unwrap();
+        assert_eq!(&blob[..bin.len()], bin);
+        assert!(blob[bin.len()..].iter().all(|&it| it == 0) && blob.len() < bin.len() + 4);
+        let meshes = |document: &gltf::Document| {
+            document
+                .meshes()
+                .map(|it| (it.name().map(str::to_string), it.primitives().count()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(meshes(&glb.document), [(Some("triangle".to_string()), 1)]);
+        assert_eq!(meshes(&gltf.document), meshes(&glb.document));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unchanged_outputs_are_not_rewritten() {
+        let dir = temp_dir("unchanged");
+        let input = dir.join("triangle.stl");
+        std::fs::write(&input, TRIANGLE_STL).unwrap();
+        let outputs = [
+            (FileFormat::Glb, dir.join("triangle.glb")),
+            (FileFormat::Gltf, dir.join("triangle.gltf")),
+        ];
+        let files = ["triangle.glb", "triangle.gltf", "triangle.bin"].map(|it| dir.join(it));
+        assert_eq!(
+            convert_default(&input, &outputs).unwrap(),
+            Outcome::Converted
+        );
+        // Back-dated, so that a rewrite cannot keep the same modification time
+        let past = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1 << 30);
+        for file in &files {
+            File::options()
+                .write(true)
+                .open(file)
+                .unwrap()
+                .set_modified(past)
+                .unwrap();
+        }
+
+        assert_eq!(
+            convert_default(&input, &outputs).unwrap(),
+            Outcome::Unchanged
+        );
+        for file in &files {
+            assert_eq!(std::fs::metadata(file).unwrap().modified().unwrap(), past);
+        }
+
+        // A changed input is written again
+        std::fs::write(&input, TRIANGLE_STL.replace("vertex 1 0 0", "vertex 2 0 0")).unwrap();
+        assert_eq!(
+            convert_default(&input, &outputs).unwrap(),
+            Outcome::Overwritten
+        );
+        assert_ne!(
+            std::fs::metadata(&files[0]).unwrap().modified().unwrap(),
+            past
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -0,0 +1,412 @@
+use std::path::Path;
+
+use crate::ConvertError;
+
+/// Vertices and triangles of a PLY file
+pub(crate) struct PlyMesh {
+    pub positions: Vec<[f32; 3]>,
+    /// Present when the vertices have `nx`, `ny` and `nz` properties
+    pub normals: Option<Vec<[f32; 3]>>,
+    /// Present when the vertices have `red`, `green` and `blue` properties
+    pub colors: Option<Vec<[f32; 4]>>,
+    /// Vertex indices of the triangles. The polygons are triangulated as fans.
+    pub indices: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<Self, ConvertError> {
+        Ok(match name {
+            "char" | "int8" => ScalarType::I8,
+            "uchar" | "uint8" => ScalarType::U8,
+            "short" | "int16" => ScalarType::I16,
+            "ushort" | "uint16" => ScalarType::U16,
+            "int" | "int32" => ScalarType::I32,
+            "uint" | "uint32" => ScalarType::U32,
+            "float" | "float32" => ScalarType::F32,
+            "double" | "float64" => ScalarType::F64,
+            _ => return Err(ConvertError::Parse(format!("Unknown PLY type {}", name))),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+
+    /// Value of a full color channel: integer colors are in [0, max], float colors in [0, 1]
+    fn color_max(self) -> f64 {
+        match self {
+            ScalarType::I8 => i8::MAX as f64,
+            ScalarType::U8 => u8::MAX as f64,
+            ScalarType::I16 => i16::MAX as f64,
+            ScalarType::U16 => u16::MAX as f64,
+            ScalarType::I32 => i32::MAX as f64,
+            ScalarType::U32 => u32::MAX as f64,
+            ScalarType::F32 | ScalarType::F64 => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PropertyType {
+    Scalar(ScalarType),
+    List { count: ScalarType, item: ScalarType },
+}
+
+#[derive(Debug)]
+struct Property {
+    name: String,
+    type_: PropertyType,
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn property(&self, name: &str) -> Option<usize> {
+        self.properties.iter().position(|it| it.name == name)
+    }
+}
+
+/// Values of the body of the file, read one at a time
+enum Values<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { data: &'a [u8], big_endian: bool },
+}
+
+impl Values<'_> {
+    fn next(&mut self, type_: ScalarType) -> Result<f64, ConvertError> {
+        let eof = || ConvertError::Parse("Unexpected end of the PLY data".to_string());
+        match self {
+            Values::Ascii(tokens) => {
+                let token = tokens.next().ok_or_else(eof)?;
+                token
+                    .parse::<f64>()
+                    .map_err(|_| ConvertError::Parse(format!("Invalid PLY value {}", token)))
+            }
+            Values::Binary { data, big_endian } => {
+                let size = type_.size();
+                if data.len() < size {
+                    return Err(eof());
+                }
+                let (value, rest) = data.split_at(size);
+                *data = rest;
+                // Little endian bytes, zero padded
+                let mut b = [0u8; 8];
+                b[..size].copy_from_slice(value);
+                if *big_endian {
+                    b[..size].reverse();
+                }
+                Ok(match type_ {
+                    ScalarType::I8 => b[0] as i8 as f64,
+                    ScalarType::U8 => b[0] as f64,
+                    ScalarType::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    ScalarType::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    ScalarType::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    ScalarType::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    ScalarType::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    ScalarType::F64 => f64::from_le_bytes(b),
+                })
+            }
+        }
+    }
+
+    /// Read the values of a property. The items of a list are appended to `list`.
+    fn read_property(
+        &mut self,
+        type_: PropertyType,
+        list: &mut Vec<f64>,
+    ) -> Result<f64, ConvertError> {
+        match type_ {
+            PropertyType::Scalar(type_) => self.next(type_),
+            PropertyType::List { count, item } => {
+                let len = self.next(count)?;
+                if !(0.0..=u32::MAX as f64).contains(&len) {
+                    return Err(ConvertError::Parse(format!(
+                        "Invalid PLY list size {}",
+                        len
+                    )));
+                }
+                for _ in 0..len as usize {
+                    list.push(self.next(item)?);
+                }
+                Ok(len)
+            }
+        }
+    }
+}
+
+/// Parse the header, up to `end_header`. Returns the elements and the offset of the body.
+fn parse_header(data: &[u8]) -> Result<(Encoding, Vec<Element>, usize), ConvertError> {
+    let invalid = |line: &str| ConvertError::Parse(format!("Invalid PLY header line: {}", line));
+    let mut encoding = None;
+    let mut elements = Vec::<Element>::new();
+    let mut offset = 0;
+    let mut first = true;
+    loop {
+        let end = data[offset..]
+            .iter()
+            .position(|&it| it == b'\n')
+            .ok_or_else(|| ConvertError::Parse("Missing PLY end_header".to_string()))?;
+        let line = String::from_utf8_lossy(&data[offset..offset + end]);
+        offset += end + 1;
+        let line = line.trim();
+        let words = line.split_ascii_whitespace().collect::<Vec<_>>();
+        if first {
+            if line != "ply" {
+                return Err(ConvertError::Parse("Not a PLY file".to_string()));
+            }
+            first = false;
+            continue;
+        }
+        match words.as_slice() {
+            ["format", format, _version] => {
+                encoding = Some(match *format {
+                    "ascii" => Encoding::Ascii,
+                    "binary_little_endian" => Encoding::BinaryLittleEndian,
+                    "binary_big_endian" => Encoding::BinaryBigEndian,
+                    _ => return Err(invalid(line)),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| invalid(line))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let element = elements.last_mut().ok_or_else(|| invalid(line))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    type_: PropertyType::List {
+                        count: ScalarType::parse(count)?,
+                        item: ScalarType::parse(item)?,
+                    },
+                });
+            }
+            ["property", type_, name] => {
+                let element = elements.last_mut().ok_or_else(|| invalid(line))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    type_: PropertyType::Scalar(ScalarType::parse(type_)?),
+                });
+            }
+            ["end_header"] => break,
+            ["comment" | "obj_info", ..] | [] => {}
+            _ => return Err(invalid(line)),
+        }
+    }
+    let encoding = encoding.ok_or_else(|| ConvertError::Parse("Missing PLY format".to_string()))?;
+    Ok((encoding, elements, offset))
+}
+
+/// Read an ASCII or binary (little or big endian) PLY file.
+/// Only the `vertex` and `face` elements are used, the others are skipped.
+pub(crate) fn read_ply_file(path: impl AsRef<Path>) -> Result<PlyMesh, ConvertError> {
+    parse_ply(&std::fs::read(path)?)
+}
+
+fn parse_ply(data: &[u8]) -> Result<PlyMesh, ConvertError> {
+    let (encoding, elements, offset) = parse_header(data)?;
+    let body = &data[offset..];
+    let mut values = match encoding {
+        Encoding::Ascii => Values::Ascii(
+            std::str::from_utf8(body)
+                .map_err(|_| ConvertError::Parse("Invalid ASCII PLY data".to_string()))?
+                .split_ascii_whitespace(),
+        ),
+        Encoding::BinaryLittleEndian | Encoding::BinaryBigEndian => Values::Binary {
+            data: body,
+            big_endian: encoding == Encoding::BinaryBigEndian,
+        },
+    };
+
+    let mut mesh = PlyMesh {
+        positions: Vec::new(),
+        normals: None,
+        colors: None,
+        indices: Vec::new(),
+    };
+    let mut found_vertices = false;
+    let mut row = Vec::new();
+    let mut list = Vec::new();
+    for element in &elements {
+        let properties = |names: &[&str]| -> Option<Vec<usize>> {
+            names.iter().map(|name| element.property(name)).collect()
+        };
+        let position = properties(&["x", "y", "z"]);
+        let normal = properties(&["nx", "ny", "nz"]);
+        let color = properties(&["red", "green", "blue"]);
+        let alpha = element.property("alpha");
+        let face = element
+            .property("vertex_indices")
+            .or_else(|| element.property("vertex_index"));
+        let is_vertex = element.name == "vertex";
+        let is_face = element.name == "face";
+        if is_vertex {
+            if position.is_none() {
+                return Err(ConvertError::Parse(
+                    "The PLY vertices have no x, y, z properties".to_string(),
+                ));
+            }
+            found_vertices = true;
+            // Not trusting the header: each vertex takes at least one byte per coordinate
+            let capacity = element.count.min(body.len() / 3);
+            mesh.positions.reserve(capacity);
+            mesh.normals = normal.as_ref().map(|_| Vec::with_capacity(capacity));
+            mesh.colors = color.as_ref().map(|_| Vec::with_capacity(capacity));
+        }
+        let channel = |row: &[f64], index: usize| match element.properties[index].type_ {
+            PropertyType::Scalar(type_) => (row[index] / type_.color_max()) as f32,
+            PropertyType::List { .. } => 0.0,
+        };
+
+        for _ in 0..element.count {
+            row.clear();
+            for (index, property) in element.properties.iter().enumerate() {
+                list.clear();
+                row.push(values.read_property(property.type_, &mut list)?);
+                if !is_face || Some(index) != face {
+                    continue;
+                }
+                // Fan triangulation of the polygon
+                if list.iter().any(|&it| it < 0.0 || it.fract() != 0.0) {
+                    return Err(ConvertError::InvalidData(
+                        "Invalid PLY vertex index".to_string(),
+                    ));
+                }
+                for k in 1..list.len().saturating_sub(1) {
+                    mesh.indices
+                        .extend([list[0], list[k], list[k + 1]].map(|it| it as usize));
+                }
+            }
+            if !is_vertex {
+                continue;
+            }
+            let vec3 = |indices: &[usize]| [0, 1, 2].map(|k| row[indices[k]] as f32);
+            if let Some(position) = &position {
+                mesh.positions.push(vec3(position));
+            }
+            if let (Some(normals), Some(normal)) = (&mut mesh.normals, &normal) {
+                normals.push(vec3(normal));
+            }
+            if let (Some(colors), Some(color)) = (&mut mesh.colors, &color) {
+                let a = alpha.map_or(1.0, |index| channel(&row, index));
+                colors.push([
+                    channel(&row, color[0]),
+                    channel(&row, color[1]),
+                    channel(&row, color[2]),
+                    a,
+                ]);
+            }
+        }
+    }
+    if !found_vertices {
+        return Err(ConvertError::Parse(
+            "The PLY file has no vertex element".to_string(),
+        ));
+    }
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIANGLE: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+
+    /// Binary PLY of [`TRIANGLE`], with float coordinates and `uchar`/`int` face lists
+    fn binary_triangle(big_endian: bool) -> Vec<u8> {
+        let format = if big_endian {
+            "binary_big_endian"
+        } else {
+            "binary_little_endian"
+        };
+        let mut data = format!(
+            "ply\nformat {} 1.0\nelement vertex 3\nproperty float x\nproperty float y\n\
+             property float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n",
+            format
+        )
+        .into_bytes();
+        for value in TRIANGLE.as_flattened() {
+            data.extend(if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            });
+        }
+        data.push(3);
+        for index in [0i32, 1, 2] {
+            data.extend(if big_endian {
+                index.to_be_bytes()
+            } else {
+                index.to_le_bytes()
+            });
+        }
+        data
+    }
+
+    #[test]
+    fn ascii_quad_with_colors() {
+        let data = b"ply\nformat ascii 1.0\ncomment quad\nelement vertex 4\n\
+            property float x\nproperty float y\nproperty float z\n\
+            property uchar red\nproperty uchar green\nproperty uchar blue\n\
+            element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+            0 0 0 255 0 0\n1 0 0 0 255 0\n1 1 0 0 0 255\n0 1 0 255 255 255\n4 0 1 2 3\n";
+        let mesh = parse_ply(data).unwrap();
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.positions[2], [1.0, 1.0, 0.0]);
+        assert_eq!(mesh.indices, [0, 1, 2, 0, 2, 3]);
+        assert!(mesh.normals.is_none());
+        let colors = mesh.colors.unwrap();
+        assert_eq!(colors[0], [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(colors[3], [1.0; 4]);
+    }
+
+    #[test]
+    fn binary_little_endian() {
+        let mesh = parse_ply(&binary_triangle(false)).unwrap();
+        assert_eq!(mesh.positions, TRIANGLE);
+        assert_eq!(mesh.indices, [0, 1, 2]);
+    }
+
+    #[test]
+    fn binary_big_endian() {
+        let mesh = parse_ply(&binary_triangle(true)).unwrap();
+        assert_eq!(mesh.positions, TRIANGLE);
+        assert_eq!(mesh.indices, [0, 1, 2]);
+    }
+
+    #[test]
+    fn huge_vertex_count_is_an_error() {
+        let data = b"ply\nformat ascii 1.0\nelement vertex 99999999999999\n\
+            property float x\nproperty float y\nproperty float z\nend_header\n0 0 0\n";
+        assert!(parse_ply(data).is_err());
+    }
+}
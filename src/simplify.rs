@@ -0,0 +1,585 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+};
+
+use stl_io::IndexedMesh;
+
+/// Weight of the planes keeping the boundary edges in place, relative to the faces
+const BOUNDARY_WEIGHT: f64 = 100.0;
+
+/// Symmetric 4x4 matrix of the sum of the squared distances to a set of planes
+/// (Garland and Heckbert, "Surface Simplification Using Quadric Error Metrics")
+#[derive(Debug, Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    /// Quadric of the plane `n.p + d = 0`, with `n` of unit length
+    fn from_plane(n: [f64; 3], d: f64, weight: f64) -> Self {
+        let [a, b, c] = n;
+        Self([
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ])
+        .scaled(weight)
+    }
+
+    fn scaled(self, weight: f64) -> Self {
+        Self(self.0.map(|it| it * weight))
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (value, other) in self.0.iter_mut().zip(other.0) {
+            *value += other;
+        }
+    }
+
+    fn sum(&self, other: &Quadric) -> Quadric {
+        let mut sum = *self;
+        sum.add(other);
+        sum
+    }
+
+    /// Weighted sum of the squared distances of `p` to the planes
+    fn error(&self, [x, y, z]: [f64; 3]) -> f64 {
+        let q = &self.0;
+        q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + 2.0 * q[3] * x
+            + q[4] * y * y
+            + 2.0 * q[5] * y * z
+            + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9]
+    }
+
+    /// Point with the smallest error, unless the planes are (nearly) parallel
+    fn optimum(&self) -> Option<[f64; 3]> {
+        let q = &self.0;
+        let m = [[q[0], q[1], q[2]], [q[1], q[4], q[5]], [q[2], q[5], q[7]]];
+        let rhs = [-q[3], -q[6], -q[8]];
+        let det3 = |m: [[f64; 3]; 3]| {
+            m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+                - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+                + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+        };
+        let det = det3(m);
+        let trace = q[0] + q[4] + q[7];
+        if det.is_nan() || det.abs() <= 1e-9 * trace.powi(3) {
+            return None;
+        }
+        // Cramer's rule
+        Some([0, 1, 2].map(|col| {
+            let mut mc = m;
+            for row in 0..3 {
+                mc[row][col] = rhs[row];
+            }
+            det3(mc) / det
+        }))
+    }
+}
+
+/// Collapse of the edge `(keep, remove)` to `position`, valid while both vertices are unchanged
+#[derive(Debug, Clone, Copy)]
+struct Collapse {
+    cost: f64,
+    keep: usize,
+    remove: usize,
+    versions: (u32, u32),
+    position: [f64; 3],
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    // Reversed, so that the heap pops the cheapest collapse first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| (other.keep, other.remove).cmp(&(self.keep, self.remove)))
+    }
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn length(v: [f64; 3]) -> f64 {
+    dot(v, v).sqrt()
+}
+
+struct Simplifier {
+    positions: Vec<[f64; 3]>,
+    quadrics: Vec<Quadric>,
+    versions: Vec<u32>,
+    removed: Vec<bool>,
+    faces: Vec<[usize; 3]>,
+    face_removed: Vec<bool>,
+    /// Faces around each vertex. May list removed faces.
+    vertex_faces: Vec<Vec<usize>>,
+    heap: BinaryHeap<Collapse>,
+}
+
+impl Simplifier {
+    fn new(stl: &IndexedMesh) -> Self {
+        let positions = stl
+            .vertices
+            .iter()
+            .map(|it| it.0.map(|c| c as f64))
+            .collect::<Vec<_>>();
+        let faces = stl.faces.iter().map(|it| it.vertices).collect::<Vec<_>>();
+        let mut vertex_faces = vec![Vec::new(); positions.len()];
+        for (fi, face) in faces.iter().enumerate() {
+            for &vi in face {
+                vertex_faces[vi].push(fi);
+            }
+        }
+
+        let mut quadrics = vec![Quadric::default(); positions.len()];
+        let mut edge_faces = HashMap::<[usize; 2], Vec<usize>>::new();
+        for (fi, face) in faces.iter().enumerate() {
+            let [a, b, c] = face.map(|vi| positions[vi]);
+            let n = cross(sub(b, a), sub(c, a));
+            let len = length(n);
+            if len > 0.0 {
+                let n = n.map(|it| it / len);
+                let quadric = Quadric::from_plane(n, -dot(n, a), len / 2.0);
+                for &vi in face {
+                    quadrics[vi].add(&quadric);
+                }
+            }
+            for k in 0..3 {
+                let (u, v) = (face[k], face[(k + 1) % 3]);
+                edge_faces.entry([u.min(v), u.max(v)]).or_default().push(fi);
+            }
+        }
+
+        // Sorted so that the quadrics are summed in the same order on every run
+        let mut edge_faces = edge_faces.into_iter().collect::<Vec<_>>();
+        edge_faces.sort_unstable();
+
+        // Planes orthogonal to the faces through their boundary edges keep the outline in place
+        for ([u, v], edge_faces) in &edge_faces {
+            let (u, v) = (*u, *v);
+            let &[fi] = &edge_faces[..] else {
+                continue;
+            };
+            let [a, b, c] = faces[fi].map(|vi| positions[vi]);
+            let face_normal = cross(sub(b, a), sub(c, a));
+            let edge = sub(positions[v], positions[u]);
+            let n = cross(edge, face_normal);
+            let len = length(n);
+            if len > 0.0 {
+                let n = n.map(|it| it / len);
+                let quadric = Quadric::from_plane(
+                    n,
+                    -dot(n, positions[u]),
+                    BOUNDARY_WEIGHT * dot(edge, edge),
+                );
+                quadrics[u].add(&quadric);
+                quadrics[v].add(&quadric);
+            }
+        }
+
+        let mut simplifier = Self {
+            versions: vec![0; positions.len()],
+            removed: vec![false; positions.len()],
+            face_removed: vec![false; faces.len()],
+            positions,
+            quadrics,
+            faces,
+            vertex_faces,
+            heap: BinaryHeap::new(),
+        };
+        for ([u, v], _) in edge_faces {
+            simplifier.push_collapse(u, v);
+        }
+        simplifier
+    }
+
+    /// Queue the collapse of the edge `(keep, remove)` at its optimal position
+    fn push_collapse(&mut self, keep: usize, remove: usize) {
+        if keep == remove {
+            return;
+        }
+        let quadric = self.quadrics[keep].sum(&self.quadrics[remove]);
+        let (a, b) = (self.positions[keep], self.positions[remove]);
+        let middle = [0, 1, 2].map(|k| (a[k] + b[k]) / 2.0);
+        // Far optima come from nearly parallel planes: they would create spikes
+        let optimum = quadric
+            .optimum()
+            .filter(|p| length(sub(*p, middle)) <= length(sub(a, b)));
+        let (position, cost) = optimum
+            .into_iter()
+            .chain([a, b, middle])
+            .map(|p| (p, quadric.error(p)))
+            .min_by(|x, y| x.1.total_cmp(&y.1))
+            .unwrap_or((middle, 0.0));
+        self.heap.push(Collapse {
+            cost,
+            keep,
+            remove,
+            versions: (self.versions[keep], self.versions[remove]),
+            position,
+        });
+    }
+
+    fn neighbors(&self, vi: usize) -> Vec<usize> {
+        let mut neighbors = self.vertex_faces[vi]
+            .iter()
+            .filter(|&&fi| !self.face_removed[fi])
+            .flat_map(|&fi| self.faces[fi])
+            .filter(|&it| it != vi)
+            .collect::<Vec<_>>();
+        neighbors.sort_unstable();
+        neighbors.dedup();
+        neighbors
+    }
+
+    /// The collapse keeps the surface manifold and does not flip any face
+    fn is_valid(&self, collapse: &Collapse) -> bool {
+        let (keep, remove) = (collapse.keep, collapse.remove);
+        let shared_faces = self.vertex_faces[keep]
+            .iter()
+            .filter(|&&fi| !self.face_removed[fi] && self.faces[fi].contains(&remove))
+            .count();
+        // Link condition: the only common neighbors are the opposite vertices of the shared faces
+        let keep_neighbors = self.neighbors(keep);
+        let common = self
+            .neighbors(remove)
+            .into_iter()
+            .filter(|it| keep_neighbors.binary_search(it).is_ok())
+            .count();
+        if shared_faces == 0 || common != shared_faces {
+            return false;
+        }
+
+        for vi in [keep, remove] {
+            for &fi in &self.vertex_faces[vi] {
+                let face = self.faces[fi];
+                if self.face_removed[fi] || (face.contains(&keep) && face.contains(&remove)) {
+                    continue;
+                }
+                let [a, b, c] = face.map(|it| self.positions[it]);
+                let before = cross(sub(b, a), sub(c, a));
+                let [a, b, c] = face.map(|it| {
+                    if it == keep || it == remove {
+                        collapse.position
+                    } else {
+                        self.positions[it]
+                    }
+                });
+                let after = cross(sub(b, a), sub(c, a));
+                if dot(before, after) <= 0.0 {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Merge `remove` into `keep`. Returns the number of faces removed.
+    fn collapse(&mut self, collapse: &Collapse) -> usize {
+        let (keep, remove) = (collapse.keep, collapse.remove);
+        self.positions[keep] = collapse.position;
+        let quadric = self.quadrics[remove];
+        self.quadrics[keep].add(&quadric);
+        self.versions[keep] += 1;
+        self.versions[remove] += 1;
+        self.removed[remove] = true;
+
+        let mut removed_faces = 0;
+        for fi in std::mem::take(&mut self.vertex_faces[remove]) {
+            if self.face_removed[fi] {
+                continue;
+            }
+            if self.faces[fi].contains(&keep) {
+                self.face_removed[fi] = true;
+                removed_faces += 1;
+            } else {
+                for vi in &mut self.faces[fi] {
+                    if *vi == remove {
+                        *vi = keep;
+                    }
+                }
+                self.vertex_faces[keep].push(fi);
+            }
+        }
+        let face_removed = &self.face_removed;
+        self.vertex_faces[keep].retain(|&fi| !face_removed[fi]);
+
+        for neighbor in self.neighbors(keep) {
+            self.push_collapse(keep, neighbor);
+        }
+        removed_faces
+    }
+
+    fn run(&mut self, target_faces: usize) {
+        let mut face_count = self.face_removed.iter().filter(|&&it| !it).count();
+        while face_count > target_faces {
+            let Some(collapse) = self.heap.pop() else {
+                break;
+            };
+            let (keep, remove) = (collapse.keep, collapse.remove);
+            if self.removed[keep]
+                || self.removed[remove]
+                || collapse.versions != (self.versions[keep], self.versions[remove])
+                || !self.is_valid(&collapse)
+            {
+                continue;
+            }
+            face_count -= self.collapse(&collapse);
+        }
+    }
+}
+
+/// Reduce the number of faces to about `ratio` times the original with edge collapses that
+/// minimize the quadric error. Boundary edges are preserved as much as possible.
+/// Returns the indices of the original faces that were kept, in order.
+pub(crate) fn simplify_mesh_faces(stl: &mut IndexedMesh, ratio: f32) -> Vec<usize> {
+    let target_faces = (stl.faces.len() as f64 * ratio.clamp(0.0, 1.0) as f64).ceil() as usize;
+    if target_faces >= stl.faces.len() {
+        return (0..stl.faces.len()).collect();
+    }
+    let mut simplifier = Simplifier::new(stl);
+    simplifier.run(target_faces);
+
+    // Drop the vertices that are not used anymore
+    let mut remap = vec![usize::MAX; stl.vertices.len()];
+    let mut vertices = Vec::new();
+    let mut faces = Vec::new();
+    let mut kept = Vec::new();
+    for (fi, face) in simplifier.faces.iter().enumerate() {
+        if simplifier.face_removed[fi] {
+            continue;
+        }
+        let vertices = face.map(|vi| {
+            if remap[vi] == usize::MAX {
+                remap[vi] = vertices.len();
+                vertices.push(stl_io::Vertex::new(
+                    simplifier.positions[vi].map(|it| it as f32),
+                ));
+            }
+            remap[vi]
+        });
+        // The normals of the file do not match the moved vertices anymore
+        let [a, b, c] = face.map(|vi| simplifier.positions[vi]);
+        let n = cross(sub(b, a), sub(c, a));
+        let len = length(n);
+        let normal = if len > 0.0 {
+            n.map(|it| (it / len) as f32)
+        } else {
+            [0.0, 0.0, 0.0]
+        };
+        faces.push(stl_io::IndexedTriangle {
+            normal: stl_io::Normal::new(normal),
+            vertices,
+        });
+        kept.push(fi);
+    }
+    stl.vertices = vertices;
+    stl.faces = faces;
+    kept
+}
+
+/// Reduce the number of faces to about `ratio` times the original, see [`simplify_mesh_faces`].
+/// Returns the number of faces removed.
+pub fn simplify_mesh(stl: &mut IndexedMesh, ratio: f32) -> usize {
+    let before = stl.faces.len();
+    simplify_mesh_faces(stl, ratio);
+    before - stl.faces.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{is_watertight, MeshStats};
+
+    fn mesh(positions: &[[f32; 3]], faces: &[[usize; 3]]) -> IndexedMesh {
+        IndexedMesh {
+            vertices: positions.iter().map(|&p| stl_io::Vertex::new(p)).collect(),
+            faces: faces
+                .iter()
+                .map(|&vertices| stl_io::IndexedTriangle {
+                    normal: stl_io::Normal::new([0.0; 3]),
+                    vertices,
+                })
+                .collect(),
+        }
+    }
+
+    /// Unit sphere: an icosahedron whose faces are split in 4, `subdivisions` times
+    fn icosphere(subdivisions: u32) -> IndexedMesh {
+        let t = (1.0 + 5f32.sqrt()) / 2.0;
+        let mut positions = vec![
+            [-1.0, t, 0.0],
+            [1.0, t, 0.0],
+            [-1.0, -t, 0.0],
+            [1.0, -t, 0.0],
+            [0.0, -1.0, t],
+            [0.0, 1.0, t],
+            [0.0, -1.0, -t],
+            [0.0, 1.0, -t],
+            [t, 0.0, -1.0],
+            [t, 0.0, 1.0],
+            [-t, 0.0, -1.0],
+            [-t, 0.0, 1.0],
+        ];
+        let mut faces = vec![
+            [0, 11, 5],
+            [0, 5, 1],
+            [0, 1, 7],
+            [0, 7, 10],
+            [0, 10, 11],
+            [1, 5, 9],
+            [5, 11, 4],
+            [11, 10, 2],
+            [10, 7, 6],
+            [7, 1, 8],
+            [3, 9, 4],
+            [3, 4, 2],
+            [3, 2, 6],
+            [3, 6, 8],
+            [3, 8, 9],
+            [4, 9, 5],
+            [2, 4, 11],
+            [6, 2, 10],
+            [8, 6, 7],
+            [9, 8, 1],
+        ];
+        for _ in 0..subdivisions {
+            let mut middles = HashMap::new();
+            let mut middle = |positions: &mut Vec<[f32; 3]>, a: usize, b: usize| {
+                *middles.entry([a.min(b), a.max(b)]).or_insert_with(|| {
+                    let (pa, pb) = (positions[a], positions[b]);
+                    positions.push([0, 1, 2].map(|k| (pa[k] + pb[k]) / 2.0));
+                    positions.len() - 1
+                })
+            };
+            faces = faces
+                .iter()
+                .flat_map(|&[a, b, c]| {
+                    let ab = middle(&mut positions, a, b);
+                    let bc = middle(&mut positions, b, c);
+                    let ca = middle(&mut positions, c, a);
+                    [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+                })
+                .collect();
+        }
+        for p in &mut positions {
+            let len = p.iter().map(|it| it * it).sum::<f32>().sqrt();
+            *p = p.map(|it| it / len);
+        }
+        mesh(&positions, &faces)
+    }
+
+    #[test]
+    fn simplify_sphere_by_half() {
+        let mut sphere = icosphere(3);
+        let before = sphere.faces.len();
+        assert_eq!(before, 1280);
+        let removed = simplify_mesh(&mut sphere, 0.5);
+        assert_eq!(removed, before - sphere.faces.len());
+        let after = sphere.faces.len();
+        assert!(
+            (before * 45 / 100..=before / 2 + 1).contains(&after),
+            "{}",
+            after
+        );
+
+        let stats = MeshStats::from_indexed_mesh(&sphere);
+        for k in 0..3 {
+            assert!((stats.min[k] + 1.0).abs() < 0.05, "{:?}", stats.min);
+            assert!((stats.max[k] - 1.0).abs() < 0.05, "{:?}", stats.max);
+        }
+        assert!(is_watertight(&sphere).is_watertight());
+        assert!(stats.volume > 0.0);
+    }
+
+    /// Collapse of `remove` into `keep` at `position`, for the current versions
+    fn collapse(keep: usize, remove: usize, position: [f64; 3]) -> Collapse {
+        Collapse {
+            cost: 0.0,
+            keep,
+            remove,
+            versions: (0, 0),
+            position,
+        }
+    }
+
+    #[test]
+    fn refuse_non_manifold_collapse() {
+        // The edge u-v has one face, but u and v have two common neighbours, a and w:
+        // collapsing it would pinch the hole u-v-w
+        let (u, v, a, w, b, c) = (0, 1, 2, 3, 4, 5);
+        let mesh = mesh(
+            &[
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [0.5, -1.0, 0.0],
+                [0.5, 1.0, 0.0],
+                [-0.5, 1.5, 0.0],
+                [1.5, 1.5, 0.0],
+            ],
+            &[[u, a, v], [u, w, b], [v, c, w]],
+        );
+        let simplifier = Simplifier::new(&mesh);
+        assert!(!simplifier.is_valid(&collapse(u, v, [0.5, 0.0, 0.0])));
+        // An edge on the boundary of a single face is fine
+        assert!(simplifier.is_valid(&collapse(u, a, [0.0, 0.0, 0.0])));
+    }
+
+    #[test]
+    fn refuse_flipping_collapse() {
+        // Fan of 4 faces around the center of a square
+        let mesh = mesh(
+            &[
+                [0.0, 0.0, 0.0],
+                [-1.0, -1.0, 0.0],
+                [1.0, -1.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [-1.0, 1.0, 0.0],
+            ],
+            &[[0, 1, 2], [0, 2, 3], [0, 3, 4], [0, 4, 1]],
+        );
+        let simplifier = Simplifier::new(&mesh);
+        assert!(simplifier.is_valid(&collapse(1, 0, [-1.0, -1.0, 0.0])));
+        // Moving the center past the opposite corner turns faces over
+        assert!(!simplifier.is_valid(&collapse(3, 0, [-3.0, -3.0, 0.0])));
+    }
+}